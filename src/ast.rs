@@ -1,134 +1,482 @@
-use common::{Arg, Field, Literal, Type, Typed};
-use mir::{self, Context, IntoMir};
+use common::{
+    Arg, BinOp, Field, Generic, Literal, Method, Pattern, PatternKind, Type, Typed, UnOp, Variant,
+};
+use hir::{self, Context, IntoHir, Var};
+use span::Span;
 
 #[derive(Debug)]
 pub struct Program(pub Vec<TopDecl>);
 
-impl IntoMir<mir::Program> for Program {
-    fn into_mir(self, ctx: &mut Context) -> mir::Program {
-        mir::Program(
-            self.0
-                .into_iter()
-                .map(|decl| decl.into_mir(ctx))
-                .collect::<Vec<_>>(),
-        )
+impl IntoHir<hir::Program> for Program {
+    fn into_hir(self, ctx: &mut Context) -> hir::Program {
+        for decl in self.0.iter() {
+            match &decl.kind {
+                TopDeclKind::Extern(name, _) | TopDeclKind::Fn(name, _, _, _, _) => {
+                    ctx.resolver.declare(name, decl.span, true)
+                }
+                TopDeclKind::Struct(name, _) => ctx.resolver.declare(name, decl.span, false),
+                TopDeclKind::Enum(name, variants) => {
+                    ctx.declare_enum(name, variants);
+                    ctx.resolver.declare(name, decl.span, false);
+                    for variant in variants {
+                        ctx.resolver.declare(&variant.0, variant.2, false);
+                    }
+                }
+                TopDeclKind::Class(name, _, methods) => {
+                    ctx.resolver.declare(name, decl.span, false);
+                    for method in methods {
+                        ctx.resolver.declare_method(&method.0);
+                    }
+                }
+                TopDeclKind::Import(_) | TopDeclKind::Instance(_, _, _) => (),
+            }
+        }
+        let mut decls = Vec::new();
+        for decl in self.0 {
+            match decl.kind {
+                // the methods of an instance become functions of their own,
+                // named after the instance
+                TopDeclKind::Instance(class, ty, methods) => {
+                    let ty = ctx.resolve_type(ty);
+                    let names = methods
+                        .iter()
+                        .map(|method| method.name().to_owned())
+                        .collect::<Vec<_>>();
+                    decls.push(hir::TopDecl::new(
+                        hir::TopDeclKind::Instance(class.clone(), ty.clone(), names),
+                        decl.span,
+                    ));
+                    for method in methods {
+                        let mut method = method.into_hir(ctx);
+                        if let hir::TopDeclKind::Fn(name, _, _, _, _) = &mut method.kind {
+                            *name = hir::method_symbol(&class, &ty, name);
+                        }
+                        decls.push(method);
+                    }
+                }
+                // imports are resolved by the module loader
+                TopDeclKind::Import(_) => (),
+                kind => decls.push(TopDecl::new(kind, decl.span).into_hir(ctx)),
+            }
+        }
+        hir::Program(decls)
     }
 }
 
 #[derive(Debug)]
-pub enum TopDecl {
+pub struct TopDecl {
+    pub kind: TopDeclKind,
+    /// Whether other modules can use this declaration.
+    pub public: bool,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum TopDeclKind {
+    Import(Vec<String>),
     Extern(String, Type),
-    Fn(String, Vec<Arg>, Type, Vec<Stmt>),
+    Fn(String, Vec<Generic>, Vec<Arg>, Option<Type>, Vec<Stmt>),
     Struct(String, Vec<Field>),
+    Enum(String, Vec<Variant>),
+    /// The name of a class, its type parameter and its methods.
+    Class(String, String, Vec<Method>),
+    /// The class, the type it is implemented for and the methods.
+    Instance(String, Type, Vec<TopDecl>),
+}
+
+impl TopDecl {
+    pub fn new(kind: TopDeclKind, span: Span) -> Self {
+        TopDecl {
+            kind,
+            public: false,
+            span,
+        }
+    }
+
+    pub fn public(mut self, public: bool) -> Self {
+        self.public = public;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.kind {
+            TopDeclKind::Import(path) => path.last().map(String::as_str).unwrap_or_default(),
+            TopDeclKind::Extern(name, _)
+            | TopDeclKind::Fn(name, _, _, _, _)
+            | TopDeclKind::Struct(name, _)
+            | TopDeclKind::Enum(name, _)
+            | TopDeclKind::Class(name, _, _)
+            | TopDeclKind::Instance(name, _, _) => name,
+        }
+    }
 }
 
-impl IntoMir<mir::TopDecl> for TopDecl {
-    fn into_mir(self, ctx: &mut Context) -> mir::TopDecl {
-        match self {
-            TopDecl::Extern(name, ty) => mir::TopDecl::Extern(name, ty),
-            TopDecl::Fn(name, args, ty, body) => mir::TopDecl::Fn(
+impl IntoHir<hir::TopDecl> for TopDecl {
+    fn into_hir(self, ctx: &mut Context) -> hir::TopDecl {
+        let kind = match self.kind {
+            TopDeclKind::Import(_) => unreachable!("imports are resolved by the module loader"),
+            TopDeclKind::Extern(name, ty) => hir::TopDeclKind::Extern(name, ctx.resolve_type(ty)),
+            TopDeclKind::Fn(name, generics, args, ty, body) => {
+                ctx.type_params = Generic::names(&generics);
+                ctx.resolver.scope();
+                let args = bind_args(args, ctx);
+                // without an annotation, a function that never returns a value
+                // returns `()`, and the others are left to inference
+                let ty = match ty {
+                    Some(ty) => ctx.resolve_type(ty),
+                    None if body.iter().any(|stmt| stmt.returns_value()) => ctx.next(),
+                    None => Type::Unit,
+                };
+                let body = body
+                    .into_iter()
+                    .map(|stmt| stmt.into_hir(ctx))
+                    .collect::<Vec<_>>();
+                ctx.resolver.unscope();
+                ctx.type_params.clear();
+                hir::TopDeclKind::Fn(name, generics, args, ty, body)
+            }
+            TopDeclKind::Struct(name, fields) => hir::TopDeclKind::Struct(
                 name,
-                args,
-                ty,
-                body.into_iter()
-                    .map(|stmt| stmt.into_mir(ctx))
+                fields
+                    .into_iter()
+                    .map(|Field(name, ty, span)| Field(name, ctx.resolve_type(ty), span))
                     .collect::<Vec<_>>(),
             ),
-            TopDecl::Struct(name, fields) => mir::TopDecl::Struct(name, fields),
-        }
+            TopDeclKind::Enum(name, variants) => hir::TopDeclKind::Enum(
+                name,
+                variants
+                    .into_iter()
+                    .map(|Variant(name, payload, span)| {
+                        let payload = payload
+                            .into_iter()
+                            .map(|ty| ctx.resolve_type(ty))
+                            .collect::<Vec<_>>();
+                        Variant(name, payload, span)
+                    }).collect::<Vec<_>>(),
+            ),
+            TopDeclKind::Class(name, param, methods) => {
+                ctx.type_params = vec![param.clone()];
+                let methods = methods
+                    .into_iter()
+                    .map(|Method(name, args, ty, span)| {
+                        let args = args
+                            .into_iter()
+                            .map(|Arg(name, ty, span)| Arg(name, ctx.resolve_type(ty), span))
+                            .collect::<Vec<_>>();
+                        Method(name, args, ctx.resolve_type(ty), span)
+                    }).collect::<Vec<_>>();
+                ctx.type_params.clear();
+                hir::TopDeclKind::Class(name, param, methods)
+            }
+            TopDeclKind::Instance(class, ty, _) => {
+                hir::TopDeclKind::Instance(class, ctx.resolve_type(ty), Vec::new())
+            }
+        };
+        hir::TopDecl::new(kind, self.span)
     }
 }
 
 #[derive(Debug)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
     Assign(bool, String, Expr),
+    /// A variable declared without a value, to be assigned later.
+    Declare(String, Type),
+    SetField(Expr, String, Expr),
     Expr(Expr),
     If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
     While(Expr, Vec<Stmt>),
+    Match(Expr, Vec<Arm>),
     Return(Option<Expr>),
 }
 
-impl IntoMir<mir::Stmt> for Stmt {
-    fn into_mir(self, ctx: &mut Context) -> mir::Stmt {
-        match self {
-            Stmt::Assign(re, name, expr) => mir::Stmt::Assign(re, name, expr.into_mir(ctx)),
-            Stmt::Expr(expr) => mir::Stmt::Expr(expr.into_mir(ctx)),
-            Stmt::If(cond, body1, body2) => mir::Stmt::If(
-                cond.into_mir(ctx),
-                body1
-                    .into_iter()
-                    .map(|stmt| stmt.into_mir(ctx))
-                    .collect::<Vec<_>>(),
-                body2.map(|body| {
-                    body.into_iter()
-                        .map(|stmt| stmt.into_mir(ctx))
-                        .collect::<Vec<_>>()
-                }),
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Stmt { kind, span }
+    }
+
+    /// Whether this statement contains a `return` with a value.
+    pub fn returns_value(&self) -> bool {
+        match &self.kind {
+            StmtKind::Return(expr) => expr.is_some(),
+            StmtKind::If(_, body1, body2) => body1
+                .iter()
+                .chain(body2.iter().flatten())
+                .any(|stmt| stmt.returns_value()),
+            StmtKind::While(_, body) => body.iter().any(|stmt| stmt.returns_value()),
+            StmtKind::Match(_, arms) => arms
+                .iter()
+                .flat_map(|arm| arm.body.iter())
+                .any(|stmt| stmt.returns_value()),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+}
+
+impl Arm {
+    pub fn new(pattern: Pattern, body: Vec<Stmt>) -> Self {
+        Arm { pattern, body }
+    }
+}
+
+/// Gives each argument a variable of its own, in a scope the caller opened.
+fn bind_args(args: Vec<Arg>, ctx: &mut Context) -> Vec<Arg<Var>> {
+    let names = args
+        .iter()
+        .map(|arg| (arg.0.as_str(), arg.2))
+        .collect::<Vec<_>>();
+    let vars = ctx.resolver.bind_all(&names);
+    args.into_iter()
+        .zip(vars)
+        .map(|(Arg(_, ty, span), var)| Arg(var, ctx.resolve_type(ty), span))
+        .collect()
+}
+
+/// Binds the names in a pattern, in a scope the caller opened.
+fn bind_pattern(pattern: Pattern, ctx: &mut Context) -> Pattern<Option<Var>> {
+    let span = pattern.span;
+    let kind = match pattern.kind {
+        PatternKind::Wildcard => PatternKind::Wildcard,
+        PatternKind::Variant(name, binders) => {
+            let names = binders
+                .iter()
+                .filter(|binder| *binder != "_")
+                .map(|binder| (binder.as_str(), span))
+                .collect::<Vec<_>>();
+            let mut vars = ctx.resolver.bind_all(&names).into_iter();
+            let binders = binders
+                .iter()
+                .map(|binder| if binder == "_" { None } else { vars.next() })
+                .collect();
+            PatternKind::Variant(name, binders)
+        }
+    };
+    Pattern::new(kind, span)
+}
+
+/// Converts a block that has a scope of its own.
+fn block_into_hir(body: Vec<Stmt>, ctx: &mut Context) -> Vec<hir::Stmt> {
+    ctx.resolver.scope();
+    let body = body
+        .into_iter()
+        .map(|stmt| stmt.into_hir(ctx))
+        .collect::<Vec<_>>();
+    ctx.resolver.unscope();
+    body
+}
+
+impl IntoHir<hir::Stmt> for Stmt {
+    fn into_hir(self, ctx: &mut Context) -> hir::Stmt {
+        let span = self.span;
+        let kind = match self.kind {
+            // the value is converted first, so that `let x = x + 1` uses the
+            // `x` from before
+            StmtKind::Assign(re, name, expr) => {
+                let expr = expr.into_hir(ctx);
+                let var = if re {
+                    ctx.resolver.assign(name, span)
+                } else {
+                    ctx.resolver.bind(name)
+                };
+                hir::StmtKind::Assign(re, var, expr)
+            }
+            StmtKind::Declare(name, ty) => {
+                hir::StmtKind::Declare(ctx.resolver.bind(name), ctx.resolve_type(ty))
+            }
+            StmtKind::SetField(object, field, expr) => {
+                hir::StmtKind::SetField(object.into_hir(ctx), field, expr.into_hir(ctx))
+            }
+            StmtKind::Expr(expr) => hir::StmtKind::Expr(expr.into_hir(ctx)),
+            StmtKind::If(cond, body1, body2) => hir::StmtKind::If(
+                cond.into_hir(ctx),
+                block_into_hir(body1, ctx),
+                body2.map(|body| block_into_hir(body, ctx)),
             ),
-            Stmt::While(cond, body) => mir::Stmt::While(
-                cond.into_mir(ctx),
-                body.into_iter()
-                    .map(|stmt| stmt.into_mir(ctx))
-                    .collect::<Vec<_>>(),
+            StmtKind::While(cond, body) => {
+                hir::StmtKind::While(cond.into_hir(ctx), block_into_hir(body, ctx))
+            }
+            StmtKind::Match(expr, arms) => hir::StmtKind::Match(
+                expr.into_hir(ctx),
+                arms.into_iter()
+                    .map(|arm| {
+                        ctx.resolver.scope();
+                        let pattern = bind_pattern(arm.pattern, ctx);
+                        let body = arm
+                            .body
+                            .into_iter()
+                            .map(|stmt| stmt.into_hir(ctx))
+                            .collect::<Vec<_>>();
+                        ctx.resolver.unscope();
+                        hir::Arm::new(pattern, body)
+                    }).collect::<Vec<_>>(),
             ),
-            Stmt::Return(expr) => mir::Stmt::Return(expr.map(|expr| expr.into_mir(ctx))),
-        }
+            StmtKind::Return(expr) => hir::StmtKind::Return(expr.map(|expr| expr.into_hir(ctx))),
+        };
+        hir::Stmt::new(kind, span)
     }
 }
 
 #[derive(Debug)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
     Call(String, Vec<Expr>),
+    /// A call to a function value that isn't just a name.
+    Apply(Box<Expr>, Vec<Expr>),
+    Lambda(Vec<Arg>, Box<Expr>),
     Literal(Literal),
     Name(String),
-    Dot(Box<Expr>, Box<Expr>),
-    NotEquals(Box<Expr>, Box<Expr>),
-    Equals(Box<Expr>, Box<Expr>),
-    Plus(Box<Expr>, Box<Expr>),
-    Minus(Box<Expr>, Box<Expr>),
-    Times(Box<Expr>, Box<Expr>),
-}
-
-impl IntoMir<mir::Expr> for Expr {
-    fn into_mir(self, ctx: &mut Context) -> mir::Expr {
-        match self {
-            Expr::Call(func, args) => {
+    Dot(Box<Expr>, String),
+    Struct(String, Vec<(String, Expr)>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    /// The segments of a name like `a.b.c`, if this expression is one.
+    pub fn path(&self) -> Option<Vec<String>> {
+        match &self.kind {
+            ExprKind::Name(name) => Some(vec![name.clone()]),
+            ExprKind::Dot(object, field) => object.path().map(|mut path| {
+                path.push(field.clone());
+                path
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl ExprKind {
+    pub fn call(func: Expr, args: Vec<Expr>) -> Self {
+        match func.kind {
+            ExprKind::Name(name) => ExprKind::Call(name, args),
+            kind => ExprKind::Apply(Box::new(Expr::new(kind, func.span)), args),
+        }
+    }
+}
+
+impl IntoHir<hir::Expr> for Expr {
+    fn into_hir(self, ctx: &mut Context) -> hir::Expr {
+        let span = self.span;
+        match self.kind {
+            ExprKind::Call(func, args) => {
                 let args_m = args
                     .into_iter()
-                    .map(|expr| expr.into_mir(ctx))
+                    .map(|expr| expr.into_hir(ctx))
+                    .collect::<Vec<_>>();
+                // a variable hides a variant, which hides a function
+                let variant = match ctx.resolver.local(&func) {
+                    Some(_) => None,
+                    None => ctx.lookup_variant(&func),
+                };
+                match variant {
+                    Some(name) => {
+                        let ty = Type::Enum(name.clone());
+                        hir::Expr::new(hir::ExprKind::Variant(name, func, args_m), ty, span)
+                    }
+                    None => {
+                        let func = ctx.resolver.resolve_call(func, span);
+                        let kind = hir::ExprKind::Call(func, Vec::new(), args_m);
+                        hir::Expr::new(kind, ctx.next(), span)
+                    }
+                }
+            }
+            ExprKind::Apply(func, args) => {
+                let func = Box::new((*func).into_hir(ctx));
+                let args = args
+                    .into_iter()
+                    .map(|expr| expr.into_hir(ctx))
                     .collect::<Vec<_>>();
-                mir::Expr::Call(func, args_m, ctx.next())
+                hir::Expr::new(hir::ExprKind::Apply(func, args), ctx.next(), span)
             }
-            Expr::Literal(lit) => {
+            ExprKind::Lambda(args, body) => {
+                ctx.resolver.scope();
+                let args = bind_args(args, ctx);
+                let body = Box::new((*body).into_hir(ctx));
+                ctx.resolver.unscope();
+                hir::Expr::new(hir::ExprKind::Lambda(args, body), ctx.next(), span)
+            }
+            ExprKind::Literal(lit) => {
                 let ty = lit.get_type();
-                mir::Expr::Literal(lit, ty)
-            }
-            Expr::Name(name) => mir::Expr::Name(name, ctx.next()),
-            Expr::Dot(_left, _right) => unimplemented!(),
-            Expr::NotEquals(left, right) => {
-                let left = Box::new((*left).into_mir(ctx));
-                let right = Box::new((*right).into_mir(ctx));
-                mir::Expr::NotEquals(left, right, Type::Bool)
-            }
-            Expr::Equals(left, right) => {
-                let left = Box::new((*left).into_mir(ctx));
-                let right = Box::new((*right).into_mir(ctx));
-                mir::Expr::Equals(left, right, Type::Bool)
-            }
-            Expr::Plus(left, right) => {
-                let left = Box::new((*left).into_mir(ctx));
-                let right = Box::new((*right).into_mir(ctx));
-                mir::Expr::Plus(left, right, ctx.next())
-            }
-            Expr::Minus(left, right) => {
-                let left = Box::new((*left).into_mir(ctx));
-                let right = Box::new((*right).into_mir(ctx));
-                mir::Expr::Minus(left, right, ctx.next())
-            }
-            Expr::Times(left, right) => {
-                let left = Box::new((*left).into_mir(ctx));
-                let right = Box::new((*right).into_mir(ctx));
-                mir::Expr::Times(left, right, ctx.next())
+                hir::Expr::new(hir::ExprKind::Literal(lit), ty, span)
+            }
+            ExprKind::Name(name) => {
+                let variant = match ctx.resolver.local(&name) {
+                    Some(_) => None,
+                    None => ctx.lookup_variant(&name),
+                };
+                match variant {
+                    Some(enum_name) => {
+                        let ty = Type::Enum(enum_name.clone());
+                        let kind = hir::ExprKind::Variant(enum_name, name, Vec::new());
+                        hir::Expr::new(kind, ty, span)
+                    }
+                    None => {
+                        let name = ctx.resolver.resolve(name, span);
+                        hir::Expr::new(hir::ExprKind::Name(name), ctx.next(), span)
+                    }
+                }
+            }
+            ExprKind::Dot(object, field) => {
+                let object = Box::new((*object).into_hir(ctx));
+                hir::Expr::new(hir::ExprKind::Field(object, field), ctx.next(), span)
+            }
+            ExprKind::Struct(name, fields) => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, expr)| (field, expr.into_hir(ctx)))
+                    .collect::<Vec<_>>();
+                let ty = Type::Struct(name.clone());
+                hir::Expr::new(hir::ExprKind::Struct(name, fields), ty, span)
+            }
+            ExprKind::And(left, right) => {
+                let left = Box::new((*left).into_hir(ctx));
+                let right = Box::new((*right).into_hir(ctx));
+                hir::Expr::new(hir::ExprKind::And(left, right), Type::Bool, span)
+            }
+            ExprKind::Or(left, right) => {
+                let left = Box::new((*left).into_hir(ctx));
+                let right = Box::new((*right).into_hir(ctx));
+                hir::Expr::new(hir::ExprKind::Or(left, right), Type::Bool, span)
+            }
+            ExprKind::Unary(op, expr) => {
+                let expr = Box::new((*expr).into_hir(ctx));
+                let ty = match op {
+                    UnOp::Not => Type::Bool,
+                    UnOp::Neg => ctx.next(),
+                };
+                hir::Expr::new(hir::ExprKind::Unary(op, expr), ty, span)
+            }
+            ExprKind::Binary(op, left, right) => {
+                let left = Box::new((*left).into_hir(ctx));
+                let right = Box::new((*right).into_hir(ctx));
+                let ty = if op.is_comparison() {
+                    Type::Bool
+                } else {
+                    ctx.next()
+                };
+                hir::Expr::new(hir::ExprKind::Binary(op, left, right), ty, span)
             }
         }
     }
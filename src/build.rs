@@ -1,5 +1,10 @@
 extern crate lalrpop;
 
+use std::env;
+
 fn main() {
+    // the lane table algorithm merges states like LALR does, so a syntax
+    // error would list the tokens expected anywhere the same rule is used
+    env::set_var("LALRPOP_LANE_TABLE", "disabled");
     lalrpop::process_root().unwrap();
 }
@@ -1,16 +1,10 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-use common::{self, next_int, Typed};
+use common::{self, next_int, BinOp, Generic, Type, Typed, UnOp};
+use diagnostic::Diagnostic;
+use hir;
 use mir;
-
-fn letter_of_number(mut n: u32) -> String {
-    let mut result = String::new();
-    while n > 0 {
-        result.push(((n % 26) as u8 + 97) as char);
-        n /= 26;
-    }
-    result.chars().rev().collect::<String>()
-}
+use span::Span;
 
 #[derive(Debug)]
 pub enum Item {
@@ -27,15 +21,14 @@ impl Item {
     }
 }
 
-impl Into<String> for Item {
-    fn into(self) -> String {
-        self.as_string()
+impl From<Item> for String {
+    fn from(item: Item) -> String {
+        item.as_string()
     }
 }
 
 #[derive(Debug, Default)]
 pub struct Scope {
-    names: BTreeMap<String, u32>,
     items: Vec<Item>,
 }
 
@@ -49,11 +42,8 @@ impl Scope {
     pub fn push_line(&mut self, line: impl AsRef<str>) {
         self.items.push(Item::Line(line.as_ref().to_owned()));
     }
-    pub fn new_variable(&mut self, name: impl AsRef<str>, id: u32) {
-        self.names.insert(name.as_ref().to_owned(), id);
-    }
-    pub fn lookup_name(&self, name: impl AsRef<str>) -> Option<u32> {
-        self.names.get(name.as_ref()).cloned()
+    pub fn prepend_lines(&mut self, lines: Vec<String>) {
+        self.items.splice(0..0, lines.into_iter().map(Item::Line));
     }
     pub fn as_string(&self) -> String {
         self.items
@@ -64,15 +54,77 @@ impl Scope {
     }
 }
 
+/// A lambda waiting to be emitted as a function of its own, which takes the
+/// values it captured in a struct behind its first argument.
+#[derive(Debug)]
+pub struct Lambda {
+    symbol: String,
+    body: mir::Body,
+}
+
 #[derive(Debug)]
 pub struct Emitter {
     scope_stack: Vec<Scope>,
+    /// The stack slot of each local of the function being emitted.
+    locals: BTreeMap<mir::Local, u32>,
+    functions: BTreeMap<String, Type>,
+    generics: BTreeMap<String, Vec<String>>,
+    instances: BTreeSet<String>,
+    pending: Vec<(String, Vec<Type>)>,
+    lambdas: Vec<Lambda>,
+    allocas: Vec<String>,
 }
 
 impl Emitter {
     pub fn new() -> Self {
         Emitter {
             scope_stack: vec![Scope::default()],
+            locals: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            generics: BTreeMap::new(),
+            instances: BTreeSet::new(),
+            pending: Vec::new(),
+            lambdas: Vec::new(),
+            allocas: Vec::new(),
+        }
+    }
+    pub fn function(&mut self, name: impl AsRef<str>, ty: Type) {
+        self.functions.insert(name.as_ref().to_owned(), ty);
+    }
+    pub fn signature(&self, name: impl AsRef<str>) -> Option<&Type> {
+        self.functions.get(name.as_ref())
+    }
+    pub fn generic(&mut self, name: impl AsRef<str>, params: &[String]) {
+        self.generics.insert(name.as_ref().to_owned(), params.to_vec());
+    }
+    pub fn generic_params(&self, name: impl AsRef<str>) -> &[String] {
+        self.generics
+            .get(name.as_ref())
+            .map(|params| params.as_slice())
+            .unwrap_or(&[])
+    }
+    /// Asks for a copy of the generic function `name` with its parameters
+    /// replaced by `args`, returning the symbol it will be emitted as.
+    pub fn instance(&mut self, name: impl AsRef<str>, args: &[Type]) -> String {
+        let symbol = mangle(name.as_ref(), args);
+        if self.instances.insert(symbol.clone()) {
+            self.pending.push((name.as_ref().to_owned(), args.to_vec()));
+        }
+        symbol
+    }
+    pub fn next_instance(&mut self) -> Option<(String, Vec<Type>)> {
+        self.pending.pop()
+    }
+    pub fn lambda(&mut self, lambda: Lambda) {
+        self.lambdas.push(lambda);
+    }
+    pub fn next_lambda(&mut self) -> Option<Lambda> {
+        self.lambdas.pop()
+    }
+    /// Emits `line` once, no matter how many times it is asked for under `key`.
+    pub fn push_global_once(&mut self, key: impl AsRef<str>, line: impl AsRef<str>) {
+        if self.instances.insert(key.as_ref().to_owned()) {
+            self.push_global_line(line);
         }
     }
     pub fn scope(&mut self) {
@@ -88,18 +140,20 @@ impl Emitter {
     pub fn next_int(&mut self) -> u32 {
         next_int()
     }
-    pub fn new_variable(&mut self, name: impl AsRef<str>, id: u32) {
-        if let Some(scope) = self.scope_stack.last_mut() {
-            scope.new_variable(name, id)
-        }
+    /// Reserves stack space for a value of type `ty`. The `alloca`s are all
+    /// placed at the top of the function by `flush_allocas`, so that locals
+    /// declared in a loop don't grow the stack on every iteration.
+    pub fn alloca(&mut self, ty: &Type) -> u32 {
+        let result = self.next_int();
+        self.allocas
+            .push(format!("%i{} = alloca {}", result, ty.ir_repr().as_ref()));
+        result
     }
-    pub fn lookup_name(&self, name: impl AsRef<str>) -> Option<u32> {
-        for v in self.scope_stack.iter().rev() {
-            if let Some(n) = v.lookup_name(&name) {
-                return Some(n);
-            }
+    pub fn flush_allocas(&mut self) {
+        let allocas = self.allocas.drain(..).collect();
+        if let Some(scope) = self.scope_stack.last_mut() {
+            scope.prepend_lines(allocas);
         }
-        None
     }
     pub fn push_global_line(&mut self, line: impl AsRef<str>) {
         if let Some(scope) = self.scope_stack.first_mut() {
@@ -117,246 +171,925 @@ impl Emitter {
 }
 
 pub trait Codegen<T = ()> {
-    fn generate(&self, &mut Emitter) -> T;
+    fn generate(&self, emitter: &mut Emitter) -> Result<T, Diagnostic>;
 }
 
 impl Codegen for mir::Program {
-    fn generate(&self, emitter: &mut Emitter) {
-        for decl in self.0.iter() {
-            decl.generate(emitter);
+    fn generate(&self, emitter: &mut Emitter) -> Result<(), Diagnostic> {
+        for decl in self.decls.iter() {
+            if let hir::TopDeclKind::Extern(name, ty) = &decl.kind {
+                emitter.function(name, ty.clone());
+            }
+        }
+        for function in self.functions.iter() {
+            let body = &function.body;
+            emitter.generic(&function.name, &Generic::names(&function.generics));
+            emitter.function(
+                &function.name,
+                Type::Fn(
+                    body.args.iter().map(|arg| body.locals[*arg].ty.clone()).collect(),
+                    Box::new(emitted_return(&function.name, &body.ret)),
+                    false,
+                ),
+            );
+        }
+        for decl in self.decls.iter() {
+            decl.generate(emitter)?;
         }
+        for function in self.functions.iter() {
+            // generic functions are only emitted once they are instantiated
+            if function.generics.is_empty() {
+                emit_body(emitter, &function.name, &function.body, false)?;
+            }
+        }
+
+        // every instantiation of a generic function and every lambda gets a
+        // function of its own, which can ask for more of them in turn
+        loop {
+            let (name, args) = match emitter.next_instance() {
+                Some(instance) => instance,
+                None => match emitter.next_lambda() {
+                    Some(lambda) => {
+                        emit_body(emitter, &lambda.symbol, &lambda.body, true)?;
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            let function = self.functions.iter().find(|function| function.name == name);
+            if let Some(function) = function {
+                let generics = Generic::names(&function.generics);
+                let mut body = function.body.clone();
+                body.map_types(&mut |ty| ty.instantiate(&generics, &args));
+                emit_body(emitter, &mangle(&name, &args), &body, false)?;
+            }
+        }
+        Ok(())
     }
 }
 
-impl Codegen for mir::TopDecl {
-    fn generate(&self, emitter: &mut Emitter) {
-        use mir::TopDecl;
-        match self {
-            TopDecl::Extern(name, _ty) => {
-                emitter.push_line(format!("declare i32 @{}(i8* nocapture) nounwind", name));
-            }
-            TopDecl::Fn(name, args, _ty, stmts) => {
-                let mut args_s = Vec::new();
-                let mut args_a = Vec::new();
-                for arg in args {
-                    let argn = emitter.next_int();
-                    args_s.push(format!("{} %i{}", arg.1.ir_repr().as_ref(), argn));
-
-                    let tmp = emitter.next_int();
-                    args_a.push(format!("%i{} = alloca i32", tmp));
-                    args_a.push(format!("store i32 %i{}, i32* %i{}", argn, tmp));
-                    emitter.new_variable(&arg.0, tmp);
+impl Codegen for hir::TopDecl {
+    fn generate(&self, emitter: &mut Emitter) -> Result<(), Diagnostic> {
+        use hir::TopDeclKind;
+        match &self.kind {
+            TopDeclKind::Extern(name, ty) => match ty {
+                Type::Fn(args, ret, variadic) => {
+                    emitter.push_line(format!(
+                        "declare {} @{}({})",
+                        ret.ir_repr().as_ref(),
+                        name,
+                        param_list(args, *variadic)
+                    ));
                 }
-                for _arg in args {}
-                emitter.push_line(format!("define i32 @{} ({}) {{", name, args_s.join(", ")));
-                emitter.push_line("entry:");
-                emitter.scope();
-                for arg in args_a {
-                    emitter.push_line(arg);
+                _ => {
+                    return Err(Diagnostic::error(
+                        self.span,
+                        format!("extern `{}` must have a function type", name),
+                    ))
                 }
-                for stmt in stmts {
-                    stmt.generate(emitter);
-                }
-                emitter.pop();
-                emitter.push_line("ret i32 0");
-                emitter.push_line("}");
-            }
-            TopDecl::Struct(name, fields) => {
+            },
+            // functions are emitted from their `mir::Body`
+            TopDeclKind::Fn(_, _, _, _, _) => (),
+            // classes only exist for typeck, and the methods of instances
+            // follow as functions
+            TopDeclKind::Class(_, _, _) | TopDeclKind::Instance(_, _, _) => (),
+            TopDeclKind::Struct(name, fields) => {
                 let fields_s = fields
                     .iter()
-                    .map(|field| field.get_type().ir_repr().as_ref().to_owned())
+                    .map(|field| field.get_type().field_repr())
                     .collect::<Vec<_>>()
                     .join(", ");
                 emitter.push_line(format!("%st.{} = type {{ {} }}", name, fields_s));
             }
+            TopDeclKind::Enum(name, variants) => {
+                // the tag, followed by a payload struct for every variant; only
+                // the one picked by the tag is ever filled in
+                let mut fields_s = vec!["i32".to_owned()];
+                for variant in variants {
+                    let payload = variant
+                        .1
+                        .iter()
+                        .map(Type::field_repr)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    fields_s.push(format!("{{ {} }}", payload));
+                }
+                emitter.push_line(format!("%en.{} = type {{ {} }}", name, fields_s.join(", ")));
+            }
         }
+        Ok(())
     }
 }
 
-impl Codegen for mir::Stmt {
-    fn generate(&self, emitter: &mut Emitter) {
-        use mir::Stmt;
-        match self {
-            Stmt::Assign(re, name, expr) => {
-                let assigned = expr.generate(emitter);
-                let result = if !*re {
-                    let result = emitter.next_int();
-                    emitter.new_variable(name, result);
-                    emitter.push_line(format!("%i{} = alloca i32", result));
-                    result
-                } else {
-                    emitter
-                        .lookup_name(name)
-                        .unwrap_or_else(|| panic!("Name '{}' not found.", name))
-                };
-                let tmp = emitter.next_int();
-                emitter.push_line(format!("%i{} = add i32 %i{}, 0", tmp, assigned));
-                emitter.push_line(format!("store i32 %i{}, i32* %i{}", tmp, result));
+/// Emits `body` as the function `symbol`, one basic block at a time. Every
+/// local lives on the stack. Lambdas take a pointer to their captures before
+/// the arguments.
+fn emit_body(
+    emitter: &mut Emitter,
+    symbol: &str,
+    body: &mir::Body,
+    lambda: bool,
+) -> Result<(), Diagnostic> {
+    emitter.locals.clear();
+    let mut args_s = Vec::new();
+    let env = emitter.next_int();
+    if lambda {
+        args_s.push(format!("i8* %i{}", env));
+    }
+    let mut args_n = Vec::new();
+    for arg in body.args.iter() {
+        let argn = emitter.next_int();
+        // values of type `()` aren't passed at all
+        let ty = &body.locals[*arg].ty;
+        if *ty != Type::Unit {
+            args_s.push(format!("{} %i{}", ty.ir_repr().as_ref(), argn));
+        }
+        args_n.push(argn);
+    }
+    let ret = emitted_return(symbol, &body.ret);
+    emitter.push_line(format!(
+        "define {} {} ({}) {{",
+        ret.ir_repr().as_ref(),
+        global(symbol),
+        args_s.join(", ")
+    ));
+    emitter.push_line("entry:");
+    emitter.scope();
+
+    for (local, decl) in body.locals.iter().enumerate() {
+        // values of type `()` don't need any storage
+        if decl.ty != Type::Unit {
+            let slot = emitter.alloca(&decl.ty);
+            emitter.locals.insert(local, slot);
+        }
+    }
+    let captures = stored_captures(body);
+    if !captures.is_empty() {
+        let env_t = env_type(body);
+        let pointer = emitter.next_int();
+        emitter.push_line(format!("%i{} = bitcast i8* %i{} to {}*", pointer, env, env_t));
+        for (i, local) in captures.iter().enumerate() {
+            let ty_s = body.locals[*local].ty.ir_repr().as_ref().to_owned();
+            let field = emitter.next_int();
+            let value = emitter.next_int();
+            emitter.push_line(format!(
+                "%i{} = getelementptr {}, {}* %i{}, i32 0, i32 {}",
+                field, env_t, env_t, pointer, i
+            ));
+            emitter.push_line(format!("%i{} = load {}, {}* %i{}", value, ty_s, ty_s, field));
+            let slot = emitter.locals[local];
+            emitter.push_line(format!("store {} %i{}, {}* %i{}", ty_s, value, ty_s, slot));
+        }
+    }
+    for (arg, argn) in body.args.iter().zip(args_n) {
+        let ty = body.locals[*arg].ty.ir_repr().as_ref().to_owned();
+        if let Some(slot) = emitter.locals.get(arg).cloned() {
+            emitter.push_line(format!("store {} %i{}, {}* %i{}", ty, argn, ty, slot));
+        }
+    }
+    emitter.push_line("br label %bb0");
+
+    for (i, block) in body.blocks.iter().enumerate() {
+        emitter.push_line(format!("bb{}:", i));
+        for statement in block.statements.iter() {
+            let ty = body.place_type(&statement.place);
+            let value = emit_rvalue(emitter, body, &statement.value, &ty, statement.span)?;
+            if ty != Type::Unit {
+                let ty = ty.ir_repr().as_ref().to_owned();
+                let pointer = emit_place(emitter, body, &statement.place);
+                emitter.push_line(format!("store {} %i{}, {}* %i{}", ty, value, ty, pointer));
             }
-            Stmt::Expr(expr) => {
-                expr.generate(emitter);
+        }
+        emit_terminator(emitter, body, &ret, i, &block.terminator)?;
+    }
+
+    emitter.flush_allocas();
+    emitter.pop();
+    emitter.push_line("}");
+    Ok(())
+}
+
+fn emit_terminator(
+    emitter: &mut Emitter,
+    body: &mir::Body,
+    ret: &Type,
+    block: mir::BlockId,
+    terminator: &mir::Terminator,
+) -> Result<(), Diagnostic> {
+    use mir::TerminatorKind;
+    let span = terminator.span;
+    match &terminator.kind {
+        TerminatorKind::Goto(target) => emitter.push_line(format!("br label %bb{}", target)),
+        TerminatorKind::Branch(cond, succ, fail) => {
+            let cond = emit_operand(emitter, body, cond, span)?;
+            emitter.push_line(format!(
+                "br i1 %i{}, label %bb{}, label %bb{}",
+                cond, succ, fail
+            ));
+        }
+        TerminatorKind::Switch(value, cases, otherwise) => {
+            let value = emit_operand(emitter, body, value, span)?;
+            let cases_s = cases
+                .iter()
+                .map(|(tag, target)| format!("i32 {}, label %bb{}", tag, target))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let default = match otherwise {
+                Some(target) => format!("bb{}", target),
+                None => format!("bb{}.unreachable", block),
+            };
+            emitter.push_line(format!(
+                "switch i32 %i{}, label %{} [ {} ]",
+                value, default, cases_s
+            ));
+            if otherwise.is_none() {
+                emitter.push_line(format!("{}:", default));
+                emitter.push_line("unreachable");
             }
-            Stmt::If(cond, body1, body2) => {
-                let cond_ty = cond.get_type().ir_repr().as_ref().to_owned();
-                let cond = cond.generate(emitter);
-                let cmp = emitter.next_int();
-                let succ_label = letter_of_number(emitter.next_int());
-                let fail_label = letter_of_number(emitter.next_int());
-                let done_label = letter_of_number(emitter.next_int());
-                emitter.push_line(format!("%i{} = icmp ne {} %i{}, 0", cmp, cond_ty, cond));
-                emitter.push_line(format!(
-                    "br i1 %i{}, label %L{}, label %L{}",
-                    cmp,
-                    succ_label,
-                    match &body2 {
-                        Some(_) => fail_label.clone(),
-                        None => done_label.clone(),
-                    }
-                ));
-                emitter.push_line(format!("L{}:", succ_label));
-                emitter.scope();
-                for stmt in body1 {
-                    stmt.generate(emitter);
+        }
+        TerminatorKind::Return(Some(value)) if body.operand_type(value) != Type::Unit => {
+            let ty = body.operand_type(value).ir_repr().as_ref().to_owned();
+            let value = emit_operand(emitter, body, value, span)?;
+            emitter.push_line(format!("ret {} %i{}", ty, value));
+        }
+        // only a `main` returning `()`, which exits with 0
+        TerminatorKind::Return(_) if *ret != Type::Unit => {
+            emitter.push_line(format!("ret {} 0", ret.ir_repr().as_ref()))
+        }
+        TerminatorKind::Return(_) => emitter.push_line("ret void"),
+        TerminatorKind::Unreachable => emitter.push_line("unreachable"),
+    }
+    Ok(())
+}
+
+/// The type a function is emitted with as returning. `main` returns the exit
+/// code of the program, so it returns an int even when it is declared to
+/// return `()`.
+fn emitted_return(symbol: &str, ret: &Type) -> Type {
+    if symbol == "main" && *ret == Type::Unit {
+        Type::Int
+    } else {
+        ret.clone()
+    }
+}
+
+/// Emits the address of a place.
+fn emit_place(emitter: &mut Emitter, body: &mir::Body, place: &mir::Place) -> u32 {
+    let mut pointer = emitter.locals.get(&place.local).cloned().unwrap_or_default();
+    let mut ty = body.locals[place.local].ty.ir_repr().as_ref().to_owned();
+    for projection in place.projection.iter() {
+        let (indices, field_ty) = match projection {
+            mir::Projection::Field(index, field_ty) => (format!("i32 {}", index), field_ty),
+            mir::Projection::Payload(tag, index, field_ty) => {
+                (format!("i32 {}, i32 {}", tag + 1, index), field_ty)
+            }
+        };
+        let result = emitter.next_int();
+        emitter.push_line(format!(
+            "%i{} = getelementptr {}, {}* %i{}, i32 0, {}",
+            result, ty, ty, pointer, indices
+        ));
+        pointer = result;
+        ty = field_ty.ir_repr().as_ref().to_owned();
+    }
+    pointer
+}
+
+/// Emits the value of an operand. Values of type `()` have no storage, so
+/// reading one emits nothing and the result must not be used.
+fn emit_operand(
+    emitter: &mut Emitter,
+    body: &mir::Body,
+    operand: &mir::Operand,
+    span: Span,
+) -> Result<u32, Diagnostic> {
+    match operand {
+        mir::Operand::Copy(place, _) if body.place_type(place) == Type::Unit => Ok(0),
+        mir::Operand::Copy(place, _) => {
+            let ty = body.place_type(place).ir_repr().as_ref().to_owned();
+            let pointer = emit_place(emitter, body, place);
+            let result = emitter.next_int();
+            emitter.push_line(format!("%i{} = load {}, {}* %i{}", result, ty, ty, pointer));
+            Ok(result)
+        }
+        mir::Operand::Constant(literal) => literal.generate(emitter),
+        mir::Operand::Function(name, ty) => function_value(emitter, name, ty, span),
+    }
+}
+
+/// Emits the operands in order, along with their types.
+fn emit_operands(
+    emitter: &mut Emitter,
+    body: &mir::Body,
+    operands: &[mir::Operand],
+    span: Span,
+) -> Result<Vec<(u32, Type)>, Diagnostic> {
+    let mut values = Vec::new();
+    for operand in operands {
+        let value = emit_operand(emitter, body, operand, span)?;
+        values.push((value, body.operand_type(operand)));
+    }
+    Ok(values)
+}
+
+/// Emits an rvalue whose result has type `ty`.
+fn emit_rvalue(
+    emitter: &mut Emitter,
+    body: &mir::Body,
+    rvalue: &mir::Rvalue,
+    ty: &Type,
+    span: Span,
+) -> Result<u32, Diagnostic> {
+    use mir::{Callee, Rvalue};
+    match rvalue {
+        Rvalue::Use(operand) => emit_operand(emitter, body, operand, span),
+        Rvalue::Call(Callee::Value(func), args) => {
+            let closure = emit_operand(emitter, body, func, span)?;
+            let args = emit_operands(emitter, body, args, span)?;
+            Ok(call_closure(emitter, closure, &args, ty))
+        }
+        Rvalue::Call(Callee::Method(class, method, method_ty), args) => {
+            let args = emit_operands(emitter, body, args, span)?;
+            let args = args.into_iter().map(|(value, _)| value).collect::<Vec<_>>();
+            call_method(emitter, class, method, method_ty, &args, span)
+        }
+        Rvalue::Call(Callee::Function(func, type_args), args) => {
+            let (mut params, mut ret, variadic) = match emitter.signature(func) {
+                Some(Type::Fn(params, ret, variadic)) => (params.clone(), (**ret).clone(), *variadic),
+                _ => {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("no signature for function `{}`", func),
+                    ))
                 }
-                emitter.pop();
-                emitter.push_line(format!("br label %L{}", done_label));
-                if let Some(body) = body2 {
-                    emitter.push_line(format!("L{}:", fail_label));
-                    emitter.scope();
-                    for stmt in body {
-                        stmt.generate(emitter);
-                    }
-                    emitter.pop();
-                    emitter.push_line(format!("br label %L{}", done_label));
+            };
+            let symbol = if type_args.is_empty() {
+                func.clone()
+            } else {
+                let generics = emitter.generic_params(func).to_vec();
+                for param in params.iter_mut() {
+                    param.instantiate(&generics, type_args);
                 }
-                emitter.push_line(format!("L{}:", done_label));
-            }
-            Stmt::While(cond, body) => {
-                let begin_label = letter_of_number(emitter.next_int());
-                let check_label = letter_of_number(emitter.next_int());
-                let done_label = letter_of_number(emitter.next_int());
-                emitter.push_line(format!("br label %L{}", check_label));
-                emitter.push_line(format!("L{}:", begin_label));
-                emitter.scope();
-                for stmt in body {
-                    stmt.generate(emitter);
+                ret.instantiate(&generics, type_args);
+                emitter.instance(func, type_args)
+            };
+            let values = emit_operands(emitter, body, args, span)?;
+            let mut args_s = Vec::new();
+            for (i, (value, arg_ty)) in values.into_iter().enumerate() {
+                let ty = params.get(i).cloned().unwrap_or(arg_ty);
+                if ty == Type::Unit {
+                    continue;
                 }
-                emitter.pop();
-
-                emitter.push_line(format!("br label %L{}", check_label));
-                emitter.push_line(format!("L{}:", check_label));
+                if i >= params.len() && ty == Type::Bool {
+                    // C promotes variadic arguments smaller than an int
+                    let promoted = emitter.next_int();
+                    emitter.push_line(format!("%i{} = zext i1 %i{} to i32", promoted, value));
+                    args_s.push(format!("i32 %i{}", promoted));
+                } else {
+                    args_s.push(format!("{} %i{}", ty.ir_repr().as_ref(), value));
+                }
+            }
 
-                let cond_ty = cond.get_type().ir_repr().as_ref().to_owned();
-                let cond = cond.generate(emitter);
-                let cmp = emitter.next_int();
-                emitter.push_line(format!("%i{} = icmp ne {} %i{}, 0", cmp, cond_ty, cond));
+            // calls to variadic functions have to spell out its type
+            let callee = if variadic {
+                format!(
+                    "{} ({}) {}",
+                    ret.ir_repr().as_ref(),
+                    param_list(&params, true),
+                    global(&symbol)
+                )
+            } else {
+                format!("{} {}", ret.ir_repr().as_ref(), global(&symbol))
+            };
+            let result = emitter.next_int();
+            if ret == Type::Unit {
+                // there is no value, so `result` is never defined
+                emitter.push_line(format!("call {}({})", callee, args_s.join(", ")));
+            } else {
                 emitter.push_line(format!(
-                    "br i1 %i{}, label %L{}, label %L{}",
-                    cmp, begin_label, done_label
+                    "%i{} = call {}({})",
+                    result,
+                    callee,
+                    args_s.join(", ")
                 ));
-                emitter.push_line(format!("L{}:", done_label));
             }
-            Stmt::Return(expr) => match expr {
-                Some(expr) => {
-                    let expr = expr.generate(emitter);
-                    emitter.push_line(format!("ret i32 %i{}", expr));
-                }
-                None => {
-                    emitter.push_line("ret void".to_owned());
-                }
-            },
+            Ok(result)
         }
-    }
-}
+        Rvalue::Closure(index, captures) => {
+            let lambda = &body.lambdas[*index];
+            let mut values = emit_operands(emitter, body, captures, span)?;
+            values.retain(|(_, ty)| *ty != Type::Unit);
 
-impl Codegen<u32> for mir::Expr {
-    fn generate(&self, emitter: &mut Emitter) -> u32 {
-        use mir::Expr;
-        match self {
-            Expr::Call(func, args, _ty) => {
+            // copy the captured values into a struct on the heap, which lives
+            // as long as the program does
+            let env = if values.is_empty() {
+                "null".to_owned()
+            } else {
+                let env_t = env_type(lambda);
+                let size = emitter.next_int();
+                let size_i = emitter.next_int();
+                let raw = emitter.next_int();
+                let pointer = emitter.next_int();
+                let malloc = match emitter.signature("malloc") {
+                    // the program declared `malloc` itself, maybe with other types
+                    Some(Type::Fn(params, ret, variadic)) => format!(
+                        "bitcast ({} ({})* @malloc to i8* (i64)*)",
+                        ret.ir_repr().as_ref(),
+                        param_list(params, *variadic)
+                    ),
+                    _ => {
+                        emitter.push_global_once("malloc", "declare i8* @malloc(i64)");
+                        "@malloc".to_owned()
+                    }
+                };
+                emitter.push_line(format!(
+                    "%i{} = getelementptr {}, {}* null, i32 1",
+                    size, env_t, env_t
+                ));
+                emitter.push_line(format!(
+                    "%i{} = ptrtoint {}* %i{} to i64",
+                    size_i, env_t, size
+                ));
+                emitter.push_line(format!("%i{} = call i8* {}(i64 %i{})", raw, malloc, size_i));
+                emitter.push_line(format!(
+                    "%i{} = bitcast i8* %i{} to {}*",
+                    pointer, raw, env_t
+                ));
+                for (i, (value, ty)) in values.iter().enumerate() {
+                    let ty_s = ty.ir_repr().as_ref().to_owned();
+                    let field = emitter.next_int();
+                    emitter.push_line(format!(
+                        "%i{} = getelementptr {}, {}* %i{}, i32 0, i32 {}",
+                        field, env_t, env_t, pointer, i
+                    ));
+                    emitter.push_line(format!(
+                        "store {} %i{}, {}* %i{}",
+                        ty_s, value, ty_s, field
+                    ));
+                }
+                format!("%i{}", raw)
+            };
+
+            let symbol = format!("lambda.{}", emitter.next_int());
+            let params = lambda
+                .args
+                .iter()
+                .map(|arg| lambda.locals[*arg].ty.clone())
+                .collect::<Vec<_>>();
+            let code = code_pointer(&symbol, &params, &lambda.ret);
+            emitter.lambda(Lambda {
+                symbol,
+                body: lambda.clone(),
+            });
+            Ok(closure_value(emitter, &code, &env))
+        }
+        Rvalue::Struct(_, fields) => {
+            let values = emit_operands(emitter, body, fields, span)?;
+            // fields of type `()` have nothing to fill in
+            let values = values
+                .into_iter()
+                .enumerate()
+                .filter(|(_, (_, field_ty))| *field_ty != Type::Unit)
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                let tmp = emitter.alloca(ty);
+                let ty = ty.ir_repr().as_ref().to_owned();
                 let result = emitter.next_int();
-                let args = args
-                    .iter()
-                    .map(|expr| {
-                        let ename = expr.generate(emitter);
-                        format!("{} %i{}", expr.get_type().ir_repr().as_ref(), ename)
-                    }).collect::<Vec<_>>()
-                    .join(", ");
-                emitter.push_line(format!("%i{} = call i32 @{}({})", result, func, args));
-                result
+                emitter.push_line(format!("%i{} = load {}, {}* %i{}", result, ty, ty, tmp));
+                return Ok(result);
             }
-            Expr::Literal(lit, _ty) => lit.generate(emitter),
-            Expr::Name(name, _ty) => match emitter.lookup_name(name) {
-                Some(val) => {
-                    let result = emitter.next_int();
-                    emitter.push_line(format!("%i{} = load i32, i32* %i{}", result, val));
-                    result
+            let ty = ty.ir_repr().as_ref().to_owned();
+            let mut previous = "undef".to_owned();
+            let mut result = 0;
+            for (index, (value, field_ty)) in values {
+                result = emitter.next_int();
+                emitter.push_line(format!(
+                    "%i{} = insertvalue {} {}, {} %i{}, {}",
+                    result,
+                    ty,
+                    previous,
+                    field_ty.ir_repr().as_ref(),
+                    value,
+                    index
+                ));
+                previous = format!("%i{}", result);
+            }
+            Ok(result)
+        }
+        Rvalue::Variant(_, tag, payload) => {
+            let ty = ty.ir_repr().as_ref().to_owned();
+            let values = emit_operands(emitter, body, payload, span)?;
+            let mut result = emitter.next_int();
+            emitter.push_line(format!("%i{} = insertvalue {} undef, i32 {}, 0", result, ty, tag));
+            for (i, (value, arg_ty)) in values.into_iter().enumerate() {
+                if arg_ty == Type::Unit {
+                    continue;
                 }
-                None => panic!("Could not find name '{}'", name),
-            },
-            Expr::NotEquals(left, right, _ty)
-            | Expr::Equals(left, right, _ty)
-            | Expr::Plus(left, right, _ty)
-            | Expr::Minus(left, right, _ty)
-            | Expr::Times(left, right, _ty) => {
-                let left = left.generate(emitter);
-                let right = right.generate(emitter);
-                let result = emitter.next_int();
+                let previous = result;
+                result = emitter.next_int();
                 emitter.push_line(format!(
-                    "%i{} = {} i32 %i{}, %i{}",
+                    "%i{} = insertvalue {} %i{}, {} %i{}, {}, {}",
                     result,
-                    match self {
-                        Expr::NotEquals(_, _, _) => "icmp ne",
-                        Expr::Equals(_, _, _) => "icmp eq",
-                        Expr::Plus(_, _, _) => "add",
-                        Expr::Minus(_, _, _) => "sub",
-                        Expr::Times(_, _, _) => "mul",
-                        _ => unreachable!(),
-                    },
-                    left,
-                    right,
+                    ty,
+                    previous,
+                    arg_ty.ir_repr().as_ref(),
+                    value,
+                    tag + 1,
+                    i
                 ));
-                result
             }
+            Ok(result)
+        }
+        Rvalue::Tag(place, _) => {
+            let enum_ty = body.place_type(place).ir_repr().as_ref().to_owned();
+            let pointer = emit_place(emitter, body, place);
+            let field = emitter.next_int();
+            let result = emitter.next_int();
+            emitter.push_line(format!(
+                "%i{} = getelementptr {}, {}* %i{}, i32 0, i32 0",
+                field, enum_ty, enum_ty, pointer
+            ));
+            emitter.push_line(format!("%i{} = load i32, i32* %i{}", result, field));
+            Ok(result)
+        }
+        Rvalue::Binary(op, left, right) => {
+            let left_t = body.operand_type(left);
+            let ty = left_t.ir_repr().as_ref().to_owned();
+            let left = emit_operand(emitter, body, left, span)?;
+            let right = emit_operand(emitter, body, right, span)?;
+
+            // `+` and `==` on anything but the primitive types go through
+            // their instance's method
+            let method = match op {
+                BinOp::Add => Some(("Add", "add")),
+                BinOp::Eq | BinOp::Ne => Some(("Eq", "eq")),
+                _ => None,
+            };
+            if let Some((class, method)) = method {
+                if builtin_method(class, &left_t).is_none() {
+                    let args = [left, right];
+                    let result = call_method(emitter, class, method, &left_t, &args, span)?;
+                    if *op != BinOp::Ne {
+                        return Ok(result);
+                    }
+                    let negated = emitter.next_int();
+                    emitter.push_line(format!("%i{} = xor i1 %i{}, true", negated, result));
+                    return Ok(negated);
+                }
+            }
+
+            let result = emitter.next_int();
+            emitter.push_line(format!(
+                "%i{} = {} {} %i{}, %i{}",
+                result,
+                match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::Div => "sdiv",
+                    BinOp::Rem => "srem",
+                    BinOp::BitAnd => "and",
+                    BinOp::BitOr => "or",
+                    BinOp::BitXor => "xor",
+                    BinOp::Shl => "shl",
+                    BinOp::Shr => "ashr",
+                    BinOp::Eq => "icmp eq",
+                    BinOp::Ne => "icmp ne",
+                    BinOp::Lt => "icmp slt",
+                    BinOp::Le => "icmp sle",
+                    BinOp::Gt => "icmp sgt",
+                    BinOp::Ge => "icmp sge",
+                },
+                ty,
+                left,
+                right,
+            ));
+            Ok(result)
+        }
+        Rvalue::Unary(op, operand) => {
+            let value = emit_operand(emitter, body, operand, span)?;
+            let result = emitter.next_int();
+            emitter.push_line(match op {
+                UnOp::Neg => format!("%i{} = sub i32 0, %i{}", result, value),
+                UnOp::Not => format!("%i{} = xor i1 %i{}, true", result, value),
+            });
+            Ok(result)
         }
     }
 }
 
+/// Escapes a string for a `c"..."` constant.
+fn escape_bytes(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'"' && b != b'\\' => (b as char).to_string(),
+            _ => format!("\\{:02X}", b),
+        }).collect()
+}
+
+/// The instruction implementing a method of one of the instances built into
+/// the compiler.
+fn builtin_method(class: &str, ty: &Type) -> Option<&'static str> {
+    match (class, ty) {
+        ("Add", Type::Int) => Some("add"),
+        ("Eq", Type::Int) | ("Eq", Type::Bool) => Some("icmp eq"),
+        _ => None,
+    }
+}
+
+/// Calls `method` from the instance of `class` for `ty` on the already
+/// evaluated `args`.
+fn call_method(
+    emitter: &mut Emitter,
+    class: &str,
+    method: &str,
+    ty: &Type,
+    args: &[u32],
+    span: Span,
+) -> Result<u32, Diagnostic> {
+    let result = emitter.next_int();
+    if let Some(instruction) = builtin_method(class, ty) {
+        let args_s = args
+            .iter()
+            .map(|arg| format!("%i{}", arg))
+            .collect::<Vec<_>>()
+            .join(", ");
+        emitter.push_line(format!(
+            "%i{} = {} {} {}",
+            result,
+            instruction,
+            ty.ir_repr().as_ref(),
+            args_s
+        ));
+        return Ok(result);
+    }
+
+    let symbol = hir::method_symbol(class, ty, method);
+    let (params, ret) = match emitter.signature(&symbol) {
+        Some(Type::Fn(params, ret, _)) => (params.clone(), (**ret).clone()),
+        _ => {
+            return Err(Diagnostic::error(
+                span,
+                format!("no instance of `{}` for `{}`", class, ty),
+            ))
+        }
+    };
+    let args_s = params
+        .iter()
+        .zip(args)
+        .filter(|(param, _)| **param != Type::Unit)
+        .map(|(param, arg)| format!("{} %i{}", param.ir_repr().as_ref(), arg))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if ret == Type::Unit {
+        emitter.push_line(format!("call void {}({})", global(&symbol), args_s));
+    } else {
+        emitter.push_line(format!(
+            "%i{} = call {} {}({})",
+            result,
+            ret.ir_repr().as_ref(),
+            global(&symbol),
+            args_s
+        ));
+    }
+    Ok(result)
+}
+
+/// Calls the function value `closure` on the already evaluated `args`,
+/// passing its environment first.
+fn call_closure(emitter: &mut Emitter, closure: u32, args: &[(u32, Type)], ret: &Type) -> u32 {
+    let params = args.iter().map(|(_, ty)| ty.clone()).collect::<Vec<_>>();
+    let code = emitter.next_int();
+    let function = emitter.next_int();
+    let env = emitter.next_int();
+    emitter.push_line(format!("%i{} = extractvalue {{ i8*, i8* }} %i{}, 0", code, closure));
+    emitter.push_line(format!(
+        "%i{} = bitcast i8* %i{} to {}",
+        function,
+        code,
+        closure_type(&params, ret)
+    ));
+    emitter.push_line(format!("%i{} = extractvalue {{ i8*, i8* }} %i{}, 1", env, closure));
+
+    let mut args_s = vec![format!("i8* %i{}", env)];
+    for (value, ty) in args.iter().filter(|(_, ty)| *ty != Type::Unit) {
+        args_s.push(format!("{} %i{}", ty.ir_repr().as_ref(), value));
+    }
+    let result = emitter.next_int();
+    if *ret == Type::Unit {
+        emitter.push_line(format!("call void %i{}({})", function, args_s.join(", ")));
+    } else {
+        emitter.push_line(format!(
+            "%i{} = call {} %i{}({})",
+            result,
+            ret.ir_repr().as_ref(),
+            function,
+            args_s.join(", ")
+        ));
+    }
+    result
+}
+
+/// Makes a function value out of the function `name`, through a wrapper that
+/// takes (and ignores) an environment like lambdas do.
+fn function_value(
+    emitter: &mut Emitter,
+    name: &str,
+    ty: &Type,
+    span: Span,
+) -> Result<u32, Diagnostic> {
+    let (mut params, mut ret) = match emitter.signature(name) {
+        Some(Type::Fn(params, ret, _)) => (params.clone(), (**ret).clone()),
+        _ => {
+            return Err(Diagnostic::error(
+                span,
+                format!("no signature for function `{}`", name),
+            ))
+        }
+    };
+    let generics = emitter.generic_params(name).to_vec();
+    let symbol = if generics.is_empty() {
+        name.to_owned()
+    } else {
+        let mut bound = BTreeMap::new();
+        Type::Fn(params.clone(), Box::new(ret.clone()), false).bind_params(ty, &mut bound);
+        let type_args = generics
+            .iter()
+            .map(|param| bound.get(param).cloned().unwrap_or(Type::Unit))
+            .collect::<Vec<_>>();
+        for param in params.iter_mut() {
+            param.instantiate(&generics, &type_args);
+        }
+        ret.instantiate(&generics, &type_args);
+        emitter.instance(name, &type_args)
+    };
+
+    let wrapper = format!("{}.closure", symbol);
+    let args_s = params
+        .iter()
+        .enumerate()
+        .filter(|(_, ty)| **ty != Type::Unit)
+        .map(|(i, ty)| format!("{} %a{}", ty.ir_repr().as_ref(), i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let ret_s = ret.ir_repr().as_ref().to_owned();
+    let body = if ret == Type::Unit {
+        format!("call void {}({})\nret void", global(&symbol), args_s)
+    } else {
+        format!("%r = call {} {}({})\nret {} %r", ret_s, global(&symbol), args_s, ret_s)
+    };
+    emitter.push_global_once(
+        &wrapper,
+        format!(
+            "define {} {} (i8* %env{}{}) {{\nentry:\n{}\n}}",
+            ret_s,
+            global(&wrapper),
+            if args_s.is_empty() { "" } else { ", " },
+            args_s,
+            body
+        ),
+    );
+    let code = code_pointer(&wrapper, &params, &ret);
+    Ok(closure_value(emitter, &code, "null"))
+}
+
+fn closure_value(emitter: &mut Emitter, code: &str, env: &str) -> u32 {
+    let partial = emitter.next_int();
+    let result = emitter.next_int();
+    emitter.push_line(format!(
+        "%i{} = insertvalue {{ i8*, i8* }} undef, i8* {}, 0",
+        partial, code
+    ));
+    emitter.push_line(format!(
+        "%i{} = insertvalue {{ i8*, i8* }} %i{}, i8* {}, 1",
+        result, partial, env
+    ));
+    result
+}
+
+/// The code of a function value, as the `i8*` stored in it.
+fn code_pointer(symbol: &str, params: &[Type], ret: &Type) -> String {
+    format!("bitcast ({} {} to i8*)", closure_type(params, ret), global(symbol))
+}
+
+/// The type of a pointer to the code of a function value.
+fn closure_type(params: &[Type], ret: &Type) -> String {
+    let mut params_s = vec!["i8*".to_owned()];
+    params_s.extend(
+        params
+            .iter()
+            .filter(|ty| **ty != Type::Unit)
+            .map(|ty| ty.ir_repr().as_ref().to_owned()),
+    );
+    format!("{} ({})*", ret.ir_repr().as_ref(), params_s.join(", "))
+}
+
+/// The struct holding the values captured by a lambda.
+fn env_type(lambda: &mir::Body) -> String {
+    let fields = stored_captures(lambda)
+        .iter()
+        .map(|local| lambda.locals[*local].ty.ir_repr().as_ref().to_owned())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{{ {} }}", fields)
+}
+
+/// The captures of a lambda that are kept in its environment, leaving out
+/// the ones of type `()`.
+fn stored_captures(lambda: &mir::Body) -> Vec<mir::Local> {
+    lambda
+        .captures
+        .iter()
+        .cloned()
+        .filter(|local| lambda.locals[*local].ty != Type::Unit)
+        .collect()
+}
+
+/// The symbol for an instantiation of a generic function, e.g. `id[int]`.
+fn mangle(name: &str, args: &[Type]) -> String {
+    let args = args
+        .iter()
+        .map(|ty| ty.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{}[{}]", name, args)
+}
+
+/// Refers to the global `symbol`, quoting it if it isn't a plain identifier.
+fn global(symbol: &str) -> String {
+    let plain = symbol
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if plain {
+        format!("@{}", symbol)
+    } else {
+        format!("@\"{}\"", symbol)
+    }
+}
+
+/// The parameter types of a function type as written in a declaration.
+fn param_list(params: &[Type], variadic: bool) -> String {
+    let mut params = params
+        .iter()
+        .map(|ty| ty.ir_repr().as_ref().to_owned())
+        .collect::<Vec<_>>();
+    if variadic {
+        params.push("...".to_owned());
+    }
+    params.join(", ")
+}
+
 impl Codegen<u32> for common::Literal {
-    fn generate(&self, emitter: &mut Emitter) -> u32 {
+    fn generate(&self, emitter: &mut Emitter) -> Result<u32, Diagnostic> {
         use common::Literal;
         match self {
+            Literal::Bool(b) => {
+                let result = emitter.next_int();
+                emitter.push_line(format!("%i{} = add i1 {}, false", result, b));
+                Ok(result)
+            }
             Literal::Int(n) => {
                 let result = emitter.next_int();
                 // wtf?
                 emitter.push_line(format!("%i{} = add i32 {}, 0", result, n));
-                result
+                Ok(result)
             }
             Literal::String(s) => {
                 let litname = emitter.next_int();
                 let tmp = emitter.next_int();
                 let result = emitter.next_int();
+                // C functions expect the terminating nul
+                let len = s.len() + 1;
                 emitter.push_global_line(format!(
-                    "@ss{} = private unnamed_addr constant [{} x i8] c\"{}\"",
+                    "@ss{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
                     litname,
-                    s.len(),
-                    s
+                    len,
+                    escape_bytes(s)
                 ));
                 emitter.push_line(format!(
                     "%i{} = getelementptr [{} x i8], [{} x i8]* @ss{}",
-                    tmp,
-                    s.len(),
-                    s.len(),
-                    litname
+                    tmp, len, len, litname
                 ));
                 emitter.push_line(format!(
                     "%i{} = bitcast [{} x i8]* %i{} to i8*",
-                    result,
-                    s.len(),
-                    tmp
+                    result, len, tmp
                 ));
-                result
+                Ok(result)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_are_asked_for_once() {
+        let mut emitter = Emitter::new();
+        let symbol = emitter.instance("f", &[Type::Int]);
+        assert_eq!(emitter.instance("f", &[Type::Int]), symbol);
+        assert!(emitter.next_instance().is_some());
+        assert!(emitter.next_instance().is_none());
+    }
+}
@@ -1,12 +1,16 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::{Arc, Mutex};
+
+use span::Span;
 use typeck::Substitution;
 
 lazy_static! {
-    static ref counter: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
+    static ref COUNTER: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
 }
 
 pub fn next_int() -> u32 {
-    let mut c = counter.lock().unwrap();
+    let mut c = COUNTER.lock().unwrap();
     *c += 1;
     *c
 }
@@ -14,20 +18,39 @@ pub fn next_int() -> u32 {
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Type {
     T(u32),
-    Fn(Vec<Type>, Box<Type>), // list of args -> type, maybe will use tuple later
+    Fn(Vec<Type>, Box<Type>, bool), // list of args -> type, maybe will use tuple later; variadic
+    Unit,
     Bool,
     Int,
     String,
+    Struct(String),
+    Enum(String),
+    /// A generic parameter of the function being checked.
+    Param(String),
 }
 
 impl Type {
     pub fn ir_repr(&self) -> impl AsRef<str> {
         match self {
-            Type::T(_) => "i32", // panic!("Should not encounter untyped variables in a typed tree."),
-            Type::Fn(_, _) => panic!("d"),
-            Type::Bool => "i1",
-            Type::Int => "i32",
-            Type::String => "i8*", // lol
+            Type::T(_) => "i32".to_owned(), // panic!("Should not encounter untyped variables in a typed tree."),
+            // a pointer to the code and one to the captured environment
+            Type::Fn(_, _, _) => "{ i8*, i8* }".to_owned(),
+            Type::Unit => "void".to_owned(),
+            Type::Bool => "i1".to_owned(),
+            Type::Int => "i32".to_owned(),
+            Type::String => "i8*".to_owned(), // lol
+            Type::Struct(name) => format!("%st.{}", name),
+            Type::Enum(name) => format!("%en.{}", name),
+            Type::Param(_) => panic!("generic parameters are replaced before codegen"),
+        }
+    }
+
+    /// The representation of a field of this type inside a struct or enum,
+    /// where `void` isn't allowed.
+    pub fn field_repr(&self) -> String {
+        match self {
+            Type::Unit => "{}".to_owned(),
+            _ => self.ir_repr().as_ref().to_owned(),
         }
     }
 
@@ -39,16 +62,22 @@ impl Type {
 
     pub fn sub(&mut self, var: u32, t: &Type) {
         let new_self = match self {
-            Type::Bool | Type::Int | Type::String => None,
+            Type::Unit
+            | Type::Bool
+            | Type::Int
+            | Type::String
+            | Type::Struct(_)
+            | Type::Enum(_)
+            | Type::Param(_) => None,
             Type::T(n) if *n == var => Some(t.clone()),
-            Type::Fn(args, ret) => {
+            Type::Fn(args, ret, variadic) => {
                 let mut args = args.clone();
                 let mut ret = ret.clone();
                 for arg in args.iter_mut() {
                     arg.sub(var, t);
                 }
                 ret.sub(var, t);
-                Some(Type::Fn(args, ret))
+                Some(Type::Fn(args, ret, *variadic))
             }
             _ => None,
         };
@@ -56,14 +85,107 @@ impl Type {
             *self = new_self;
         }
     }
+
+    /// Whether the type variable `var` appears anywhere in this type.
+    pub fn occurs(&self, var: u32) -> bool {
+        match self {
+            Type::T(n) => *n == var,
+            Type::Fn(args, ret, _) => args.iter().any(|arg| arg.occurs(var)) || ret.occurs(var),
+            _ => false,
+        }
+    }
+
+    /// How many function types are nested inside each other in this type.
+    pub fn depth(&self) -> usize {
+        match self {
+            Type::Fn(args, ret, _) => {
+                1 + args.iter().map(Type::depth).chain(Some(ret.depth())).max().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Adds the type variables in this type to `vars`, in the order they appear.
+    pub fn free_vars(&self, vars: &mut Vec<u32>) {
+        match self {
+            Type::T(n) if !vars.contains(n) => vars.push(*n),
+            Type::Fn(args, ret, _) => {
+                for arg in args {
+                    arg.free_vars(vars);
+                }
+                ret.free_vars(vars);
+            }
+            _ => (),
+        }
+    }
+
+    /// Finds what the generic parameters in this type stand for in `ty`.
+    pub fn bind_params(&self, ty: &Type, bound: &mut BTreeMap<String, Type>) {
+        match (self, ty) {
+            (Type::Param(name), ty) => {
+                bound.insert(name.clone(), ty.clone());
+            }
+            (Type::Fn(params1, ret1, _), Type::Fn(params2, ret2, _)) => {
+                for (param1, param2) in params1.iter().zip(params2.iter()) {
+                    param1.bind_params(param2, bound);
+                }
+                ret1.bind_params(ret2, bound);
+            }
+            _ => (),
+        }
+    }
+
+    /// Replaces the generic parameters `params` with the matching `args`.
+    pub fn instantiate(&mut self, params: &[String], args: &[Type]) {
+        match self {
+            Type::Param(name) => {
+                if let Some(i) = params.iter().position(|param| param == name) {
+                    *self = args[i].clone();
+                }
+            }
+            Type::Fn(args_t, ret, _) => {
+                for arg in args_t.iter_mut() {
+                    arg.instantiate(params, args);
+                }
+                ret.instantiate(params, args);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::T(n) => write!(f, "'t{}", n),
+            Type::Fn(args, ret, variadic) => {
+                write!(f, "(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                if *variadic {
+                    write!(f, "{}...", if args.is_empty() { "" } else { ", " })?;
+                }
+                write!(f, ") -> {}", ret)
+            }
+            Type::Unit => write!(f, "()"),
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::String => write!(f, "string"),
+            Type::Struct(name) | Type::Enum(name) | Type::Param(name) => write!(f, "{}", name),
+        }
+    }
 }
 
 pub trait Typed {
     fn get_type(&self) -> Type;
 }
 
-#[derive(Debug)]
-pub struct Field(pub String, pub Type);
+#[derive(Clone, Debug)]
+pub struct Field(pub String, pub Type, pub Span);
 
 impl Typed for Field {
     fn get_type(&self) -> Type {
@@ -71,17 +193,115 @@ impl Typed for Field {
     }
 }
 
-#[derive(Debug)]
-pub struct Arg(pub String, pub Type);
+/// An enum variant and the types of its payload.
+#[derive(Clone, Debug)]
+pub struct Variant(pub String, pub Vec<Type>, pub Span);
+
+/// A generic parameter and the classes it has to have instances of.
+#[derive(Clone, Debug)]
+pub struct Generic(pub String, pub Vec<String>);
+
+impl Generic {
+    pub fn names(generics: &[Generic]) -> Vec<String> {
+        generics.iter().map(|generic| generic.0.clone()).collect()
+    }
+}
+
+impl fmt::Display for Generic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        if !self.1.is_empty() {
+            write!(f, ": {}", self.1.join(" + "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The signature of a method declared by a class.
+#[derive(Clone, Debug)]
+pub struct Method(pub String, pub Vec<Arg>, pub Type, pub Span);
 
-impl Typed for Arg {
+impl Typed for Method {
+    fn get_type(&self) -> Type {
+        Type::Fn(
+            self.1.iter().map(|arg| arg.get_type()).collect(),
+            Box::new(self.2.clone()),
+            false,
+        )
+    }
+}
+
+/// A pattern binding names of type `N`, which are strings until the names are
+/// resolved.
+#[derive(Clone, Debug)]
+pub struct Pattern<N = String> {
+    pub kind: PatternKind<N>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum PatternKind<N = String> {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// A variant and the names its payload is bound to, where `_` binds nothing.
+    Variant(String, Vec<N>),
+}
+
+impl<N> Pattern<N> {
+    pub fn new(kind: PatternKind<N>, span: Span) -> Self {
+        Pattern { kind, span }
+    }
+}
+
+/// An argument, named by a string until the names are resolved.
+#[derive(Clone, Debug)]
+pub struct Arg<N = String>(pub N, pub Type, pub Span);
+
+impl<N> Typed for Arg<N> {
     fn get_type(&self) -> Type {
         self.1.clone()
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    /// Whether the operator compares its operands, producing a `bool`.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Debug)]
 pub enum Literal {
+    Bool(bool),
     Int(u32),
     String(String),
 }
@@ -89,6 +309,7 @@ pub enum Literal {
 impl Typed for Literal {
     fn get_type(&self) -> Type {
         match self {
+            Literal::Bool(_) => Type::Bool,
             Literal::Int(_) => Type::Int,
             Literal::String(_) => Type::String,
         }
@@ -0,0 +1,202 @@
+use std::collections::BTreeSet;
+
+use common::Type;
+use diagnostic::Diagnostic;
+use mir::{Block, Body, Function, Local, Place, Program, TerminatorKind};
+use span::Span;
+
+/// Runs the dataflow passes over every function, returning what they found.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for function in program.functions.iter() {
+        check_body(&function.body, &mut diagnostics);
+        missing_return(function, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn check_body(body: &Body, diagnostics: &mut Vec<Diagnostic>) {
+    let reachable = forward(body, (), |_, _| (), |_, _| ());
+    unreachable_code(body, &reachable, diagnostics);
+    uninitialized(body, diagnostics);
+    for lambda in body.lambdas.iter() {
+        check_body(lambda, diagnostics);
+    }
+}
+
+/// Solves a forward dataflow problem, where `entry` holds at the start of the
+/// body, `transfer` applies the effect of a block and `join` merges the facts
+/// of paths that meet. Returns the fact at the start of each block, which is
+/// `None` for the blocks that can't be reached.
+fn forward<T: Clone + PartialEq>(
+    body: &Body,
+    entry: T,
+    transfer: impl Fn(&Block, &mut T),
+    join: impl Fn(&mut T, &T),
+) -> Vec<Option<T>> {
+    let mut facts = vec![None; body.blocks.len()];
+    facts[0] = Some(entry);
+    let mut worklist = vec![0];
+    while let Some(block) = worklist.pop() {
+        let mut fact = match &facts[block] {
+            Some(fact) => fact.clone(),
+            None => continue,
+        };
+        transfer(&body.blocks[block], &mut fact);
+        for succ in body.blocks[block].terminator.kind.successors() {
+            let changed = match &mut facts[succ] {
+                Some(old) => {
+                    let before = old.clone();
+                    join(old, &fact);
+                    *old != before
+                }
+                slot => {
+                    *slot = Some(fact.clone());
+                    true
+                }
+            };
+            if changed && !worklist.contains(&succ) {
+                worklist.push(succ);
+            }
+        }
+    }
+    facts
+}
+
+/// Warns about code that can't run, once for every stretch of it.
+fn unreachable_code(body: &Body, reachable: &[Option<()>], diagnostics: &mut Vec<Diagnostic>) {
+    let mut preds = vec![0; body.blocks.len()];
+    for block in body.blocks.iter() {
+        for succ in block.terminator.kind.successors() {
+            preds[succ] += 1;
+        }
+    }
+
+    // start from the blocks nothing jumps to, like the one after a `return`,
+    // and report the first thing written in the dead blocks they lead to
+    let mut visited = vec![false; body.blocks.len()];
+    for root in 0..body.blocks.len() {
+        if root == 0 || preds[root] > 0 || visited[root] {
+            continue;
+        }
+        let mut first: Option<Span> = None;
+        let mut stack = vec![root];
+        while let Some(block) = stack.pop() {
+            if visited[block] || reachable[block].is_some() {
+                continue;
+            }
+            visited[block] = true;
+            let block = &body.blocks[block];
+            let mut spans = block
+                .statements
+                .iter()
+                .map(|statement| statement.span)
+                .collect::<Vec<_>>();
+            // jumps are only written as part of other statements
+            match &block.terminator.kind {
+                TerminatorKind::Goto(_) | TerminatorKind::Unreachable => (),
+                _ if block.terminator.span == Span::default() => (),
+                _ => spans.push(block.terminator.span),
+            }
+            first = spans.into_iter().chain(first).min_by_key(|span| span.start);
+            stack.extend(block.terminator.kind.successors());
+        }
+        if let Some(span) = first {
+            diagnostics.push(
+                Diagnostic::warning(span, "unreachable statement")
+                    .with_label("this is never run")
+                    .with_note("every path to it returns or loops forever first"),
+            );
+        }
+    }
+}
+
+/// Reports variables that might be read before they are assigned, once for
+/// each variable.
+fn uninitialized(body: &Body, diagnostics: &mut Vec<Diagnostic>) {
+    // the variables that might not have been assigned yet
+    let entry = (0..body.locals.len())
+        .filter(|local| {
+            body.locals[*local].var.is_some()
+                && !body.args.contains(local)
+                && !body.captures.contains(local)
+        }).collect::<BTreeSet<Local>>();
+    let facts = forward(
+        body,
+        entry,
+        |block, uninit| {
+            for statement in block.statements.iter() {
+                assign(&statement.place, uninit);
+            }
+        },
+        |old, new| old.extend(new.iter().cloned()),
+    );
+
+    let mut reported = BTreeSet::new();
+    for (block, fact) in body.blocks.iter().zip(facts) {
+        let mut uninit = match fact {
+            Some(uninit) => uninit,
+            None => continue,
+        };
+        for statement in block.statements.iter() {
+            for read in statement.value.reads() {
+                check_read(body, read, &uninit, &mut reported, diagnostics);
+            }
+            assign(&statement.place, &mut uninit);
+        }
+        if let Some(read) = block.terminator.kind.reads() {
+            check_read(body, read, &uninit, &mut reported, diagnostics);
+        }
+    }
+}
+
+/// Assigning a whole local initializes it; assigning part of one doesn't.
+fn assign(place: &Place, uninit: &mut BTreeSet<Local>) {
+    if place.projection.is_empty() {
+        uninit.remove(&place.local);
+    }
+}
+
+fn check_read(
+    body: &Body,
+    (place, span): (&Place, Span),
+    uninit: &BTreeSet<Local>,
+    reported: &mut BTreeSet<Local>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if !uninit.contains(&place.local) || !reported.insert(place.local) {
+        return;
+    }
+    if let Some(var) = &body.locals[place.local].var {
+        diagnostics.push(
+            Diagnostic::error(span, format!("use of possibly uninitialized variable `{}`", var))
+                .with_label(format!("`{}` might not have been assigned here", var)),
+        );
+    }
+}
+
+/// Reports functions returning a value whose body can run to the end without
+/// a `return`.
+fn missing_return(function: &Function, diagnostics: &mut Vec<Diagnostic>) {
+    let body = &function.body;
+    if body.ret == Type::Unit {
+        return;
+    }
+    let reachable = forward(body, (), |_, _| (), |_, _| ());
+    let falls_off = body
+        .blocks
+        .iter()
+        .zip(reachable)
+        .any(|(block, reachable)| {
+            reachable.is_some() && matches!(block.terminator.kind, TerminatorKind::Unreachable)
+        });
+    if falls_off {
+        diagnostics.push(
+            Diagnostic::error(
+                function.span,
+                format!("function `{}` does not return a value on every path", function.name),
+            ).with_label(format!("expected to return `{}`", body.ret))
+            .with_note("the end of the body can be reached without a `return`"),
+        );
+    }
+}
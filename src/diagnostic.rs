@@ -0,0 +1,229 @@
+use std::fmt;
+
+use span::{SourceFile, SourceMap, Span};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoteKind {
+    Note,
+    Help,
+}
+
+impl fmt::Display for NoteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NoteKind::Note => write!(f, "note"),
+            NoteKind::Help => write!(f, "help"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Boxed to keep `Result<_, Diagnostic>` small.
+    pub message: Box<str>,
+    /// Where the problem is, or `None` if it isn't about any source code.
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<(NoteKind, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            message: message.into().into_boxed_str(),
+            primary: Some(Label {
+                span,
+                message: String::new(),
+            }),
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Warning, span, message)
+    }
+
+    /// An error that doesn't point at any source, like a tool failing.
+    pub fn unspanned(message: impl Into<String>) -> Self {
+        Diagnostic {
+            primary: None,
+            ..Diagnostic::error(Span::default(), message)
+        }
+    }
+
+    /// The primary span, or the default one for unspanned diagnostics.
+    pub fn span(&self) -> Span {
+        self.primary
+            .as_ref()
+            .map(|label| label.span)
+            .unwrap_or_default()
+    }
+
+    /// Sets the text printed under the primary span.
+    pub fn with_label(mut self, message: impl Into<String>) -> Self {
+        if let Some(primary) = &mut self.primary {
+            primary.message = message.into();
+        }
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push((NoteKind::Note, note.into()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.notes.push((NoteKind::Help, help.into()));
+        self
+    }
+
+    /// Renders the diagnostic as a source snippet in the style of rustc:
+    ///
+    /// ```text
+    /// error: mismatched types
+    ///  --> test/1.g:3:9
+    ///   |
+    /// 3 |     x = "hi"
+    ///   |         ^^^^ expected `int`, found `string`
+    ///   |
+    ///   = note: ...
+    /// ```
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+
+        // group the labels by file, keeping the primary file first
+        let mut groups: Vec<(Span, Vec<(&Label, bool)>)> = Vec::new();
+        let labels = self
+            .primary
+            .iter()
+            .map(|label| (label, true))
+            .chain(self.secondary.iter().map(|label| (label, false)));
+        for (label, primary) in labels {
+            match groups
+                .iter_mut()
+                .find(|(span, _)| span.file == label.span.file)
+            {
+                Some((_, group)) => group.push((label, primary)),
+                None => groups.push((label.span, vec![(label, primary)])),
+            }
+        }
+
+        let width = groups
+            .iter()
+            .flat_map(|(_, group)| group.iter())
+            .map(|(label, _)| {
+                let file = sources.get(label.span.file);
+                (file.line_index(label.span.start) + 1).to_string().len()
+            }).max()
+            .unwrap_or(1);
+        let pad = " ".repeat(width);
+
+        for (i, (span, group)) in groups.iter().enumerate() {
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            out += &format!("{}{} {}\n", pad, arrow, sources.position(*span));
+            out += &format!("{} |\n", pad);
+            render_snippet(&mut out, sources.get(span.file), group, width);
+        }
+
+        if !self.notes.is_empty() {
+            out += &format!("{} |\n", pad);
+        }
+        for (kind, note) in self.notes.iter() {
+            out += &format!("{} = {}: {}\n", pad, kind, note);
+        }
+        out
+    }
+}
+
+fn render_snippet(out: &mut String, file: &SourceFile, labels: &[(&Label, bool)], width: usize) {
+    let pad = " ".repeat(width);
+    let mut lines = labels
+        .iter()
+        .map(|(label, _)| file.line_index(label.span.start))
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+
+    let mut previous: Option<usize> = None;
+    for line in lines {
+        if let Some(previous) = previous {
+            if line > previous + 1 {
+                *out += "...\n";
+            }
+        }
+        previous = Some(line);
+
+        let text = file.line(line);
+        *out += &format!("{:>width$} | {}\n", line + 1, expand_tabs(text), width = width);
+
+        let start_of_line = file.line_start(line);
+        for (label, primary) in labels {
+            if file.line_index(label.span.start) != line {
+                continue;
+            }
+            // spans running past the end of the line are cut off there
+            let start = label.span.start - start_of_line;
+            let end = label.span.end.max(label.span.start) - start_of_line;
+            let end = end.min(text.len()).max(start);
+            let offset = expand_tabs(&text[..start.min(text.len())]).chars().count();
+            let length = expand_tabs(&text[start.min(text.len())..end])
+                .chars()
+                .count()
+                .max(1);
+            let marker = if *primary { "^" } else { "-" };
+            let mut underline = format!("{} | {}{}", pad, " ".repeat(offset), marker.repeat(length));
+            if !label.message.is_empty() {
+                underline += " ";
+                underline += &label.message;
+            }
+            *out += &underline;
+            *out += "\n";
+        }
+    }
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', "    ")
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+use std::thread;
+
+use ast;
+use codegen::{Codegen, Emitter};
+use dataflow;
+use diagnostic::{Diagnostic, Severity};
+use hir::{self, IntoHir};
+use interp::Interpreter;
+use lexer::Lexer;
+use mir;
+use module::{self, SearchPath};
+use span::SourceMap;
+use typeck::TypeStack;
+
+/// The stack size of the thread running the interpreter.
+const INTERP_STACK_SIZE: usize = 1 << 30;
+/// How many names to try for a temporary directory before giving up.
+const TEMP_DIR_ATTEMPTS: usize = 16;
+
+/// How far to take a program, and what to write out at the end.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Emit {
+    Ast,
+    Mir,
+    LlvmIr,
+    Asm,
+    Obj,
+    Exe,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "ast" => Ok(Emit::Ast),
+            "mir" => Ok(Emit::Mir),
+            "llvm-ir" => Ok(Emit::LlvmIr),
+            "asm" => Ok(Emit::Asm),
+            "obj" => Ok(Emit::Obj),
+            "exe" => Ok(Emit::Exe),
+            _ => Err(format!(
+                "unknown output `{}`, expected one of ast, mir, llvm-ir, asm, obj or exe",
+                s
+            )),
+        }
+    }
+}
+
+impl Emit {
+    /// The output file for a program named `stem` when there is no `-o`, or
+    /// `None` for the outputs printed to stdout.
+    pub fn default_output(self, stem: &str) -> Option<PathBuf> {
+        let extension = match self {
+            Emit::Ast | Emit::Mir => return None,
+            Emit::LlvmIr => "ll",
+            Emit::Asm => "s",
+            Emit::Obj => "o",
+            Emit::Exe => return Some(PathBuf::from(stem)),
+        };
+        Some(PathBuf::from(format!("{}.{}", stem, extension)))
+    }
+}
+
+/// The source of the program being compiled.
+pub struct Input {
+    pub name: String,
+    pub contents: String,
+    pub search: SearchPath,
+}
+
+/// Runs the program through the front end, as far as `emit` asks for, and
+/// returns it as text: the syntax tree, the MIR or the LLVM IR. Warnings are
+/// printed on the way.
+pub fn compile(
+    sources: &mut SourceMap,
+    input: &Input,
+    emit: Emit,
+    print_types: bool,
+) -> Result<String, Vec<Diagnostic>> {
+    let ast = module::load(sources, input.name.clone(), &input.contents, &input.search)?;
+    if emit == Emit::Ast {
+        return Ok(format!("{:#?}\n", ast));
+    }
+
+    let hir = typecheck(ast)?;
+    if print_types {
+        let mut out = String::new();
+        for decl in hir.0.iter() {
+            if let Some(signature) = decl.signature() {
+                out += &signature;
+                out += "\n";
+            }
+        }
+        return Ok(out);
+    }
+
+    let program = lower(sources, hir)?;
+    if emit == Emit::Mir {
+        return Ok(program.to_string());
+    }
+
+    let mut emitter = Emitter::new();
+    program.generate(&mut emitter).map_err(|err| vec![err])?;
+    Ok(emitter.as_string() + "\n")
+}
+
+/// Lists the tokens of the program's own file, one per line with its span,
+/// along with the errors the lexer found in between.
+pub fn dump_tokens(sources: &mut SourceMap, input: &Input) -> String {
+    let file = sources.add(input.name.clone(), &input.contents);
+    let source = sources.get(file);
+    let mut out = String::new();
+    for token in Lexer::new(&input.contents) {
+        let (start, end, token) = match token {
+            Ok((start, token, end)) => (start, end, format!("{:?}", token)),
+            Err(err) => (err.start, err.end, format!("error: {}", err.message)),
+        };
+        let (start_line, start_column) = source.location(start);
+        let (end_line, end_column) = source.location(end);
+        let span = format!("{}:{}-{}:{}", start_line, start_column, end_line, end_column);
+        out += &format!("{:<13} {}\n", span, token);
+    }
+    out
+}
+
+/// Runs the program with the interpreter instead of compiling it, returning
+/// its exit code. It goes through the same checks as a compiled program,
+/// including the ones on its MIR, so that what runs here also compiles.
+pub fn interpret(sources: &mut SourceMap, input: &Input) -> Result<i32, Vec<Diagnostic>> {
+    let ast = module::load(sources, input.name.clone(), &input.contents, &input.search)?;
+    let hir = typecheck(ast)?;
+    lower(sources, hir.clone())?;
+    with_stack(move || Interpreter::new(&hir).run_main())
+        .and_then(|result| result)
+        .map_err(|err| vec![err])
+}
+
+/// Runs `f` on a thread of its own with a large stack, since the interpreter
+/// recurses along with the program it runs.
+pub fn with_stack<T, F>(f: F) -> Result<T, Diagnostic>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let handle = thread::Builder::new()
+        .stack_size(INTERP_STACK_SIZE)
+        .spawn(f)
+        .map_err(|err| {
+            Diagnostic::unspanned(format!("could not start the interpreter: {}", err))
+        })?;
+    Ok(handle.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+}
+
+/// Resolves the names in the syntax tree and infers its types, and checks
+/// that there is a `main` to run and that its generic functions can be
+/// instantiated.
+pub fn typecheck(ast: ast::Program) -> Result<hir::Program, Vec<Diagnostic>> {
+    let mut context = hir::Context::default();
+    let mut hir = ast.into_hir(&mut context);
+    if !context.errors().is_empty() {
+        return Err(context.errors().to_vec());
+    }
+    let mut stack = TypeStack::default();
+    hir.typeck(&mut stack)?;
+    hir.check_main().map_err(|err| vec![err])?;
+    hir.check_instances().map_err(|err| vec![err])?;
+    Ok(hir)
+}
+
+/// Lowers the typed program to MIR and runs the dataflow passes over it,
+/// printing their warnings.
+pub fn lower(sources: &SourceMap, hir: hir::Program) -> Result<mir::Program, Vec<Diagnostic>> {
+    let program = mir::lower(hir);
+    let (errors, warnings): (Vec<_>, Vec<_>) = dataflow::check(&program)
+        .into_iter()
+        .partition(|diagnostic| diagnostic.severity == Severity::Error);
+    for warning in warnings {
+        eprintln!("{}", warning.render(sources));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(program)
+}
+
+/// A directory for the files passed between tools, which is removed along
+/// with everything in it when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates a directory with a random name that only this user can use.
+    /// It must not exist already, so that nobody else can have made it, or
+    /// put a link there, beforehand.
+    pub fn new() -> Result<Self, Diagnostic> {
+        let mut attempts = 0;
+        loop {
+            let name = format!("gala-{}-{:016x}", process::id(), random());
+            let path = env::temp_dir().join(name);
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::DirBuilderExt;
+                builder.mode(0o700);
+            }
+            match builder.create(&path) {
+                Ok(()) => return Ok(TempDir(path)),
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::AlreadyExists
+                        && attempts < TEMP_DIR_ATTEMPTS =>
+                {
+                    attempts += 1
+                }
+                Err(err) => {
+                    return Err(Diagnostic::unspanned(format!(
+                        "could not create temporary directory `{}`: {}",
+                        path.display(),
+                        err
+                    )))
+                }
+            }
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A random number, from the random keys the standard library uses for hash
+/// maps.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Optimizes the IR with `opt`, unless `opt_level` is 0.
+pub fn optimize(ir: String, opt_level: u8, temp: &TempDir) -> Result<String, Diagnostic> {
+    if opt_level == 0 {
+        return Ok(ir);
+    }
+    let input = temp.path("input.ll");
+    let output = temp.path("optimized.ll");
+    write(&input, &ir)?;
+    run(Command::new("opt")
+        .arg("-S")
+        .arg(format!("-O{}", opt_level))
+        .arg("-o")
+        .arg(&output)
+        .arg(&input))?;
+    fs::read_to_string(&output).map_err(|err| {
+        Diagnostic::unspanned(format!("could not read `{}`: {}", output.display(), err))
+    })
+}
+
+/// Turns the IR into assembly, an object file or an executable at `output`
+/// with `llc` and the system's C compiler.
+pub fn backend(
+    ir: &str,
+    emit: Emit,
+    output: &Path,
+    opt_level: u8,
+    temp: &TempDir,
+) -> Result<(), Diagnostic> {
+    let input = temp.path("output.ll");
+    write(&input, ir)?;
+    let object = match emit {
+        Emit::Exe => temp.path("output.o"),
+        _ => output.to_owned(),
+    };
+    run(Command::new("llc")
+        .arg(format!("-O{}", opt_level))
+        .arg("-relocation-model=pic")
+        .arg(if emit == Emit::Asm { "-filetype=asm" } else { "-filetype=obj" })
+        .arg("-o")
+        .arg(&object)
+        .arg(&input))?;
+    if emit == Emit::Exe {
+        run(Command::new("cc").arg("-o").arg(output).arg(&object))?;
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path`, or to stdout if the path is `-`.
+pub fn write(path: &Path, contents: &str) -> Result<(), Diagnostic> {
+    let result = if path == Path::new("-") {
+        print!("{}", contents);
+        Ok(())
+    } else {
+        fs::write(path, contents)
+    };
+    result.map_err(|err| {
+        Diagnostic::unspanned(format!("could not write `{}`: {}", path.display(), err))
+    })
+}
+
+/// Runs one of the external tools, turning its failures into diagnostics.
+fn run(command: &mut Command) -> Result<(), Diagnostic> {
+    let name = format!("`{}`", command.get_program().to_string_lossy());
+    let output = command.output().map_err(|err| {
+        let diagnostic = Diagnostic::unspanned(format!("could not run {}: {}", name, err));
+        if err.kind() == io::ErrorKind::NotFound {
+            diagnostic.with_help(
+                "compiling to native code needs LLVM's `opt` and `llc` and a C compiler on the \
+                 PATH; `--emit=llvm-ir` works without them",
+            )
+        } else {
+            diagnostic
+        }
+    })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut diagnostic =
+        Diagnostic::unspanned(format!("{} failed with {}", name, output.status));
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        if !line.trim().is_empty() {
+            diagnostic = diagnostic.with_note(line);
+        }
+    }
+    Err(diagnostic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_dirs_are_distinct_and_removed() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        assert_ne!(first.0, second.0);
+        assert!(first.0.is_dir());
+        let path = first.0.clone();
+        drop(first);
+        assert!(!path.exists());
+    }
+}
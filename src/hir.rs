@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use common::{
+    next_int, Arg, BinOp, Field, Generic, Literal, Method, Pattern, Type, Typed, UnOp, Variant,
+};
+use diagnostic::Diagnostic;
+use resolve::Resolver;
+use span::Span;
+
+#[derive(Clone, Default)]
+pub struct Context {
+    enums: BTreeSet<String>,
+    variants: BTreeMap<String, String>,
+    /// The generic parameters of the function being converted.
+    pub type_params: Vec<String>,
+    pub resolver: Resolver,
+}
+
+impl Context {
+    pub fn next(&mut self) -> Type {
+        Type::T(next_int())
+    }
+
+    pub fn declare_enum(&mut self, name: impl AsRef<str>, variants: &[Variant]) {
+        let name = name.as_ref().to_owned();
+        for variant in variants {
+            self.variants
+                .entry(variant.0.clone())
+                .or_insert_with(|| name.clone());
+        }
+        self.enums.insert(name);
+    }
+
+    /// The enum that a variant name belongs to.
+    pub fn lookup_variant(&self, name: impl AsRef<str>) -> Option<String> {
+        self.variants.get(name.as_ref()).cloned()
+    }
+
+    /// The names that couldn't be resolved while converting.
+    pub fn errors(&self) -> &[Diagnostic] {
+        self.resolver.errors()
+    }
+
+    /// The parser reads every named type as a struct; this points the ones
+    /// naming enums at the enum instead.
+    pub fn resolve_type(&self, ty: Type) -> Type {
+        match ty {
+            Type::Struct(ref name) if self.type_params.contains(name) => Type::Param(name.clone()),
+            Type::Struct(ref name) if self.enums.contains(name) => Type::Enum(name.clone()),
+            Type::Fn(args, ret, variadic) => Type::Fn(
+                args.into_iter()
+                    .map(|arg| self.resolve_type(arg))
+                    .collect::<Vec<_>>(),
+                Box::new(self.resolve_type(*ret)),
+                variadic,
+            ),
+            ty => ty,
+        }
+    }
+}
+
+pub trait IntoHir<T> {
+    fn into_hir(self, ctx: &mut Context) -> T;
+}
+
+/// A variable, numbered by the resolver so that every binding is distinct
+/// even when names are reused.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Var(pub u32, pub String);
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.1)
+    }
+}
+
+/// What a name used in an expression refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Name {
+    Local(Var),
+    /// A function, an extern or a class method.
+    Global(String),
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Name::Local(var) => write!(f, "{}", var),
+            Name::Global(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A program with its names resolved, shaped like the syntax tree. Typeck
+/// fills in its types and the interpreter runs it, then `mir::lower` turns
+/// the functions into control flow graphs.
+#[derive(Clone, Debug)]
+pub struct Program(pub Vec<TopDecl>);
+
+#[derive(Clone, Debug)]
+pub struct TopDecl {
+    pub kind: TopDeclKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum TopDeclKind {
+    Extern(String, Type),
+    /// The name, generic parameters, arguments, return type and body.
+    Fn(String, Vec<Generic>, Vec<Arg<Var>>, Type, Vec<Stmt>),
+    Struct(String, Vec<Field>),
+    Enum(String, Vec<Variant>),
+    /// The name of a class, its type parameter and its methods.
+    Class(String, String, Vec<Method>),
+    /// The class, the type it is implemented for and the names of the methods,
+    /// which follow as functions named by `method_symbol`.
+    Instance(String, Type, Vec<String>),
+}
+
+/// The name of the function implementing `method` for an instance.
+pub fn method_symbol(class: &str, ty: &Type, method: &str) -> String {
+    format!("{}[{}].{}", class, ty, method)
+}
+
+impl TopDecl {
+    pub fn new(kind: TopDeclKind, span: Span) -> Self {
+        TopDecl { kind, span }
+    }
+    pub fn name(&self) -> &str {
+        match &self.kind {
+            TopDeclKind::Extern(name, _)
+            | TopDeclKind::Fn(name, _, _, _, _)
+            | TopDeclKind::Struct(name, _)
+            | TopDeclKind::Enum(name, _)
+            | TopDeclKind::Class(name, _, _)
+            | TopDeclKind::Instance(name, _, _) => name,
+        }
+    }
+
+    /// How a function's signature would be written out with all of its types,
+    /// e.g. `fn id['a](x: 'a): 'a`.
+    pub fn signature(&self) -> Option<String> {
+        match &self.kind {
+            TopDeclKind::Fn(name, generics, args, ty, _) => {
+                let generics = if generics.is_empty() {
+                    String::new()
+                } else {
+                    let generics = generics
+                        .iter()
+                        .map(|generic| generic.to_string())
+                        .collect::<Vec<_>>();
+                    format!("[{}]", generics.join(", "))
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| format!("{}: {}", arg.0, arg.1))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Some(format!("fn {}{}({}): {}", name, generics, args, ty))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum StmtKind {
+    /// Whether the variable was already declared, the variable and its value.
+    Assign(bool, Var, Expr),
+    Declare(Var, Type),
+    SetField(Expr, String, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    While(Expr, Vec<Stmt>),
+    Match(Expr, Vec<Arm>),
+    Return(Option<Expr>),
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Stmt { kind, span }
+    }
+
+    /// Calls `f` on every expression in the statement, innermost first.
+    pub fn map_exprs(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        match &mut self.kind {
+            StmtKind::Assign(_, _, expr) | StmtKind::Expr(expr) => expr.map_exprs(f),
+            StmtKind::SetField(object, _, expr) => {
+                object.map_exprs(f);
+                expr.map_exprs(f);
+            }
+            StmtKind::If(cond, body1, body2) => {
+                cond.map_exprs(f);
+                for stmt in body1.iter_mut().chain(body2.iter_mut().flatten()) {
+                    stmt.map_exprs(f);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                cond.map_exprs(f);
+                for stmt in body.iter_mut() {
+                    stmt.map_exprs(f);
+                }
+            }
+            StmtKind::Match(expr, arms) => {
+                expr.map_exprs(f);
+                for stmt in arms.iter_mut().flat_map(|arm| arm.body.iter_mut()) {
+                    stmt.map_exprs(f);
+                }
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    expr.map_exprs(f);
+                }
+            }
+            StmtKind::Declare(_, _) => (),
+        }
+    }
+
+    /// Calls `f` on every type stored in the statement.
+    pub fn map_types(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        self.map_exprs(&mut |expr| {
+            match &mut expr.kind {
+                ExprKind::Call(_, type_args, _) => {
+                    for ty in type_args.iter_mut() {
+                        f(ty);
+                    }
+                }
+                ExprKind::Lambda(args, _) => {
+                    for arg in args.iter_mut() {
+                        f(&mut arg.1);
+                    }
+                }
+                _ => (),
+            }
+            f(&mut expr.ty);
+        });
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Arm {
+    /// The pattern, with `None` for the payloads bound to `_`.
+    pub pattern: Pattern<Option<Var>>,
+    pub body: Vec<Stmt>,
+}
+
+impl Arm {
+    pub fn new(pattern: Pattern<Option<Var>>, body: Vec<Stmt>) -> Self {
+        Arm { pattern, body }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Type,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+    /// The function, the types its generic parameters are instantiated with
+    /// (filled in by typeck) and the arguments.
+    Call(Name, Vec<Type>, Vec<Expr>),
+    Apply(Box<Expr>, Vec<Expr>),
+    Lambda(Vec<Arg<Var>>, Box<Expr>),
+    Literal(Literal),
+    Name(Name),
+    Field(Box<Expr>, String),
+    Struct(String, Vec<(String, Expr)>),
+    /// An enum, one of its variants and the payload.
+    Variant(String, String, Vec<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, ty: Type, span: Span) -> Self {
+        Expr { kind, ty, span }
+    }
+
+    /// Calls `f` on this expression and everything inside it, innermost
+    /// first.
+    pub fn map_exprs(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        match &mut self.kind {
+            ExprKind::Literal(_) | ExprKind::Name(_) => (),
+            ExprKind::Call(_, _, args) | ExprKind::Variant(_, _, args) => {
+                for arg in args.iter_mut() {
+                    arg.map_exprs(f);
+                }
+            }
+            ExprKind::Apply(func, args) => {
+                func.map_exprs(f);
+                for arg in args.iter_mut() {
+                    arg.map_exprs(f);
+                }
+            }
+            ExprKind::Lambda(_, body) => body.map_exprs(f),
+            ExprKind::Struct(_, fields) => {
+                for (_, expr) in fields.iter_mut() {
+                    expr.map_exprs(f);
+                }
+            }
+            ExprKind::Field(expr, _) | ExprKind::Unary(_, expr) => expr.map_exprs(f),
+            ExprKind::And(left, right)
+            | ExprKind::Or(left, right)
+            | ExprKind::Binary(_, left, right) => {
+                left.map_exprs(f);
+                right.map_exprs(f);
+            }
+        }
+        f(self);
+    }
+}
+
+impl Typed for Expr {
+    fn get_type(&self) -> Type {
+        self.ty.clone()
+    }
+}
@@ -0,0 +1,655 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use common::{Arg, BinOp, Generic, Literal, PatternKind, Type, Typed, UnOp};
+use diagnostic::Diagnostic;
+use hir::{self, ExprKind, Name, StmtKind, TopDeclKind, Var};
+use span::Span;
+
+/// The externs the interpreter provides itself.
+const BUILTINS: &[&str] = &["exit", "printf", "putchar", "puts"];
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Int(i32),
+    String(Rc<str>),
+    /// A struct with its fields in the order they were declared.
+    Struct(String, Vec<(String, Value)>),
+    /// An enum's variant and its payload.
+    Variant(String, Vec<Value>),
+    Function(Rc<Callable>),
+}
+
+#[derive(Debug)]
+pub enum Callable {
+    /// A function or extern, with the types its generic parameters stand for.
+    Global(String, Vec<Type>),
+    /// A lambda, with the variables it captured and the types of the generic
+    /// parameters where it was made.
+    Lambda(Vec<Arg<Var>>, hir::Expr, Frame),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Unit => write!(f, "()"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Struct(name, fields) => {
+                write!(f, "{} {{", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i > 0 { "," } else { "" }, field, value)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Variant(name, payload) if payload.is_empty() => write!(f, "{}", name),
+            Value::Variant(name, payload) => {
+                write!(f, "{}(", name)?;
+                for (i, value) in payload.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { ", " } else { "" }, value)?;
+                }
+                write!(f, ")")
+            }
+            Value::Function(callable) => match &**callable {
+                Callable::Global(name, _) => write!(f, "<fn {}>", name),
+                Callable::Lambda(_, _, _) => write!(f, "<lambda>"),
+            },
+        }
+    }
+}
+
+/// Why evaluation stopped early.
+#[derive(Debug)]
+pub enum Stop {
+    /// The program called `exit`.
+    Exit(i32),
+    Error(Diagnostic),
+}
+
+impl From<Diagnostic> for Stop {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Stop::Error(diagnostic)
+    }
+}
+
+/// What running a statement did to the control flow.
+enum Flow {
+    Next,
+    Return(Value),
+}
+
+/// The variables of a running function, and what its generic parameters
+/// stand for.
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    pub locals: BTreeMap<u32, Value>,
+    params: Vec<String>,
+    args: Vec<Type>,
+}
+
+impl Frame {
+    /// Replaces the generic parameters in `ty` with their types in this frame.
+    fn resolve(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        ty.instantiate(&self.params, &self.args);
+        ty
+    }
+
+    fn lookup(&self, var: &Var, span: Span) -> Result<&Value, Stop> {
+        self.locals.get(&var.0).ok_or_else(|| {
+            Stop::Error(Diagnostic::error(
+                span,
+                format!("variable `{}` is used before it is assigned", var),
+            ))
+        })
+    }
+}
+
+struct Function {
+    generics: Vec<Generic>,
+    args: Vec<Arg<Var>>,
+    ret: Type,
+    body: Vec<hir::Stmt>,
+}
+
+/// Runs a typechecked program by walking its tree.
+#[derive(Default)]
+pub struct Interpreter {
+    functions: BTreeMap<String, Rc<Function>>,
+    externs: BTreeMap<String, Type>,
+    structs: BTreeMap<String, Vec<String>>,
+    /// Which class declares each method.
+    methods: BTreeMap<String, String>,
+}
+
+impl Interpreter {
+    pub fn new(program: &hir::Program) -> Self {
+        let mut interpreter = Interpreter::default();
+        interpreter.methods.insert("add".to_owned(), "Add".to_owned());
+        interpreter.methods.insert("eq".to_owned(), "Eq".to_owned());
+        for decl in program.0.iter() {
+            interpreter.declare(decl);
+        }
+        interpreter
+    }
+
+    pub fn declare(&mut self, decl: &hir::TopDecl) {
+        match &decl.kind {
+            TopDeclKind::Fn(name, generics, args, ret, body) => {
+                let function = Function {
+                    generics: generics.clone(),
+                    args: args.clone(),
+                    ret: ret.clone(),
+                    body: body.clone(),
+                };
+                self.functions.insert(name.clone(), Rc::new(function));
+            }
+            TopDeclKind::Extern(name, ty) => {
+                self.externs.insert(name.clone(), ty.clone());
+            }
+            TopDeclKind::Struct(name, fields) => {
+                let fields = fields.iter().map(|field| field.0.clone()).collect();
+                self.structs.insert(name.clone(), fields);
+            }
+            TopDeclKind::Class(name, _, methods) => {
+                for method in methods {
+                    self.methods.insert(method.0.clone(), name.clone());
+                }
+            }
+            TopDeclKind::Enum(_, _) | TopDeclKind::Instance(_, _, _) => (),
+        }
+    }
+
+    /// Runs `main`, returning the exit code of the program.
+    pub fn run_main(&self) -> Result<i32, Diagnostic> {
+        if !self.functions.contains_key("main") {
+            return Err(Diagnostic::unspanned("there is no `main` function to run"));
+        }
+        let result = self.call_global("main", &[], Vec::new(), Span::default());
+        let _ = io::stdout().flush();
+        match result {
+            Ok(Value::Int(code)) => Ok(code),
+            Ok(_) => Ok(0),
+            Err(Stop::Exit(code)) => Ok(code),
+            Err(Stop::Error(err)) => Err(err),
+        }
+    }
+
+    pub fn exec(&self, frame: &mut Frame, stmts: &[hir::Stmt]) -> Result<Option<Value>, Stop> {
+        for stmt in stmts {
+            if let Flow::Return(value) = self.stmt(frame, stmt)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    fn stmt(&self, frame: &mut Frame, stmt: &hir::Stmt) -> Result<Flow, Stop> {
+        match &stmt.kind {
+            StmtKind::Assign(_, var, expr) => {
+                let value = self.eval(frame, expr)?;
+                frame.locals.insert(var.0, value);
+            }
+            // reading it first is caught by `Frame::lookup`
+            StmtKind::Declare(_, _) => (),
+            StmtKind::SetField(object, field, expr) => {
+                let value = self.eval(frame, expr)?;
+                *self.field_mut(frame, object, field)? = value;
+            }
+            StmtKind::Expr(expr) => {
+                self.eval(frame, expr)?;
+            }
+            StmtKind::If(cond, body1, body2) => {
+                let body = if self.eval_bool(frame, cond)? {
+                    Some(body1)
+                } else {
+                    body2.as_ref()
+                };
+                if let Some(body) = body {
+                    if let Some(value) = self.exec(frame, body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            StmtKind::While(cond, body) => {
+                while self.eval_bool(frame, cond)? {
+                    if let Some(value) = self.exec(frame, body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            StmtKind::Match(expr, arms) => {
+                let (variant, payload) = match self.eval(frame, expr)? {
+                    Value::Variant(variant, payload) => (variant, payload),
+                    value => return Err(mismatch(expr.span, "an enum", &value)),
+                };
+                let arm = arms.iter().find(|arm| match &arm.pattern.kind {
+                    PatternKind::Variant(name, _) => *name == variant,
+                    PatternKind::Wildcard => true,
+                });
+                let arm = match arm {
+                    Some(arm) => arm,
+                    None => {
+                        return Err(Stop::Error(Diagnostic::error(
+                            expr.span,
+                            format!("no arm matches `{}`", variant),
+                        )))
+                    }
+                };
+                if let PatternKind::Variant(_, binders) = &arm.pattern.kind {
+                    for (binder, value) in binders.iter().zip(payload) {
+                        if let Some(var) = binder {
+                            frame.locals.insert(var.0, value);
+                        }
+                    }
+                }
+                if let Some(value) = self.exec(frame, &arm.body)? {
+                    return Ok(Flow::Return(value));
+                }
+            }
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => self.eval(frame, expr)?,
+                    None => Value::Unit,
+                };
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// The field of the struct stored at the place expression `object`.
+    fn field_mut<'f>(
+        &self,
+        frame: &'f mut Frame,
+        object: &hir::Expr,
+        field: &str,
+    ) -> Result<&'f mut Value, Stop> {
+        let value = match &object.kind {
+            ExprKind::Name(Name::Local(var)) => {
+                frame.lookup(var, object.span)?;
+                frame.locals.get_mut(&var.0).unwrap()
+            }
+            ExprKind::Field(inner, inner_field) => self.field_mut(frame, inner, inner_field)?,
+            _ => {
+                return Err(Stop::Error(Diagnostic::error(
+                    object.span,
+                    "invalid left-hand side of assignment",
+                )))
+            }
+        };
+        match value {
+            Value::Struct(_, fields) => fields
+                .iter_mut()
+                .find(|(name, _)| name == field)
+                .map(|(_, value)| value)
+                .ok_or_else(|| no_field(object.span, field)),
+            value => Err(mismatch(object.span, "a struct", value)),
+        }
+    }
+
+    fn eval_bool(&self, frame: &mut Frame, expr: &hir::Expr) -> Result<bool, Stop> {
+        match self.eval(frame, expr)? {
+            Value::Bool(b) => Ok(b),
+            value => Err(mismatch(expr.span, "a bool", &value)),
+        }
+    }
+
+    fn eval_all(&self, frame: &mut Frame, exprs: &[hir::Expr]) -> Result<Vec<Value>, Stop> {
+        exprs.iter().map(|expr| self.eval(frame, expr)).collect()
+    }
+
+    pub fn eval(&self, frame: &mut Frame, expr: &hir::Expr) -> Result<Value, Stop> {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Literal(Literal::Bool(b)) => Ok(Value::Bool(*b)),
+            ExprKind::Literal(Literal::Int(n)) => Ok(Value::Int(*n as i32)),
+            ExprKind::Literal(Literal::String(s)) => Ok(Value::String(s.as_str().into())),
+            ExprKind::Name(Name::Local(var)) => frame.lookup(var, span).cloned(),
+            ExprKind::Name(Name::Global(name)) => {
+                let ty = frame.resolve(&expr.ty);
+                self.function_value(name, &ty, span)
+            }
+            ExprKind::Call(Name::Local(var), _, args) => {
+                let func = frame.lookup(var, span)?.clone();
+                let args = self.eval_all(frame, args)?;
+                self.apply(&func, args, span)
+            }
+            ExprKind::Call(Name::Global(name), type_args, args) => {
+                let type_args = type_args
+                    .iter()
+                    .map(|ty| frame.resolve(ty))
+                    .collect::<Vec<_>>();
+                let args = self.eval_all(frame, args)?;
+                self.call_global(name, &type_args, args, span)
+            }
+            ExprKind::Apply(func, args) => {
+                let func = self.eval(frame, func)?;
+                let args = self.eval_all(frame, args)?;
+                self.apply(&func, args, span)
+            }
+            ExprKind::Lambda(args, body) => Ok(Value::Function(Rc::new(Callable::Lambda(
+                args.clone(),
+                (**body).clone(),
+                frame.clone(),
+            )))),
+            ExprKind::Field(object, field) => match self.eval(frame, object)? {
+                Value::Struct(_, fields) => fields
+                    .into_iter()
+                    .find(|(name, _)| name == field)
+                    .map(|(_, value)| value)
+                    .ok_or_else(|| no_field(span, field)),
+                value => Err(mismatch(object.span, "a struct", &value)),
+            },
+            ExprKind::Struct(name, fields) => {
+                // evaluate the fields in the order they were written
+                let mut values = Vec::new();
+                for (field, expr) in fields {
+                    values.push((field.clone(), self.eval(frame, expr)?));
+                }
+                let order = self.structs.get(name).cloned().unwrap_or_default();
+                values.sort_by_key(|(field, _)| order.iter().position(|other| other == field));
+                Ok(Value::Struct(name.clone(), values))
+            }
+            ExprKind::Variant(_, variant, args) => {
+                Ok(Value::Variant(variant.clone(), self.eval_all(frame, args)?))
+            }
+            ExprKind::And(left, right) => {
+                Ok(Value::Bool(self.eval_bool(frame, left)? && self.eval_bool(frame, right)?))
+            }
+            ExprKind::Or(left, right) => {
+                Ok(Value::Bool(self.eval_bool(frame, left)? || self.eval_bool(frame, right)?))
+            }
+            ExprKind::Unary(op, operand) => match (op, self.eval(frame, operand)?) {
+                (UnOp::Neg, Value::Int(n)) => Ok(Value::Int(n.wrapping_neg())),
+                (UnOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (_, value) => Err(mismatch(operand.span, "a number or bool", &value)),
+            },
+            ExprKind::Binary(op, left, right) => {
+                let ty = frame.resolve(&left.ty);
+                let left = self.eval(frame, left)?;
+                let right = self.eval(frame, right)?;
+                self.binary(*op, &ty, left, right, span)
+            }
+        }
+    }
+
+    fn binary(
+        &self,
+        op: BinOp,
+        ty: &Type,
+        left: Value,
+        right: Value,
+        span: Span,
+    ) -> Result<Value, Stop> {
+        let (a, b) = match (&left, &right) {
+            (Value::Int(a), Value::Int(b)) => (*a, *b),
+            (Value::Bool(a), Value::Bool(b)) if op == BinOp::Eq => return Ok(Value::Bool(a == b)),
+            (Value::Bool(a), Value::Bool(b)) if op == BinOp::Ne => return Ok(Value::Bool(a != b)),
+            // `+` and `==` on anything else go through their instance's
+            // method
+            _ if op == BinOp::Add => return self.method("Add", "add", ty, vec![left, right], span),
+            _ if op == BinOp::Eq || op == BinOp::Ne => {
+                let equal = match self.method("Eq", "eq", ty, vec![left, right], span)? {
+                    Value::Bool(equal) => equal,
+                    value => return Err(mismatch(span, "a bool", &value)),
+                };
+                return Ok(Value::Bool(equal == (op == BinOp::Eq)));
+            }
+            _ => return Err(mismatch(span, "two numbers", &left)),
+        };
+        let value = match op {
+            BinOp::Add => Value::Int(a.wrapping_add(b)),
+            BinOp::Sub => Value::Int(a.wrapping_sub(b)),
+            BinOp::Mul => Value::Int(a.wrapping_mul(b)),
+            BinOp::Div | BinOp::Rem if b == 0 => {
+                return Err(Stop::Error(
+                    Diagnostic::error(span, "attempt to divide by zero")
+                        .with_label("the right side is zero"),
+                ))
+            }
+            BinOp::Div => Value::Int(a.wrapping_div(b)),
+            BinOp::Rem => Value::Int(a.wrapping_rem(b)),
+            BinOp::BitAnd => Value::Int(a & b),
+            BinOp::BitOr => Value::Int(a | b),
+            BinOp::BitXor => Value::Int(a ^ b),
+            BinOp::Shl => Value::Int(a.wrapping_shl(b as u32)),
+            BinOp::Shr => Value::Int(a.wrapping_shr(b as u32)),
+            BinOp::Eq => Value::Bool(a == b),
+            BinOp::Ne => Value::Bool(a != b),
+            BinOp::Lt => Value::Bool(a < b),
+            BinOp::Le => Value::Bool(a <= b),
+            BinOp::Gt => Value::Bool(a > b),
+            BinOp::Ge => Value::Bool(a >= b),
+        };
+        Ok(value)
+    }
+
+    /// Makes a function value out of the function or extern `name`, used at
+    /// type `ty`.
+    fn function_value(&self, name: &str, ty: &Type, span: Span) -> Result<Value, Stop> {
+        let type_args = match self.functions.get(name) {
+            Some(function) => {
+                let mut bound = BTreeMap::new();
+                let params = function.args.iter().map(|arg| arg.get_type()).collect();
+                Type::Fn(params, Box::new(function.ret.clone()), false).bind_params(ty, &mut bound);
+                Generic::names(&function.generics)
+                    .iter()
+                    .map(|param| bound.get(param).cloned().unwrap_or(Type::Unit))
+                    .collect()
+            }
+            None if self.externs.contains_key(name) => Vec::new(),
+            None => {
+                return Err(Stop::Error(Diagnostic::error(
+                    span,
+                    format!("cannot use `{}` as a value", name),
+                )))
+            }
+        };
+        Ok(Value::Function(Rc::new(Callable::Global(name.to_owned(), type_args))))
+    }
+
+    /// Calls a function value.
+    pub fn apply(&self, func: &Value, args: Vec<Value>, span: Span) -> Result<Value, Stop> {
+        match func {
+            Value::Function(callable) => match &**callable {
+                Callable::Global(name, type_args) => self.call_global(name, type_args, args, span),
+                Callable::Lambda(params, body, env) => {
+                    let mut frame = env.clone();
+                    for (param, arg) in params.iter().zip(args) {
+                        frame.locals.insert((param.0).0, arg);
+                    }
+                    self.eval(&mut frame, body)
+                }
+            },
+            value => Err(mismatch(span, "a function", value)),
+        }
+    }
+
+    /// Calls the function, extern or class method `name`, with the types its
+    /// generic parameters stand for.
+    fn call_global(
+        &self,
+        name: &str,
+        type_args: &[Type],
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Stop> {
+        if let Some(function) = self.functions.get(name) {
+            let mut frame = Frame {
+                locals: BTreeMap::new(),
+                params: Generic::names(&function.generics),
+                args: type_args.to_vec(),
+            };
+            for (arg, value) in function.args.iter().zip(args) {
+                frame.locals.insert((arg.0).0, value);
+            }
+            return Ok(self.exec(&mut frame, &function.body)?.unwrap_or(Value::Unit));
+        }
+        if self.externs.contains_key(name) {
+            return builtin(name, &args, span);
+        }
+        match self.methods.get(name) {
+            Some(class) => {
+                let ty = type_args.first().cloned().unwrap_or(Type::Unit);
+                self.method(class, name, &ty, args, span)
+            }
+            None => Err(Stop::Error(Diagnostic::error(
+                span,
+                format!("cannot find function `{}`", name),
+            ))),
+        }
+    }
+
+    /// Calls `method` from the instance of `class` for `ty`.
+    fn method(
+        &self,
+        class: &str,
+        method: &str,
+        ty: &Type,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Stop> {
+        match (class, ty, args.as_slice()) {
+            ("Add", Type::Int, [Value::Int(a), Value::Int(b)]) => {
+                Ok(Value::Int(a.wrapping_add(*b)))
+            }
+            ("Eq", Type::Int, [Value::Int(a), Value::Int(b)]) => Ok(Value::Bool(a == b)),
+            ("Eq", Type::Bool, [Value::Bool(a), Value::Bool(b)]) => Ok(Value::Bool(a == b)),
+            _ => {
+                let symbol = hir::method_symbol(class, ty, method);
+                if !self.functions.contains_key(&symbol) {
+                    return Err(Stop::Error(Diagnostic::error(
+                        span,
+                        format!("no instance of `{}` for `{}`", class, ty),
+                    )));
+                }
+                self.call_global(&symbol, &[], args, span)
+            }
+        }
+    }
+}
+
+/// Runs one of the externs in `BUILTINS`.
+fn builtin(name: &str, args: &[Value], span: Span) -> Result<Value, Stop> {
+    let mut stdout = io::stdout();
+    match (name, args) {
+        ("puts", [Value::String(s)]) => {
+            let _ = writeln!(stdout, "{}", s);
+            Ok(Value::Int(s.len() as i32 + 1))
+        }
+        ("putchar", [Value::Int(c)]) => {
+            let _ = stdout.write_all(&[*c as u8]);
+            Ok(Value::Int(*c))
+        }
+        ("printf", [Value::String(format), args @ ..]) => {
+            let out = printf(format, args, span)?;
+            let _ = stdout.write_all(out.as_bytes());
+            Ok(Value::Int(out.len() as i32))
+        }
+        ("exit", [Value::Int(code)]) => {
+            let _ = stdout.flush();
+            Err(Stop::Exit(*code))
+        }
+        _ if BUILTINS.contains(&name) => Err(Stop::Error(Diagnostic::error(
+            span,
+            format!("unexpected arguments to extern `{}`", name),
+        ))),
+        _ => Err(Stop::Error(
+            Diagnostic::error(
+                span,
+                format!("extern `{}` is not available in the interpreter", name),
+            ).with_note(format!("the interpreter only provides {}", BUILTINS.join(", "))),
+        )),
+    }
+}
+
+/// Formats the arguments like C's `printf`, supporting flags, widths and the
+/// `d`, `i`, `u`, `x`, `X`, `c` and `s` conversions.
+fn printf(format: &str, args: &[Value], span: Span) -> Result<String, Stop> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek() {
+            if !"-+ 0#".contains(c) && !c.is_ascii_digit() {
+                break;
+            }
+            spec.push(c);
+            chars.next();
+        }
+        let conversion = match chars.next() {
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            Some(conversion) => conversion,
+            None => break,
+        };
+        // C promotes bools to ints when passing them to variadic functions
+        let arg = match args.next() {
+            Some(Value::Bool(b)) => Value::Int(*b as i32),
+            Some(arg) => arg.clone(),
+            None => {
+                return Err(Stop::Error(Diagnostic::error(
+                    span,
+                    format!("missing argument for `%{}{}`", spec, conversion),
+                )))
+            }
+        };
+        let text = match (conversion, &arg) {
+            ('d', Value::Int(n)) | ('i', Value::Int(n)) => n.to_string(),
+            ('u', Value::Int(n)) => (*n as u32).to_string(),
+            ('x', Value::Int(n)) => format!("{:x}", n),
+            ('X', Value::Int(n)) => format!("{:X}", n),
+            ('c', Value::Int(n)) => (*n as u8 as char).to_string(),
+            ('s', Value::String(s)) => s.to_string(),
+            _ => {
+                return Err(Stop::Error(Diagnostic::error(
+                    span,
+                    format!("cannot format {} with `%{}{}`", arg, spec, conversion),
+                )))
+            }
+        };
+        let left = spec.contains('-');
+        let zero = spec.starts_with('0') && !left;
+        let width: usize = spec
+            .trim_start_matches(|c: char| !c.is_ascii_digit() || c == '0')
+            .parse()
+            .unwrap_or(0);
+        let padding = width.saturating_sub(text.chars().count());
+        if left {
+            out += &text;
+            out += &" ".repeat(padding);
+        } else if zero && text.starts_with('-') {
+            out += "-";
+            out += &"0".repeat(padding);
+            out += &text[1..];
+        } else {
+            out += &(if zero { "0" } else { " " }).repeat(padding);
+            out += &text;
+        }
+    }
+    Ok(out)
+}
+
+fn mismatch(span: Span, expected: &str, found: &Value) -> Stop {
+    Stop::Error(Diagnostic::error(
+        span,
+        format!("expected {}, found `{}`", expected, found),
+    ))
+}
+
+fn no_field(span: Span, field: &str) -> Stop {
+    Stop::Error(Diagnostic::error(span, format!("no field `{}` on this struct", field)))
+}
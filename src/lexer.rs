@@ -1,47 +1,79 @@
 use std::collections::VecDeque;
+use std::fmt;
 use std::str;
 
-use failure;
-
 const TAB_INDENT_WIDTH: usize = 8;
 const MAX_DEPTH: usize = 64;
+/// The words that start a line at the top level, which end any brackets
+/// left open before them.
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "class", "enum", "extern", "fn", "import", "instance", "pub", "struct",
+];
+
+/// Reported by the parser for an indented block that follows no block header.
+pub const UNEXPECTED_INDENT: &str = "unexpected indentation";
 
 pub type Spanned<Token, Location, Error> = Result<(Location, Token, Location), Error>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     // indentation
     Newline,
     Indent,
     Dedent,
-    EOF,
+    Eof,
 
     // symbols
     Arrow,
+    Ellipsis,
     DoubleEqual,
+    GreaterEqual,
+    LessEqual,
     NotEqual,
+    ShiftLeft,
+    ShiftRight,
 
+    Ampersand,
+    Caret,
     Colon,
     Comma,
     Dash,
     Dot,
     Equal,
+    Greater,
+    LeftBrace,
+    LeftBracket,
     LeftParen,
+    Less,
+    Percent,
+    Pipe,
     Plus,
+    RightBrace,
+    RightBracket,
     RightParen,
     Semicolon,
+    Slash,
     Star,
 
     // literals
     Integer(u32),
 
     // keywords
+    KeywordAnd,
+    KeywordClass,
     KeywordElse,
+    KeywordEnum,
     KeywordExtern,
     KeywordFalse,
     KeywordFn,
     KeywordIf,
+    KeywordImport,
+    KeywordInstance,
     KeywordLet,
+    KeywordMatch,
+    KeywordNot,
+    KeywordOr,
+    KeywordPub,
     KeywordReturn,
     KeywordStruct,
     KeywordTrue,
@@ -57,12 +89,99 @@ pub enum Token {
     Symbol(String),
     Ident(String),
     Char(char),
+    /// Stands in for a character that can't start a token, which the lexer
+    /// has already reported.
+    Error,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Token::Newline => return write!(f, "newline"),
+            Token::Indent => return write!(f, "indent"),
+            Token::Dedent => return write!(f, "dedent"),
+            Token::Eof => return write!(f, "end of file"),
+            Token::Integer(n) => return write!(f, "integer `{}`", n),
+            Token::String(_) => return write!(f, "string literal"),
+            Token::Char(_) => return write!(f, "character literal"),
+            Token::Error => return write!(f, "invalid character"),
+            Token::Ident(name) => return write!(f, "identifier `{}`", name),
+            Token::Symbol(symbol) => symbol.as_ref(),
+
+            Token::Arrow => "->",
+            Token::Ellipsis => "...",
+            Token::DoubleEqual => "==",
+            Token::GreaterEqual => ">=",
+            Token::LessEqual => "<=",
+            Token::NotEqual => "!=",
+            Token::ShiftLeft => "<<",
+            Token::ShiftRight => ">>",
+            Token::Ampersand => "&",
+            Token::Caret => "^",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dash => "-",
+            Token::Dot => ".",
+            Token::Equal => "=",
+            Token::Greater => ">",
+            Token::LeftBrace => "{",
+            Token::LeftBracket => "[",
+            Token::LeftParen => "(",
+            Token::Less => "<",
+            Token::Percent => "%",
+            Token::Pipe => "|",
+            Token::Plus => "+",
+            Token::RightBrace => "}",
+            Token::RightBracket => "]",
+            Token::RightParen => ")",
+            Token::Semicolon => ";",
+            Token::Slash => "/",
+            Token::Star => "*",
+
+            Token::KeywordAnd => "and",
+            Token::KeywordClass => "class",
+            Token::KeywordElse => "else",
+            Token::KeywordEnum => "enum",
+            Token::KeywordExtern => "extern",
+            Token::KeywordFalse => "false",
+            Token::KeywordFn => "fn",
+            Token::KeywordIf => "if",
+            Token::KeywordImport => "import",
+            Token::KeywordInstance => "instance",
+            Token::KeywordLet => "let",
+            Token::KeywordMatch => "match",
+            Token::KeywordNot => "not",
+            Token::KeywordOr => "or",
+            Token::KeywordPub => "pub",
+            Token::KeywordReturn => "return",
+            Token::KeywordStruct => "struct",
+            Token::KeywordTrue => "true",
+            Token::KeywordWhile => "while",
+
+            Token::TypeBool => "bool",
+            Token::TypeChar => "char",
+            Token::TypeInt => "int",
+            Token::TypeString => "string",
+        };
+        write!(f, "`{}`", symbol)
+    }
 }
 
-#[derive(Clone, Debug, Fail)]
-#[fail(display = "Lex error: {}", message)]
+#[derive(Clone, Debug)]
 pub struct LexError {
-    message: String,
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+impl LexError {
+    pub fn new(start: usize, end: usize, message: impl Into<String>) -> Self {
+        LexError {
+            start,
+            end,
+            message: message.into(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -73,6 +192,7 @@ pub struct Lexer {
     istack: Vec<usize>,
     nesting: usize,
     first: bool,
+    incomplete: bool,
 }
 
 impl Lexer {
@@ -84,19 +204,22 @@ impl Lexer {
             istack: vec![0],
             nesting: 0,
             first: true,
+            incomplete: false,
         };
         lexer.precalc();
         lexer
     }
+    /// Whether the input ended inside brackets or an indented block, or right
+    /// where one starts, so that more lines are needed to finish it.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
     fn rest(&self) -> &str {
         &self.source[self.position..]
     }
+    /// The character `offset` bytes past the current position.
     fn peek(&self, offset: usize) -> Option<char> {
-        let rest = self.rest();
-        if rest.is_empty() {
-            return None;
-        }
-        rest.chars().nth(offset)
+        self.rest().get(offset..).and_then(|rest| rest.chars().next())
     }
     fn peekwhile<F>(&self, f: F, offset: usize) -> &str
     where
@@ -107,7 +230,7 @@ impl Lexer {
             if !f(ch) {
                 break;
             }
-            length += 1;
+            length += ch.len_utf8();
         }
         &self.rest()[offset..length]
     }
@@ -127,6 +250,12 @@ impl Lexer {
         }
         (len, count)
     }
+    fn error(&mut self, start: usize, end: usize, message: impl Into<String>) {
+        self.queue
+            .push_back(Err(LexError::new(start, end, message)));
+    }
+    /// Emits the tokens for the indentation of the line starting at the
+    /// current position, returning how many bytes of it to skip.
     fn indentcalc(&mut self, line: &str) -> usize {
         if self.nesting > 0 {
             return 0;
@@ -137,7 +266,7 @@ impl Lexer {
         if whitelen == self.istack[level] {
             if !self.first {
                 self.queue
-                    .push_back(Ok((self.position, Token::Newline, self.position + 1)));
+                    .push_back(Ok((self.position, Token::Newline, self.position)));
             }
             self.first = false;
             return 0;
@@ -150,10 +279,16 @@ impl Lexer {
                 self.position + whitecount,
             )));
             if level + 1 > MAX_DEPTH {
-                panic!("exceeded max depth");
+                // still track the level so the dedents stay balanced
+                let (start, end) = (self.position, self.position + whitecount);
+                self.error(
+                    start,
+                    end,
+                    format!("indentation is nested more than {} levels deep", MAX_DEPTH),
+                );
             }
             self.istack.push(whitelen);
-            return whitelen;
+            return whitecount;
         }
 
         while whitelen < self.istack[level] {
@@ -165,6 +300,15 @@ impl Lexer {
             self.istack.pop();
         }
 
+        // `else` continues the statement whose block just ended
+        let word = line
+            .trim_start()
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .next();
+        if word == Some("else") {
+            self.queue.pop_back();
+        }
+
         0
     }
     fn read_comment(&mut self) {
@@ -174,7 +318,7 @@ impl Lexer {
             if c == '\n' {
                 break;
             }
-            length += 1;
+            length += c.len_utf8();
         }
         self.position += length;
     }
@@ -182,24 +326,28 @@ impl Lexer {
         // already guaranteed that ch is not going to be a digit
         let name = self
             .peekwhile(
-                |ch| {
-                    (ch >= 'a' && ch <= 'z')
-                        || (ch >= 'A' && ch <= 'Z')
-                        || (ch >= '0' && ch <= '9')
-                        || ch == '_'
-                },
+                |ch| ch.is_ascii_alphanumeric() || ch == '_',
                 0,
             ).to_owned();
         let length = name.len();
         self.queue.push_back(Ok((
             self.position,
             match name.as_ref() {
+                "and" => Token::KeywordAnd,
+                "class" => Token::KeywordClass,
                 "else" => Token::KeywordElse,
+                "enum" => Token::KeywordEnum,
                 "extern" => Token::KeywordExtern,
                 "false" => Token::KeywordFalse,
                 "fn" => Token::KeywordFn,
                 "if" => Token::KeywordIf,
+                "import" => Token::KeywordImport,
+                "instance" => Token::KeywordInstance,
                 "let" => Token::KeywordLet,
+                "match" => Token::KeywordMatch,
+                "not" => Token::KeywordNot,
+                "or" => Token::KeywordOr,
+                "pub" => Token::KeywordPub,
                 "return" => Token::KeywordReturn,
                 "struct" => Token::KeywordStruct,
                 "true" => Token::KeywordTrue,
@@ -216,185 +364,148 @@ impl Lexer {
         )));
         self.position += length;
     }
-    fn read_number_generic(&self, base: u32) -> Option<(u32, usize)> {
-        let mut dstr = String::new();
-        let mut length = 0;
-        let mut float = false;
-        let mut unsigned = false;
-        let mut long = false;
-        let mut uchecked = false;
-        let mut lchecked = false;
-        if base != 10 {
-            length += 2;
-        }
-        while let Some(c) = self.peek(length) {
-            match c {
-                '0'...'9' => if lchecked || uchecked {
-                    return None;
-                } else {
-                    dstr += &c.to_string();
-                },
-                'a'...'f' | 'A'...'F' => if lchecked || uchecked {
-                    return None;
-                } else if base == 16 {
-                    dstr += &c.to_string();
-                },
-                '.' => if lchecked || uchecked {
-                    return None;
-                } else {
-                    float = true;
-                    dstr += &c.to_string();
-                },
-                'u' => if lchecked || float {
-                    return None; // this is definitely an error
-                } else {
-                    unsigned = true;
-                    uchecked = true;
-                },
-                'L' => {
-                    long = true;
-                    lchecked = true;
-                }
-                _ => break,
-            }
-            length += 1;
-        }
-
-        // temporarily using unwrap here, we know what chars are in this string anyway
-        if float {
-            // if long {
-            //     Number::LongFloat(FromStr::from_str(&dstr).unwrap())
-            // } else {
-            //     Number::Float(FromStr::from_str(&dstr).unwrap())
-            // }
-            None
-        } else {
-            match (unsigned, long) {
-                (false, false) => Some((
-                    // println!("dstr: '{}'", dstr);
-                    // (true, true) => Number::ULongInteger(u64::from_str_radix(&dstr, base).unwrap()),
-                    // (true, false) => Number::UInteger(u32::from_str_radix(&dstr, base).unwrap()),
-                    // (false, true) => Number::LongInteger(i64::from_str_radix(&dstr, base).unwrap()),
-                    u32::from_str_radix(&dstr, base).unwrap(),
-                    length,
-                )),
-                _ => None,
-            }
-        }
-        // Some((
-        //     Number::Integer(i32::from_str_radix(&dstr, base as u32).unwrap()),
-        //     length,
-        // ))
-    }
     fn read_number(&mut self) {
-        let value = match self.peek(0) {
-            Some('0') => match self.peek(1) {
-                // this could be bin / oct / hex
-                Some('b') | Some('B') => self.read_number_generic(2),
-                Some('o') | Some('O') => self.read_number_generic(8),
-                Some('x') | Some('X') => self.read_number_generic(16),
-                _ => self.read_number_generic(10),
-            },
-            Some('1') | Some('2') | Some('3') | Some('4') | Some('5') | Some('6') | Some('7')
-            | Some('8') | Some('9') => self.read_number_generic(10),
-            Some(c) => panic!("invalid numeric character: {:?}", c),
-            None => panic!("what"),
+        // take the whole alphanumeric run so a bad literal is skipped as one
+        let literal = self
+            .peekwhile(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '.', 0)
+            .to_owned();
+        let (start, end) = (self.position, self.position + literal.len());
+        let value = match parse_number(&literal) {
+            Ok(value) => value,
+            Err(message) => {
+                self.error(start, end, message);
+                0
+            }
         };
-
-        if let Some((v, len)) = value {
-            self.queue
-                .push_back(Ok((self.position, Token::Integer(v), self.position + len)));
-            self.position += len;
+        self.queue.push_back(Ok((start, Token::Integer(value), end)));
+        self.position = end;
+    }
+    /// Skips past the closing `quote` on the current line, or up to the end
+    /// of the line if there isn't one.
+    fn skip_past(&mut self, quote: char) {
+        let rest = self.peekwhile(|c| c != quote && c != '\n', 1).len() + 1;
+        self.position += rest;
+        if self.peek(0) == Some(quote) {
+            self.position += 1;
         }
     }
     fn read_char(&mut self) {
+        let start = self.position;
         let retval;
         let next;
-        if let Some(c) = self.peek(1) {
-            if c == '\'' {
-                panic!("empty character");
+        match self.peek(1) {
+            Some('\'') => {
+                self.error(start, start + 2, "empty character literal");
+                self.position += 2;
+                self.placeholder_char(start);
+                return;
             }
-            if c == '\\' {
-                if let Some(c) = self.peek(2) {
-                    retval = match c {
-                        'n' => '\n',
-                        't' => '\t',
-                        '\\' => '\\',
-                        '\'' => '\'',
-                        '\"' => '\"',
-                        _ => panic!("invalid character escape: \\{}", c),
-                    };
+            Some('\\') => match self.peek(2).and_then(escape) {
+                Some(c) => {
+                    retval = c;
                     next = 3;
-                } else {
-                    panic!("premature end of string (0)");
                 }
-            } else {
+                None => {
+                    let end = start + 2 + self.peek(2).map(char::len_utf8).unwrap_or(0);
+                    self.error(start + 1, end, "invalid character escape");
+                    self.skip_past('\'');
+                    self.placeholder_char(start);
+                    return;
+                }
+            },
+            Some(c) if c != '\n' => {
                 retval = c;
-                next = 2;
+                next = 1 + c.len_utf8();
+            }
+            _ => {
+                self.error(start, start + 1, "unterminated character literal");
+                self.position += 1;
+                self.placeholder_char(start);
+                return;
             }
-        } else {
-            panic!("premature end of string (1)");
         }
         if let Some('\'') = self.peek(next) {
-            self.queue.push_back(Ok((
-                self.position,
-                Token::Char(retval),
-                self.position + next,
-            )));
+            self.queue
+                .push_back(Ok((start, Token::Char(retval), start + next + 1)));
             self.position += next + 1;
             return;
         }
-        panic!("unterminated char literal");
+        self.error(start, start + next, "unterminated character literal");
+        self.skip_past('\'');
+        self.placeholder_char(start);
+    }
+    /// Produces a character for a bad literal that ends at the current
+    /// position, so that parsing can carry on like it does for strings.
+    fn placeholder_char(&mut self, start: usize) {
+        self.queue
+            .push_back(Ok((start, Token::Char('\0'), self.position)));
     }
     fn read_string(&mut self) {
         // TODO: check triple string
         let quote_type: char = self.peek(0).unwrap();
+        let start = self.position;
         let mut length = 1;
         let mut chars = String::new();
         while let Some(c) = self.peek(length) {
-            // eprintln!("read char: {}", c);
-            if c == quote_type {
+            if c == quote_type || c == '\n' {
                 break;
             } else if c == '\\' {
-                length += 1;
-                if let Some(c) = self.peek(length) {
-                    chars.push(match c {
-                        'n' => '\n',
-                        't' => '\t',
-                        '\\' => '\\',
-                        '\'' => '\'',
-                        '\"' => '\"',
-                        _ => panic!("invalid character escape: \\{}", c),
-                    });
-                } else {
-                    panic!("premature end of string");
+                match self.peek(length + 1) {
+                    Some(e) => {
+                        match escape(e) {
+                            Some(escaped) => chars.push(escaped),
+                            None => self.error(
+                                start + length,
+                                start + length + 1 + e.len_utf8(),
+                                format!("invalid character escape: \\{}", e),
+                            ),
+                        }
+                        length += 1 + e.len_utf8();
+                    }
+                    None => length += 1,
                 }
             } else {
                 chars.push(c);
+                length += c.len_utf8();
             }
-            length += 1;
         }
-        // eprintln!("Final: {}", chars);
-        self.queue.push_back(Ok((
-            self.position,
-            Token::String(chars),
-            self.position + length,
-        )));
-        self.position += length + 1; // for quote
+        let end = if self.peek(length) == Some(quote_type) {
+            start + length + 1
+        } else {
+            // still produce the token so parsing can carry on
+            self.error(start, start + length, "unterminated string literal");
+            start + length
+        };
+        self.queue.push_back(Ok((start, Token::String(chars), end)));
+        self.position = end;
+    }
+    /// Whether the line after the line break at the current position leaves
+    /// the block or starts a new declaration, so the brackets still open were
+    /// never meant to be closed and the statement ends here.
+    fn abandons_brackets(&self) -> bool {
+        let line = self.peekwhile(|c| c != '\n', 1);
+        let (whitelen, _) = self.whitecount(line);
+        let word = line
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .next()
+            .unwrap_or("");
+        whitelen < self.istack[self.istack.len() - 1] || DECLARATION_KEYWORDS.contains(&word)
     }
     fn skipwhite(&self) -> usize {
         let mut offset = 0;
-        loop {
-            match self.peek(offset) {
-                Some(' ') | Some('\t') => offset += 1,
-                _ => break,
-            }
+        while let Some(' ') | Some('\t') = self.peek(offset) {
+            offset += 1;
         }
         offset
     }
     fn precalc(&mut self) {
         while let Some(c) = self.peek(0) {
-            if c == '\n' {
+            if c == '\n' && self.nesting > 0 && !self.abandons_brackets() {
+                // line breaks inside brackets don't end the statement
+                self.position += 1;
+                continue;
+            } else if c == '\n' {
+                self.nesting = 0;
                 self.queue
                     .push_back(Ok((self.position, Token::Newline, self.position)));
                 self.position += 1;
@@ -414,12 +525,22 @@ impl Lexer {
             }
 
             // check tokens
+            if self.rest().starts_with("...") {
+                self.queue
+                    .push_back(Ok((self.position, Token::Ellipsis, self.position + 3)));
+                self.position += 3;
+                continue;
+            }
             if let Some(c2) = self.peek(1) {
                 // match double token
                 let opt = match (c, c2) {
                     ('-', '>') => Some(Token::Arrow),
                     ('=', '=') => Some(Token::DoubleEqual),
+                    ('>', '=') => Some(Token::GreaterEqual),
+                    ('<', '=') => Some(Token::LessEqual),
                     ('!', '=') => Some(Token::NotEqual),
+                    ('<', '<') => Some(Token::ShiftLeft),
+                    ('>', '>') => Some(Token::ShiftRight),
                     (_, _) => None,
                 };
 
@@ -432,19 +553,36 @@ impl Lexer {
             }
             match c {
                 '#' => self.read_comment(),
-                '(' | ')' | '=' | ':' | ';' | '.' | ',' | '+' | '-' | '*' | '/' => {
+                '(' | ')' | '{' | '}' | '[' | ']' | '=' | ':' | ';' | '.' | ',' | '+' | '-' | '*' | '/' | '%'
+                | '<' | '>' | '&' | '|' | '^' => {
+                    match c {
+                        '(' | '{' | '[' => self.nesting += 1,
+                        ')' | '}' | ']' => self.nesting = self.nesting.saturating_sub(1),
+                        _ => (),
+                    }
                     self.queue.push_back(Ok((
                         self.position,
                         match c {
+                            '&' => Token::Ampersand,
+                            '^' => Token::Caret,
                             ':' => Token::Colon,
                             ',' => Token::Comma,
                             '-' => Token::Dash,
                             '.' => Token::Dot,
                             '=' => Token::Equal,
+                            '>' => Token::Greater,
+                            '{' => Token::LeftBrace,
+                            '[' => Token::LeftBracket,
                             '(' => Token::LeftParen,
+                            '<' => Token::Less,
+                            '%' => Token::Percent,
+                            '|' => Token::Pipe,
                             '+' => Token::Plus,
+                            '}' => Token::RightBrace,
+                            ']' => Token::RightBracket,
                             ')' => Token::RightParen,
                             ';' => Token::Semicolon,
+                            '/' => Token::Slash,
                             '*' => Token::Star,
                             _ => {
                                 Token::Symbol(self.source[self.position..=self.position].to_owned())
@@ -457,21 +595,415 @@ impl Lexer {
                 '\'' => self.read_char(),
                 '"' => self.read_string(),
                 // raw literals here
-                'a'...'z' | 'A'...'Z' => self.read_ident(),
-                '0'...'9' => self.read_number(),
-                _ => self.position += 1,
+                'a'..='z' | 'A'..='Z' | '_' => self.read_ident(),
+                '0'..='9' => self.read_number(),
+                _ => {
+                    let (start, end) = (self.position, self.position + c.len_utf8());
+                    self.error(start, end, format!("unexpected character `{}`", c));
+                    self.queue.push_back(Ok((start, Token::Error, end)));
+                    self.position = end;
+                }
             };
         }
+        // the input stops partway through a statement if a bracket or an
+        // indented block is still open, or the last token starts a block
+        let last = self.queue.iter().rev().find_map(|token| match token {
+            Ok((_, Token::Newline, _)) | Ok((_, Token::Indent, _)) | Ok((_, Token::Dedent, _)) => {
+                None
+            }
+            Ok((_, token, _)) => Some(token),
+            Err(_) => None,
+        });
+        self.incomplete = self.nesting > 0
+            || self.istack.len() > 1
+            || matches!(last, Some(Token::Colon) | Some(Token::Equal));
         self.indentcalc("");
         self.queue
-            .push_back(Ok((self.position, Token::EOF, self.position)));
+            .push_back(Ok((self.position, Token::Eof, self.position)));
+    }
+}
+
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        '\\' => Some('\\'),
+        '\'' => Some('\''),
+        '"' => Some('"'),
+        _ => None,
     }
 }
 
+fn parse_number(literal: &str) -> Result<u32, String> {
+    let (base, digits) = match literal.get(..2) {
+        Some("0b") | Some("0B") => (2, &literal[2..]),
+        Some("0o") | Some("0O") => (8, &literal[2..]),
+        Some("0x") | Some("0X") => (16, &literal[2..]),
+        _ => (10, literal),
+    };
+    if digits.contains('.') {
+        return Err("floating point literals are not supported".to_owned());
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_digit(base)) {
+        return Err(format!("invalid digit `{}` in base {} literal", c, base));
+    }
+    if digits.is_empty() {
+        return Err("missing digits after the base prefix".to_owned());
+    }
+    u32::from_str_radix(digits, base).map_err(|_| "integer literal is too large".to_owned())
+}
+
 impl Iterator for Lexer {
-    type Item = Spanned<Token, usize, failure::Error>;
+    type Item = Spanned<Token, usize, LexError>;
     fn next(&mut self) -> Option<Self::Item> {
-        let opt = self.queue.pop_front();
-        opt.map(|res| res.map_err(|err| err.into()))
+        self.queue.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        Lexer::new(source)
+            .map(|token| token.expect("unexpected lex error").1)
+            .collect()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        Lexer::new(source)
+            .filter_map(|token| token.err())
+            .map(|err| err.message)
+            .collect()
+    }
+
+    fn ident(name: &str) -> Token {
+        Token::Ident(name.to_owned())
+    }
+
+    fn count(tokens: &[Token], token: &Token) -> usize {
+        tokens.iter().filter(|other| *other == token).count()
+    }
+
+    /// A line `x` for every depth, indented by `indent` once per level.
+    fn nested(depths: &[usize], indent: &str) -> String {
+        depths
+            .iter()
+            .map(|depth| format!("{}x", indent.repeat(*depth)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Depths where each line is at most one level deeper than the last.
+    fn depths() -> impl Strategy<Value = Vec<usize>> {
+        prop::collection::vec(0..3usize, 0..30).prop_map(|steps| {
+            let mut depth = 0;
+            let rest = steps.into_iter().map(move |step| {
+                // 0 goes in, 1 stays and 2 goes out as far as it can
+                depth = match step {
+                    0 => depth + 1,
+                    1 => depth,
+                    _ => 0,
+                };
+                depth
+            });
+            // the first line can't be indented
+            Some(0).into_iter().chain(rest).collect()
+        })
+    }
+
+    #[test]
+    fn block_is_closed_at_eof() {
+        use self::Token::*;
+        assert_eq!(
+            tokens("a:\n    b"),
+            vec![ident("a"), Colon, Newline, Indent, ident("b"), Dedent, Newline, Eof]
+        );
+        assert_eq!(
+            tokens("a:\n    b\n"),
+            vec![ident("a"), Colon, Newline, Indent, ident("b"), Newline, Dedent, Newline, Eof]
+        );
+    }
+
+    #[test]
+    fn nested_blocks_are_closed_innermost_first() {
+        use self::Token::*;
+        assert_eq!(
+            tokens("a\n  b\n    c"),
+            vec![
+                ident("a"),
+                Newline,
+                Indent,
+                ident("b"),
+                Newline,
+                Indent,
+                ident("c"),
+                Dedent,
+                Newline,
+                Dedent,
+                Newline,
+                Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn else_continues_the_statement_before_it() {
+        let tokens = tokens("if a:\n    b\nelse:\n    c");
+        let dedent = tokens.iter().position(|token| *token == Token::Dedent).unwrap();
+        assert_eq!(tokens[dedent + 1], Token::KeywordElse);
+    }
+
+    #[test]
+    fn tab_is_eight_columns() {
+        // a tab lines up with eight spaces, and is deeper than four
+        let same = tokens("a\n\tb\n        c");
+        assert_eq!(count(&same, &Token::Indent), 1);
+        assert_eq!(count(&same, &Token::Dedent), 1);
+        let deeper = tokens("a\n    b\n\tc");
+        assert_eq!(count(&deeper, &Token::Indent), 2);
+        assert_eq!(count(&deeper, &Token::Dedent), 2);
+    }
+
+    #[test]
+    fn tab_indentation_spans_the_tab() {
+        let spans = Lexer::new("a\n\tbc")
+            .map(|token| {
+                let (start, token, end) = token.unwrap();
+                (token, start, end)
+            }).collect::<Vec<_>>();
+        assert_eq!(spans[2], (Token::Indent, 2, 3));
+        assert_eq!(spans[3], (ident("bc"), 3, 5));
+    }
+
+    #[test]
+    fn indentation_up_to_max_depth() {
+        let depths = (0..=MAX_DEPTH).collect::<Vec<_>>();
+        let tokens = tokens(&nested(&depths, " "));
+        assert_eq!(count(&tokens, &Token::Indent), MAX_DEPTH);
+        assert_eq!(count(&tokens, &Token::Dedent), MAX_DEPTH);
+    }
+
+    #[test]
+    fn indentation_past_max_depth() {
+        let depths = (0..=MAX_DEPTH + 1).collect::<Vec<_>>();
+        let source = nested(&depths, " ");
+        assert_eq!(
+            errors(&source),
+            vec![format!("indentation is nested more than {} levels deep", MAX_DEPTH)]
+        );
+        // the levels past the limit are still closed
+        let tokens = Lexer::new(&source)
+            .filter_map(|token| token.ok().map(|token| token.1))
+            .collect::<Vec<_>>();
+        assert_eq!(count(&tokens, &Token::Indent), MAX_DEPTH + 1);
+        assert_eq!(count(&tokens, &Token::Dedent), MAX_DEPTH + 1);
+    }
+
+    #[test]
+    fn line_breaks_in_brackets_are_ignored() {
+        use self::Token::*;
+        assert_eq!(
+            tokens("f(a,\n        b)"),
+            vec![ident("f"), LeftParen, ident("a"), Comma, ident("b"), RightParen, Eof]
+        );
+    }
+
+    #[test]
+    fn unclosed_brackets_end_with_their_block() {
+        let tokens = tokens("fn f() =\n    g(a\nfn h() =\n    b");
+        assert_eq!(count(&tokens, &Token::Indent), 2);
+        assert_eq!(count(&tokens, &Token::Dedent), 2);
+        let tokens = self::tokens("fn f() =\n    if a:\n        g(a\n    b");
+        assert_eq!(count(&tokens, &Token::Dedent), 2);
+        // any declaration at the top level ends them, not just a function
+        for keyword in DECLARATION_KEYWORDS {
+            let open = self::tokens(&format!("extern e: (int\n{} x", keyword));
+            let closed = self::tokens(&format!("extern e: (int)\n{} x", keyword));
+            assert_eq!(count(&open, &Token::Newline), count(&closed, &Token::Newline));
+        }
+    }
+
+    #[test]
+    fn number_bases() {
+        let numbers = tokens("42 0x1F 0X1f 0o17 0O17 0b101 0B101 4294967295");
+        let expected = [42, 31, 31, 15, 15, 5, 5, u32::MAX];
+        for (token, n) in numbers.iter().zip(expected.iter()) {
+            assert_eq!(*token, Token::Integer(*n));
+        }
+    }
+
+    #[test]
+    fn bad_numbers() {
+        assert_eq!(errors("0x"), vec!["missing digits after the base prefix"]);
+        assert_eq!(errors("0b102"), vec!["invalid digit `2` in base 2 literal"]);
+        assert_eq!(errors("12ab"), vec!["invalid digit `a` in base 10 literal"]);
+        assert_eq!(errors("0o8"), vec!["invalid digit `8` in base 8 literal"]);
+        assert_eq!(errors("4294967296"), vec!["integer literal is too large"]);
+        assert_eq!(errors("1.5"), vec!["floating point literals are not supported"]);
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            tokens(r#""a\n\t\\\"\'b""#),
+            vec![Token::String("a\n\t\\\"'b".to_owned()), Token::Eof]
+        );
+        assert_eq!(tokens(r#"'\n'"#), vec![Token::Char('\n'), Token::Eof]);
+    }
+
+    #[test]
+    fn bad_strings() {
+        assert_eq!(errors(r#""a\qb""#), vec!["invalid character escape: \\q"]);
+        assert_eq!(errors("\"abc\nx"), vec!["unterminated string literal"]);
+        assert_eq!(errors("''"), vec!["empty character literal"]);
+        assert_eq!(errors("'ab'"), vec!["unterminated character literal"]);
+        // the string is still produced, without the bad escape
+        let token = Lexer::new(r#""a\qb""#).find_map(|token| token.ok());
+        assert_eq!(token.map(|token| token.1), Some(Token::String("ab".to_owned())));
+    }
+
+    #[test]
+    fn every_error_is_reported_in_one_pass() {
+        let source = "a = '' + 0b2 $ b\nc";
+        let (tokens, errors): (Vec<_>, Vec<_>) = Lexer::new(source).partition(Result::is_ok);
+        let errors = errors
+            .into_iter()
+            .map(|err| err.unwrap_err())
+            .map(|err| (err.start, err.end, err.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (4, 6, "empty character literal".to_owned()),
+                (9, 12, "invalid digit `2` in base 2 literal".to_owned()),
+                (13, 14, "unexpected character `$`".to_owned()),
+            ]
+        );
+        // the tokens after each error are still there
+        let tokens = tokens.into_iter().map(|token| token.unwrap().1).collect::<Vec<_>>();
+        assert_eq!(count(&tokens, &ident("b")), 1);
+        assert_eq!(count(&tokens, &ident("c")), 1);
+    }
+
+    #[test]
+    fn bad_literals_leave_a_placeholder() {
+        for source in &["''", "'x", "'\\q'", "\"ab", "0b2"] {
+            let tokens = Lexer::new(source)
+                .filter_map(|token| token.ok())
+                .map(|token| token.1)
+                .collect::<Vec<_>>();
+            assert_eq!(tokens.len(), 2, "no placeholder for {}", source);
+        }
+        assert_eq!(errors("1 $ 2").len(), 1);
+        let mut tokens = Lexer::new("1 $ 2").filter_map(|token| token.ok());
+        assert!(tokens.any(|token| token.1 == Token::Error));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(Lexer::new("fn f(): int =").is_incomplete());
+        assert!(Lexer::new("if a:").is_incomplete());
+        assert!(Lexer::new("if a:\n    b").is_incomplete());
+        assert!(Lexer::new("f(a,").is_incomplete());
+        assert!(!Lexer::new("if a:\n    b\n").is_incomplete());
+        assert!(!Lexer::new("f(a, b)").is_incomplete());
+        assert!(!Lexer::new("").is_incomplete());
+    }
+
+    proptest! {
+        #[test]
+        fn spans_are_in_bounds(source in "(\\PC|[\t\n ])*") {
+            let mut previous = 0;
+            let mut last = None;
+            for token in Lexer::new(&source) {
+                let (start, end) = match &token {
+                    Ok((start, _, end)) => (*start, *end),
+                    Err(err) => (err.start, err.end),
+                };
+                prop_assert!(start <= end && end <= source.len());
+                prop_assert!(source.is_char_boundary(start) && source.is_char_boundary(end));
+                // errors inside a literal come before its token, but the
+                // tokens themselves are in order
+                if token.is_ok() {
+                    prop_assert!(start >= previous);
+                    previous = start;
+                }
+                last = token.ok().map(|token| token.1);
+            }
+            prop_assert_eq!(last, Some(Token::Eof));
+        }
+
+        #[test]
+        fn indentation_is_balanced(depths in depths()) {
+            let tokens = tokens(&nested(&depths, "    "));
+            let mut depth = 0;
+            let mut lines = depths.iter();
+            for (i, token) in tokens.iter().enumerate() {
+                match token {
+                    Token::Indent => depth += 1,
+                    Token::Dedent => {
+                        prop_assert!(depth > 0);
+                        depth -= 1;
+                        prop_assert_eq!(&tokens[i + 1], &Token::Newline);
+                    }
+                    Token::Ident(_) => prop_assert_eq!(Some(&depth), lines.next()),
+                    _ => (),
+                }
+            }
+            prop_assert_eq!(depth, 0);
+            prop_assert_eq!(tokens.last(), Some(&Token::Eof));
+        }
+
+        #[test]
+        fn tabs_and_eight_spaces_agree(depths in depths()) {
+            prop_assert_eq!(
+                tokens(&nested(&depths, "\t")),
+                tokens(&nested(&depths, "        "))
+            );
+        }
+
+        #[test]
+        fn eof_closes_every_block(depth in 1..MAX_DEPTH) {
+            let depths = (0..=depth).collect::<Vec<_>>();
+            let tokens = tokens(&nested(&depths, " "));
+            let closing = &tokens[tokens.len() - 1 - 2 * depth..];
+            for pair in closing[..2 * depth].chunks(2) {
+                prop_assert_eq!(pair, &[Token::Dedent, Token::Newline][..]);
+            }
+            prop_assert_eq!(closing.last(), Some(&Token::Eof));
+        }
+
+        #[test]
+        fn integers_in_every_base(n in any::<u32>()) {
+            let literals = [
+                n.to_string(),
+                format!("0x{:x}", n),
+                format!("0X{:X}", n),
+                format!("0o{:o}", n),
+                format!("0b{:b}", n),
+            ];
+            for literal in literals.iter() {
+                let spans = Lexer::new(literal).map(Result::unwrap).collect::<Vec<_>>();
+                prop_assert_eq!(&spans[0], &(0, Token::Integer(n), literal.len()));
+            }
+        }
+
+        #[test]
+        fn escaped_strings_round_trip(text in "(\\PC|[\t\n])*") {
+            let mut source = "\"".to_owned();
+            for c in text.chars() {
+                match c {
+                    '\n' => source += "\\n",
+                    '\t' => source += "\\t",
+                    '\\' => source += "\\\\",
+                    '"' => source += "\\\"",
+                    c => source.push(c),
+                }
+            }
+            source.push('"');
+            prop_assert_eq!(tokens(&source), vec![Token::String(text), Token::Eof]);
+        }
     }
 }
@@ -1,85 +1,258 @@
-#[macro_use]
 extern crate failure;
 extern crate itertools;
 #[macro_use]
 extern crate lalrpop_util;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(test)]
+extern crate proptest;
 extern crate structopt;
 
 mod ast;
 mod codegen;
 mod common;
+mod dataflow;
+mod diagnostic;
+mod driver;
+mod hir;
+mod interp;
 mod lexer;
 mod mir;
+mod module;
+mod repl;
+mod resolve;
+mod span;
+mod syntax;
 mod typeck;
 
-lalrpop_mod!(pub parser);
+lalrpop_mod!(#[allow(clippy::all, unused_parens)] pub parser);
 
+use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::{stdin, Read, Stdin};
 use std::path::PathBuf;
+use std::process::{self, Command};
 
 use failure::Error;
 use structopt::StructOpt;
 
-use codegen::{Codegen, Emitter};
-use mir::IntoMir;
-use typeck::TypeStack;
+use diagnostic::Diagnostic;
+use driver::{Emit, Input, TempDir};
+use module::SearchPath;
+use span::SourceMap;
 
-enum Input {
+enum Source {
     File(File),
     Stdin(Stdin),
 }
 
-impl AsMut<Read> for Input {
-    fn as_mut(&mut self) -> &mut (Read + 'static) {
+impl AsMut<dyn Read> for Source {
+    fn as_mut(&mut self) -> &mut (dyn Read + 'static) {
         match self {
-            Input::File(file) => file,
-            Input::Stdin(stdin) => stdin,
+            Source::File(file) => file,
+            Source::Stdin(stdin) => stdin,
         }
     }
 }
 
 #[derive(StructOpt)]
-struct Opt {
+#[structopt(name = "gala")]
+enum Opt {
+    /// Compile a program.
+    #[structopt(name = "build")]
+    Build {
+        #[structopt(flatten)]
+        compile: CompileOpt,
+        /// Where to write the output, or `-` for stdout.
+        #[structopt(short = "o", parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// What to produce: ast, mir, llvm-ir, asm, obj or exe.
+        #[structopt(long = "emit", default_value = "exe")]
+        emit: Emit,
+        /// Print the inferred signature of every function instead of compiling.
+        #[structopt(long = "print-types")]
+        print_types: bool,
+        /// Print the tokens of the file with their spans instead of compiling.
+        #[structopt(long = "dump-tokens")]
+        dump_tokens: bool,
+    },
+    /// Compile a program and run it, exiting with its exit code.
+    #[structopt(name = "run")]
+    Run {
+        #[structopt(flatten)]
+        compile: CompileOpt,
+        /// Run the program with the interpreter instead of compiling it.
+        #[structopt(long = "interp")]
+        interp: bool,
+        /// Arguments for the program.
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+    /// Read declarations and statements from stdin and run them as they are
+    /// entered.
+    #[structopt(name = "repl")]
+    Repl {
+        /// The directory holding the modules imported as `std.*`.
+        #[structopt(long = "std-path", parse(from_os_str))]
+        std_path: Option<PathBuf>,
+    },
+}
+
+#[derive(StructOpt)]
+struct CompileOpt {
+    /// The program to compile, read from stdin if missing.
     #[structopt(parse(from_os_str))]
     file: Option<PathBuf>,
+    /// The directory holding the modules imported as `std.*`.
+    #[structopt(long = "std-path", parse(from_os_str))]
+    std_path: Option<PathBuf>,
+    /// How much to optimize, from 0 to 3.
+    #[structopt(short = "O", default_value = "0", parse(try_from_str = "parse_opt_level"))]
+    opt_level: u8,
 }
 
-// lalrpop_mod!(pub parser);
+fn parse_opt_level(s: &str) -> Result<u8, String> {
+    match s.parse() {
+        Ok(level) if level <= 3 => Ok(level),
+        _ => Err(format!("invalid optimization level `{}`, expected 0 to 3", s)),
+    }
+}
 
 fn main() -> Result<(), Error> {
-    let opt = Opt::from_args();
-    let mut input = match opt.file {
-        Some(path) => Input::File(File::open(&path)?),
-        None => Input::Stdin(stdin()),
+    match Opt::from_args() {
+        Opt::Build {
+            compile,
+            output,
+            emit,
+            print_types,
+            dump_tokens,
+        } => {
+            let (input, stem) = read_input(&compile)
+                .unwrap_or_else(|err| report(&SourceMap::default(), &[err]));
+            let mut sources = SourceMap::default();
+            if dump_tokens {
+                let tokens = driver::dump_tokens(&mut sources, &input);
+                let output = output.unwrap_or_else(|| PathBuf::from("-"));
+                if let Err(err) = driver::write(&output, &tokens) {
+                    report(&sources, &[err]);
+                }
+                return Ok(());
+            }
+            let text = driver::compile(&mut sources, &input, emit, print_types)
+                .unwrap_or_else(|errors| report(&sources, &errors));
+            // the signatures go to stdout unless asked for elsewhere
+            let output = output
+                .or_else(|| if print_types { None } else { emit.default_output(&stem) })
+                .unwrap_or_else(|| PathBuf::from("-"));
+            let result = if print_types || emit == Emit::Ast || emit == Emit::Mir {
+                driver::write(&output, &text)
+            } else {
+                TempDir::new().and_then(|temp| {
+                    let ir = driver::optimize(text, compile.opt_level, &temp)?;
+                    match emit {
+                        Emit::LlvmIr => driver::write(&output, &ir),
+                        _ => driver::backend(&ir, emit, &output, compile.opt_level, &temp),
+                    }
+                })
+            };
+            if let Err(err) = result {
+                report(&sources, &[err]);
+            }
+        }
+        Opt::Run {
+            compile,
+            interp,
+            args,
+        } => {
+            let (input, stem) = read_input(&compile)
+                .unwrap_or_else(|err| report(&SourceMap::default(), &[err]));
+            let mut sources = SourceMap::default();
+            if interp {
+                let code = driver::interpret(&mut sources, &input)
+                    .unwrap_or_else(|errors| report(&sources, &errors));
+                process::exit(code);
+            }
+            let ir = driver::compile(&mut sources, &input, Emit::Exe, false)
+                .unwrap_or_else(|errors| report(&sources, &errors));
+            let temp = TempDir::new().unwrap_or_else(|err| report(&sources, &[err]));
+            let exe = temp.path(&stem);
+            let result = driver::optimize(ir, compile.opt_level, &temp)
+                .and_then(|ir| driver::backend(&ir, Emit::Exe, &exe, compile.opt_level, &temp));
+            if let Err(err) = result {
+                drop(temp);
+                report(&sources, &[err]);
+            }
+            let status = Command::new(&exe).args(&args).status()?;
+            drop(temp);
+            process::exit(status.code().unwrap_or(1));
+        }
+        Opt::Repl { std_path } => {
+            let search = SearchPath {
+                root: env::current_dir()?,
+                std: std_path.unwrap_or_else(default_std_path),
+            };
+            driver::with_stack(move || repl::run(search))
+                .unwrap_or_else(|err| report(&SourceMap::default(), &[err]))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the program, returning it along with the name to give its output.
+fn read_input(opt: &CompileOpt) -> Result<(Input, String), Diagnostic> {
+    let (name, stem, root, mut source) = match &opt.file {
+        Some(path) => (
+            path.to_string_lossy().into_owned(),
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path.parent().map(PathBuf::from).unwrap_or_default(),
+            Source::File(
+                File::open(path).map_err(|err| cannot_read(&path.to_string_lossy(), err))?,
+            ),
+        ),
+        None => (
+            "<stdin>".to_owned(),
+            "a.out".to_owned(),
+            env::current_dir().map_err(|err| {
+                Diagnostic::unspanned(format!("could not find the current directory: {}", err))
+            })?,
+            Source::Stdin(stdin()),
+        ),
+    };
+    let search = SearchPath {
+        root,
+        std: opt.std_path.clone().unwrap_or_else(default_std_path),
     };
 
     let mut buf = Vec::new();
-    let reader = input.as_mut();
-    reader.read_to_end(&mut buf)?;
-    let contents = String::from_utf8(buf)?;
-
-    let lexer = lexer::Lexer::new(&contents);
-    // eprintln!("Lexer: {:#?}", lexer.clone().collect::<Vec<_>>());
-    // panic!("");
-
-    let parser = parser::ProgramParser::new();
-    let ast = parser
-        .parse(lexer)
-        .map_err(|err| format_err!("{:?}", err))?;
-
-    let mut context = mir::Context::default();
-    let mut mir = ast.into_mir(&mut context);
+    let reader = source.as_mut();
+    reader.read_to_end(&mut buf).map_err(|err| cannot_read(&name, err))?;
+    let contents = String::from_utf8(buf).map_err(|err| cannot_read(&name, err))?;
+    Ok((
+        Input {
+            name,
+            contents,
+            search,
+        },
+        stem,
+    ))
+}
 
-    let mut stack = TypeStack::default();
-    mir.typeck(&mut stack)?;
-    eprintln!("{:?}", mir);
+fn cannot_read(name: &str, err: impl fmt::Display) -> Diagnostic {
+    Diagnostic::unspanned(format!("could not read `{}`: {}", name, err))
+}
 
-    let mut emitter = Emitter::new();
-    mir.generate(&mut emitter);
+/// Where `std` is when there is no `--std-path`.
+fn default_std_path() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/std"))
+}
 
-    println!("{}", emitter.as_string());
-    Ok(())
+/// Prints the diagnostics to stderr and exits.
+fn report(sources: &SourceMap, diagnostics: &[Diagnostic]) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(sources));
+    }
+    process::exit(1)
 }
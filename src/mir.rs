@@ -1,60 +1,929 @@
-use common::{next_int, Arg, Field, Literal, Type, Typed};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-#[derive(Default)]
-pub struct Context {}
+use common::{Arg, BinOp, Generic, Literal, PatternKind, Type, Typed, UnOp, Variant};
+use hir::{self, ExprKind, Name, StmtKind, Var};
+use span::Span;
+
+/// The index of a local in `Body::locals`.
+pub type Local = usize;
+/// The index of a block in `Body::blocks`.
+pub type BlockId = usize;
+
+/// A typechecked program with the body of every function lowered to a control
+/// flow graph, which is what codegen and the dataflow passes work on.
+#[derive(Clone, Debug)]
+pub struct Program {
+    /// The declarations other than functions.
+    pub decls: Vec<hir::TopDecl>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub generics: Vec<Generic>,
+    pub body: Body,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub struct Body {
+    pub locals: Vec<LocalDecl>,
+    pub args: Vec<Local>,
+    /// The locals a lambda copies out of its environment before it starts.
+    pub captures: Vec<Local>,
+    pub ret: Type,
+    /// Starts at the first block.
+    pub blocks: Vec<Block>,
+    /// The bodies of the lambdas created by `Rvalue::Closure`.
+    pub lambdas: Vec<Body>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalDecl {
+    pub ty: Type,
+    /// The variable stored in the local, or `None` for temporaries.
+    pub var: Option<Var>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+}
+
+/// Stores the result of `value` in `place`.
+#[derive(Clone, Debug)]
+pub struct Statement {
+    pub place: Place,
+    pub value: Rvalue,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub struct Terminator {
+    pub kind: TerminatorKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum TerminatorKind {
+    Goto(BlockId),
+    /// A `bool`, and where to go when it is true or false.
+    Branch(Operand, BlockId, BlockId),
+    /// An `int`, the blocks for some of its values and the one for the rest,
+    /// which is `None` when no other value is possible.
+    Switch(Operand, Vec<(usize, BlockId)>, Option<BlockId>),
+    Return(Option<Operand>),
+    Unreachable,
+}
+
+/// Memory that can be read and written: a local, or part of one.
+#[derive(Clone, Debug)]
+pub struct Place {
+    pub local: Local,
+    pub projection: Vec<Projection>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Projection {
+    /// A field of a struct, by index, and its type.
+    Field(usize, Type),
+    /// A value in the payload of an enum's variant: the variant's tag, the
+    /// index in the payload and the type.
+    Payload(usize, usize, Type),
+}
+
+#[derive(Clone, Debug)]
+pub enum Operand {
+    /// The value in a place, and the expression it is read for.
+    Copy(Place, Span),
+    Constant(Literal),
+    /// A function used as a value, with the type it is used at.
+    Function(String, Type),
+}
+
+#[derive(Clone, Debug)]
+pub enum Rvalue {
+    Use(Operand),
+    Unary(UnOp, Operand),
+    Binary(BinOp, Operand, Operand),
+    Call(Callee, Vec<Operand>),
+    /// A struct and its fields in the order they were declared.
+    Struct(String, Vec<Operand>),
+    /// An enum, the tag of the variant and its payload.
+    Variant(String, usize, Vec<Operand>),
+    /// The tag of the enum in a place, and the expression it is read for.
+    Tag(Place, Span),
+    /// One of the body's lambdas and the values it captures.
+    Closure(usize, Vec<Operand>),
+}
+
+#[derive(Clone, Debug)]
+pub enum Callee {
+    /// A function or extern, with the types its generic parameters are
+    /// instantiated with.
+    Function(String, Vec<Type>),
+    /// A class, one of its methods and the type whose instance to use.
+    Method(String, String, Type),
+    /// A function value.
+    Value(Operand),
+}
+
+impl TerminatorKind {
+    /// The blocks control can go to next. A branch on a constant only ever
+    /// takes one way.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            TerminatorKind::Goto(target) => vec![*target],
+            TerminatorKind::Branch(Operand::Constant(Literal::Bool(true)), succ, _) => vec![*succ],
+            TerminatorKind::Branch(Operand::Constant(Literal::Bool(false)), _, fail) => {
+                vec![*fail]
+            }
+            TerminatorKind::Branch(_, succ, fail) => vec![*succ, *fail],
+            TerminatorKind::Switch(_, cases, otherwise) => cases
+                .iter()
+                .map(|(_, target)| *target)
+                .chain(otherwise.iter().cloned())
+                .collect(),
+            TerminatorKind::Return(_) | TerminatorKind::Unreachable => Vec::new(),
+        }
+    }
+
+    /// The place read before leaving the block, and where it is read.
+    pub fn reads(&self) -> Option<(&Place, Span)> {
+        match self {
+            TerminatorKind::Branch(operand, _, _)
+            | TerminatorKind::Switch(operand, _, _)
+            | TerminatorKind::Return(Some(operand)) => operand.read(),
+            _ => None,
+        }
+    }
+}
+
+impl Place {
+    pub fn local(local: Local) -> Self {
+        Place {
+            local,
+            projection: Vec::new(),
+        }
+    }
+
+    fn project(mut self, projection: Projection) -> Self {
+        self.projection.push(projection);
+        self
+    }
+
+    fn map_types(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        for projection in self.projection.iter_mut() {
+            match projection {
+                Projection::Field(_, ty) | Projection::Payload(_, _, ty) => f(ty),
+            }
+        }
+    }
+}
+
+impl Operand {
+    /// The place the operand reads, and where.
+    pub fn read(&self) -> Option<(&Place, Span)> {
+        match self {
+            Operand::Copy(place, span) => Some((place, *span)),
+            _ => None,
+        }
+    }
+
+    fn map_types(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        match self {
+            Operand::Copy(place, _) => place.map_types(f),
+            Operand::Constant(_) => (),
+            Operand::Function(_, ty) => f(ty),
+        }
+    }
+}
+
+impl Rvalue {
+    /// The places whose values the rvalue reads, and where.
+    pub fn reads(&self) -> Vec<(&Place, Span)> {
+        let operands = match self {
+            Rvalue::Use(operand) | Rvalue::Unary(_, operand) => vec![operand],
+            Rvalue::Binary(_, left, right) => vec![left, right],
+            Rvalue::Call(Callee::Value(func), args) => {
+                ::std::iter::once(func).chain(args.iter()).collect()
+            }
+            Rvalue::Call(_, operands)
+            | Rvalue::Struct(_, operands)
+            | Rvalue::Variant(_, _, operands)
+            | Rvalue::Closure(_, operands) => operands.iter().collect(),
+            Rvalue::Tag(place, span) => return vec![(place, *span)],
+        };
+        operands.into_iter().filter_map(Operand::read).collect()
+    }
 
-impl Context {
-    pub fn next(&mut self) -> Type {
-        Type::T(next_int())
+    fn map_types(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        match self {
+            Rvalue::Use(operand) | Rvalue::Unary(_, operand) => operand.map_types(f),
+            Rvalue::Binary(_, left, right) => {
+                left.map_types(f);
+                right.map_types(f);
+            }
+            Rvalue::Call(callee, args) => {
+                match callee {
+                    Callee::Function(_, type_args) => {
+                        for ty in type_args.iter_mut() {
+                            f(ty);
+                        }
+                    }
+                    Callee::Method(_, _, ty) => f(ty),
+                    Callee::Value(operand) => operand.map_types(f),
+                }
+                for arg in args.iter_mut() {
+                    arg.map_types(f);
+                }
+            }
+            Rvalue::Struct(_, operands)
+            | Rvalue::Variant(_, _, operands)
+            | Rvalue::Closure(_, operands) => {
+                for operand in operands.iter_mut() {
+                    operand.map_types(f);
+                }
+            }
+            Rvalue::Tag(place, _) => place.map_types(f),
+        }
+    }
+}
+
+impl Body {
+    pub fn place_type(&self, place: &Place) -> Type {
+        match place.projection.last() {
+            Some(Projection::Field(_, ty)) | Some(Projection::Payload(_, _, ty)) => ty.clone(),
+            None => self.locals[place.local].ty.clone(),
+        }
+    }
+
+    pub fn operand_type(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Copy(place, _) => self.place_type(place),
+            Operand::Constant(literal) => literal.get_type(),
+            Operand::Function(_, ty) => ty.clone(),
+        }
+    }
+
+    /// Calls `f` on every type stored in the body and its lambdas.
+    pub fn map_types(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        for local in self.locals.iter_mut() {
+            f(&mut local.ty);
+        }
+        f(&mut self.ret);
+        for block in self.blocks.iter_mut() {
+            for statement in block.statements.iter_mut() {
+                statement.place.map_types(f);
+                statement.value.map_types(f);
+            }
+            match &mut block.terminator.kind {
+                TerminatorKind::Branch(operand, _, _)
+                | TerminatorKind::Switch(operand, _, _)
+                | TerminatorKind::Return(Some(operand)) => operand.map_types(f),
+                _ => (),
+            }
+        }
+        for lambda in self.lambdas.iter_mut() {
+            lambda.map_types(f);
+        }
+    }
+
+    fn fmt_named(&self, f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| format!("_{}: {}", arg, self.locals[*arg].ty))
+            .collect::<Vec<_>>();
+        writeln!(f, "fn {}({}) -> {} {{", name, args.join(", "), self.ret)?;
+        for (i, local) in self.locals.iter().enumerate() {
+            if self.args.contains(&i) {
+                continue;
+            }
+            write!(f, "    let _{}: {};", i, local.ty)?;
+            match &local.var {
+                Some(var) if self.captures.contains(&i) => writeln!(f, " // {} (captured)", var)?,
+                Some(var) => writeln!(f, " // {}", var)?,
+                None => writeln!(f)?,
+            }
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, "    bb{}: {{", i)?;
+            for statement in block.statements.iter() {
+                writeln!(f, "        {} = {};", statement.place, statement.value)?;
+            }
+            writeln!(f, "        {};", block.terminator.kind)?;
+            writeln!(f, "    }}")?;
+        }
+        writeln!(f, "}}")?;
+        for (i, lambda) in self.lambdas.iter().enumerate() {
+            writeln!(f)?;
+            lambda.fmt_named(f, &format!("{}.lambda{}", name, i))?;
+        }
+        Ok(())
     }
 }
 
-pub trait IntoMir<T> {
-    fn into_mir(self, &mut Context) -> T;
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
-pub struct Program(pub Vec<TopDecl>);
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.generics.is_empty() {
+            self.body.fmt_named(f, &self.name)
+        } else {
+            let generics = self
+                .generics
+                .iter()
+                .map(|generic| generic.to_string())
+                .collect::<Vec<_>>();
+            self.body
+                .fmt_named(f, &format!("{}[{}]", self.name, generics.join(", ")))
+        }
+    }
+}
 
-#[derive(Debug)]
-pub enum TopDecl {
-    Extern(String, Type),
-    Fn(String, Vec<Arg>, Type, Vec<Stmt>),
-    Struct(String, Vec<Field>),
+impl fmt::Display for TerminatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TerminatorKind::Goto(target) => write!(f, "goto -> bb{}", target),
+            TerminatorKind::Branch(cond, succ, fail) => {
+                write!(f, "branch {} -> [true: bb{}, false: bb{}]", cond, succ, fail)
+            }
+            TerminatorKind::Switch(value, cases, otherwise) => {
+                write!(f, "switch {} -> [", value)?;
+                for (tag, target) in cases {
+                    write!(f, "{}: bb{}, ", tag, target)?;
+                }
+                match otherwise {
+                    Some(target) => write!(f, "otherwise: bb{}]", target),
+                    None => write!(f, "otherwise: unreachable]"),
+                }
+            }
+            TerminatorKind::Return(Some(value)) => write!(f, "return {}", value),
+            TerminatorKind::Return(None) => write!(f, "return"),
+            TerminatorKind::Unreachable => write!(f, "unreachable"),
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum Stmt {
-    Assign(bool, String, Expr),
-    Expr(Expr),
-    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
-    While(Expr, Vec<Stmt>),
-    Return(Option<Expr>),
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut place = format!("_{}", self.local);
+        for projection in self.projection.iter() {
+            place = match projection {
+                Projection::Field(index, _) => format!("{}.{}", place, index),
+                Projection::Payload(tag, index, _) => format!("({} as #{}).{}", place, tag, index),
+            };
+        }
+        write!(f, "{}", place)
+    }
 }
 
-#[derive(Debug)]
-pub enum Expr {
-    Call(String, Vec<Expr>, Type),
-    Literal(Literal, Type),
-    Name(String, Type),
-    NotEquals(Box<Expr>, Box<Expr>, Type),
-    Equals(Box<Expr>, Box<Expr>, Type),
-    Plus(Box<Expr>, Box<Expr>, Type),
-    Minus(Box<Expr>, Box<Expr>, Type),
-    Times(Box<Expr>, Box<Expr>, Type),
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Copy(place, _) => write!(f, "{}", place),
+            Operand::Constant(Literal::Bool(b)) => write!(f, "const {}", b),
+            Operand::Constant(Literal::Int(n)) => write!(f, "const {}", n),
+            Operand::Constant(Literal::String(s)) => write!(f, "const {:?}", s),
+            Operand::Function(name, _) => write!(f, "fn {}", name),
+        }
+    }
 }
 
-impl Typed for Expr {
-    fn get_type(&self) -> Type {
+impl fmt::Display for Rvalue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list(operands: &[Operand]) -> String {
+            operands
+                .iter()
+                .map(|operand| operand.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
         match self {
-            Expr::Call(_, _, t)
-            | Expr::Literal(_, t)
-            | Expr::Name(_, t)
-            | Expr::NotEquals(_, _, t)
-            | Expr::Equals(_, _, t)
-            | Expr::Plus(_, _, t)
-            | Expr::Minus(_, _, t)
-            | Expr::Times(_, _, t) => t.clone(),
+            Rvalue::Use(operand) => write!(f, "{}", operand),
+            Rvalue::Unary(op, operand) => write!(f, "{:?}({})", op, operand),
+            Rvalue::Binary(op, left, right) => write!(f, "{:?}({}, {})", op, left, right),
+            Rvalue::Call(Callee::Function(name, type_args), args) if !type_args.is_empty() => {
+                let type_args = type_args
+                    .iter()
+                    .map(|ty| ty.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "{}[{}]({})", name, type_args.join(", "), list(args))
+            }
+            Rvalue::Call(Callee::Function(name, _), args) => write!(f, "{}({})", name, list(args)),
+            Rvalue::Call(Callee::Method(class, method, ty), args) => {
+                write!(f, "{}({})", hir::method_symbol(class, ty, method), list(args))
+            }
+            Rvalue::Call(Callee::Value(func), args) => write!(f, "({})({})", func, list(args)),
+            Rvalue::Struct(name, fields) => write!(f, "{} {{ {} }}", name, list(fields)),
+            Rvalue::Variant(name, tag, payload) => write!(f, "{}#{}({})", name, tag, list(payload)),
+            Rvalue::Tag(place, _) => write!(f, "tag({})", place),
+            Rvalue::Closure(index, captures) => {
+                write!(f, "closure lambda{} [{}]", index, list(captures))
+            }
+        }
+    }
+}
+
+/// What lowering needs to know about the declarations of a program.
+#[derive(Default)]
+struct Tables {
+    structs: BTreeMap<String, Vec<String>>,
+    enums: BTreeMap<String, Vec<Variant>>,
+    /// The functions and externs.
+    functions: BTreeSet<String>,
+    /// Which class declares each method.
+    methods: BTreeMap<String, String>,
+}
+
+impl Tables {
+    fn field_index(&self, ty: &Type, field: &str) -> usize {
+        match ty {
+            Type::Struct(name) => self
+                .structs
+                .get(name)
+                .and_then(|fields| fields.iter().position(|other| other == field))
+                .unwrap_or_default(),
+            _ => 0,
+        }
+    }
+
+    /// The tag of a variant and the types of its payload.
+    fn variant(&self, name: &str, variant: &str) -> (usize, &[Type]) {
+        self.enums
+            .get(name)
+            .and_then(|variants| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .find(|(_, other)| other.0 == variant)
+                    .map(|(tag, other)| (tag, other.1.as_slice()))
+            }).unwrap_or((0, &[]))
+    }
+}
+
+/// Lowers every function of a typechecked program.
+pub fn lower(program: hir::Program) -> Program {
+    let mut tables = Tables::default();
+    tables.methods.insert("add".to_owned(), "Add".to_owned());
+    tables.methods.insert("eq".to_owned(), "Eq".to_owned());
+    for decl in program.0.iter() {
+        match &decl.kind {
+            hir::TopDeclKind::Struct(name, fields) => {
+                let fields = fields.iter().map(|field| field.0.clone()).collect();
+                tables.structs.insert(name.clone(), fields);
+            }
+            hir::TopDeclKind::Enum(name, variants) => {
+                tables.enums.insert(name.clone(), variants.clone());
+            }
+            hir::TopDeclKind::Class(name, _, methods) => {
+                for method in methods {
+                    tables.methods.insert(method.0.clone(), name.clone());
+                }
+            }
+            hir::TopDeclKind::Extern(name, _) | hir::TopDeclKind::Fn(name, _, _, _, _) => {
+                tables.functions.insert(name.clone());
+            }
+            hir::TopDeclKind::Instance(_, _, _) => (),
+        }
+    }
+
+    let mut decls = Vec::new();
+    let mut functions = Vec::new();
+    for decl in program.0 {
+        match decl.kind {
+            hir::TopDeclKind::Fn(name, generics, args, ret, stmts) => {
+                let mut builder = Builder::new(&tables, ret);
+                for arg in args.iter() {
+                    let local = builder.var(&arg.0, &arg.1);
+                    builder.body.args.push(local);
+                }
+                for stmt in stmts.iter() {
+                    builder.stmt(stmt);
+                }
+                functions.push(Function {
+                    name,
+                    generics,
+                    body: builder.finish(),
+                    span: decl.span,
+                });
+            }
+            kind => decls.push(hir::TopDecl::new(kind, decl.span)),
+        }
+    }
+    Program { decls, functions }
+}
+
+/// Builds the body of one function or lambda, appending to `current` until
+/// a terminator moves on to another block.
+struct Builder<'a> {
+    tables: &'a Tables,
+    body: Body,
+    current: BlockId,
+    vars: BTreeMap<u32, Local>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(tables: &'a Tables, ret: Type) -> Self {
+        let mut builder = Builder {
+            tables,
+            body: Body {
+                locals: Vec::new(),
+                args: Vec::new(),
+                captures: Vec::new(),
+                ret,
+                blocks: Vec::new(),
+                lambdas: Vec::new(),
+            },
+            current: 0,
+            vars: BTreeMap::new(),
+        };
+        builder.current = builder.block();
+        builder
+    }
+
+    /// Ends the block that falls off the end of the body, which only unit
+    /// functions can do. Nothing was written there, so it gets no span.
+    fn finish(mut self) -> Body {
+        if self.body.ret == Type::Unit {
+            self.terminate(TerminatorKind::Return(None), Span::default());
+        } else {
+            self.terminate(TerminatorKind::Unreachable, Span::default());
         }
+        self.body
+    }
+
+    fn temp(&mut self, ty: Type) -> Local {
+        self.body.locals.push(LocalDecl { ty, var: None });
+        self.body.locals.len() - 1
+    }
+
+    /// The local holding `var`, which is made the first time it is seen.
+    fn var(&mut self, var: &Var, ty: &Type) -> Local {
+        if let Some(local) = self.vars.get(&var.0) {
+            return *local;
+        }
+        self.body.locals.push(LocalDecl {
+            ty: ty.clone(),
+            var: Some(var.clone()),
+        });
+        let local = self.body.locals.len() - 1;
+        self.vars.insert(var.0, local);
+        local
+    }
+
+    fn block(&mut self) -> BlockId {
+        self.body.blocks.push(Block {
+            statements: Vec::new(),
+            terminator: Terminator {
+                kind: TerminatorKind::Unreachable,
+                span: Span::default(),
+            },
+        });
+        self.body.blocks.len() - 1
+    }
+
+    fn push(&mut self, place: Place, value: Rvalue, span: Span) {
+        self.body.blocks[self.current]
+            .statements
+            .push(Statement { place, value, span });
+    }
+
+    fn terminate(&mut self, kind: TerminatorKind, span: Span) {
+        self.body.blocks[self.current].terminator = Terminator { kind, span };
+    }
+
+    fn goto(&mut self, target: BlockId, span: Span) {
+        self.terminate(TerminatorKind::Goto(target), span);
+        self.current = target;
+    }
+
+    fn stmts(&mut self, stmts: &[hir::Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &hir::Stmt) {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Assign(_, var, expr) => {
+                let value = self.rvalue(expr);
+                let local = self.var(var, &expr.ty);
+                self.push(Place::local(local), value, span);
+            }
+            StmtKind::Declare(var, ty) => {
+                self.var(var, ty);
+            }
+            StmtKind::SetField(object, field, expr) => {
+                let value = self.rvalue(expr);
+                let index = self.tables.field_index(&object.ty, field);
+                let place = self
+                    .place(object)
+                    .project(Projection::Field(index, expr.get_type()));
+                self.push(place, value, span);
+            }
+            StmtKind::Expr(expr) => match self.rvalue(expr) {
+                // nothing to do for a value that is only read
+                Rvalue::Use(_) => (),
+                value => {
+                    let temp = self.temp(expr.get_type());
+                    self.push(Place::local(temp), value, span);
+                }
+            },
+            StmtKind::If(cond, body1, body2) => {
+                let cond = self.operand(cond);
+                let succ = self.block();
+                let fail = body2.as_ref().map(|_| self.block());
+                let done = self.block();
+                self.terminate(
+                    TerminatorKind::Branch(cond, succ, fail.unwrap_or(done)),
+                    span,
+                );
+                self.current = succ;
+                self.stmts(body1);
+                self.goto(done, span);
+                if let (Some(fail), Some(body2)) = (fail, body2) {
+                    self.current = fail;
+                    self.stmts(body2);
+                    self.goto(done, span);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                let check = self.block();
+                let begin = self.block();
+                let done = self.block();
+                self.goto(check, span);
+                let cond = self.operand(cond);
+                self.terminate(TerminatorKind::Branch(cond, begin, done), span);
+                self.current = begin;
+                self.stmts(body);
+                self.goto(check, span);
+                self.current = done;
+            }
+            StmtKind::Match(expr, arms) => {
+                let name = match &expr.ty {
+                    Type::Enum(name) => name.clone(),
+                    _ => String::new(),
+                };
+                let place = self.place(expr);
+                let tag = self.temp(Type::Int);
+                self.push(Place::local(tag), Rvalue::Tag(place.clone(), expr.span), expr.span);
+
+                let mut cases: Vec<(usize, BlockId)> = Vec::new();
+                let mut otherwise = None;
+                let mut blocks = Vec::new();
+                for arm in arms {
+                    let block = self.block();
+                    match &arm.pattern.kind {
+                        PatternKind::Variant(variant, _) => {
+                            let tag = self.tables.variant(&name, variant).0;
+                            // later arms for the same variant can never run
+                            if cases.iter().all(|(other, _)| *other != tag) {
+                                cases.push((tag, block));
+                            }
+                        }
+                        PatternKind::Wildcard => {
+                            otherwise.get_or_insert(block);
+                        }
+                    }
+                    blocks.push(block);
+                }
+                let done = self.block();
+                self.terminate(
+                    TerminatorKind::Switch(
+                        Operand::Copy(Place::local(tag), expr.span),
+                        cases,
+                        otherwise,
+                    ),
+                    span,
+                );
+
+                for (arm, block) in arms.iter().zip(blocks) {
+                    self.current = block;
+                    if let PatternKind::Variant(variant, binders) = &arm.pattern.kind {
+                        let (tag, payload) = self.tables.variant(&name, variant);
+                        let payload = payload.to_vec();
+                        for (i, (binder, ty)) in binders.iter().zip(payload).enumerate() {
+                            if let Some(var) = binder {
+                                let local = self.var(var, &ty);
+                                let payload = place
+                                    .clone()
+                                    .project(Projection::Payload(tag, i, ty));
+                                self.push(
+                                    Place::local(local),
+                                    Rvalue::Use(Operand::Copy(payload, arm.pattern.span)),
+                                    arm.pattern.span,
+                                );
+                            }
+                        }
+                    }
+                    self.stmts(&arm.body);
+                    self.goto(done, span);
+                }
+                self.current = done;
+            }
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(expr) if expr.ty != Type::Unit => Some(self.operand(expr)),
+                    Some(expr) => {
+                        self.stmt(&hir::Stmt::new(StmtKind::Expr(expr.clone()), span));
+                        None
+                    }
+                    None => None,
+                };
+                self.terminate(TerminatorKind::Return(value), span);
+                // whatever follows can't be reached
+                self.current = self.block();
+            }
+        }
+    }
+
+    /// Lowers `expr` to something that can be used directly, storing it in a
+    /// temporary first if needed.
+    fn operand(&mut self, expr: &hir::Expr) -> Operand {
+        match self.rvalue(expr) {
+            Rvalue::Use(operand) => operand,
+            value => {
+                let temp = self.temp(expr.get_type());
+                self.push(Place::local(temp), value, expr.span);
+                Operand::Copy(Place::local(temp), expr.span)
+            }
+        }
+    }
+
+    /// Lowers `expr` to a place, using a temporary if it doesn't name one.
+    fn place(&mut self, expr: &hir::Expr) -> Place {
+        match &expr.kind {
+            ExprKind::Name(Name::Local(var)) => Place::local(self.var(var, &expr.ty)),
+            ExprKind::Field(object, field) => {
+                let index = self.tables.field_index(&object.ty, field);
+                self.place(object)
+                    .project(Projection::Field(index, expr.get_type()))
+            }
+            _ => match self.operand(expr) {
+                Operand::Copy(place, _) => place,
+                operand => {
+                    let temp = self.temp(expr.get_type());
+                    self.push(Place::local(temp), Rvalue::Use(operand), expr.span);
+                    Place::local(temp)
+                }
+            },
+        }
+    }
+
+    fn operands(&mut self, exprs: &[hir::Expr]) -> Vec<Operand> {
+        exprs.iter().map(|expr| self.operand(expr)).collect()
+    }
+
+    fn rvalue(&mut self, expr: &hir::Expr) -> Rvalue {
+        match &expr.kind {
+            ExprKind::Literal(literal) => Rvalue::Use(Operand::Constant(literal.clone())),
+            ExprKind::Name(Name::Global(name)) => {
+                Rvalue::Use(Operand::Function(name.clone(), expr.get_type()))
+            }
+            ExprKind::Name(Name::Local(_)) | ExprKind::Field(_, _) => {
+                Rvalue::Use(Operand::Copy(self.place(expr), expr.span))
+            }
+            ExprKind::Call(Name::Local(var), _, args) => {
+                let func_ty = Type::Fn(
+                    args.iter().map(|arg| arg.get_type()).collect(),
+                    Box::new(expr.get_type()),
+                    false,
+                );
+                let func = Operand::Copy(Place::local(self.var(var, &func_ty)), expr.span);
+                Rvalue::Call(Callee::Value(func), self.operands(args))
+            }
+            ExprKind::Call(Name::Global(func), type_args, args) => {
+                let callee = match self.tables.methods.get(func) {
+                    Some(class) if !self.tables.functions.contains(func) => Callee::Method(
+                        class.clone(),
+                        func.clone(),
+                        type_args.first().cloned().unwrap_or(Type::Unit),
+                    ),
+                    _ => Callee::Function(func.clone(), type_args.clone()),
+                };
+                Rvalue::Call(callee, self.operands(args))
+            }
+            ExprKind::Apply(func, args) => {
+                let func = self.operand(func);
+                Rvalue::Call(Callee::Value(func), self.operands(args))
+            }
+            ExprKind::Lambda(args, body) => self.lambda(args, body),
+            ExprKind::Struct(name, fields) => {
+                // evaluate the fields in the order they were written, then
+                // pass them in the order they were declared
+                let mut values = Vec::new();
+                for (field, expr) in fields {
+                    let index = self.tables.field_index(&Type::Struct(name.clone()), field);
+                    values.push((index, self.operand(expr)));
+                }
+                values.sort_by_key(|(index, _)| *index);
+                Rvalue::Struct(
+                    name.clone(),
+                    values.into_iter().map(|(_, value)| value).collect(),
+                )
+            }
+            ExprKind::Variant(name, variant, args) => {
+                let tag = self.tables.variant(name, variant).0;
+                Rvalue::Variant(name.clone(), tag, self.operands(args))
+            }
+            ExprKind::And(left, right) | ExprKind::Or(left, right) => {
+                // the right side only runs if the left one didn't decide the
+                // result
+                let result = self.temp(Type::Bool);
+                let left = self.operand(left);
+                self.push(Place::local(result), Rvalue::Use(left.clone()), expr.span);
+                let rest = self.block();
+                let done = self.block();
+                let kind = match &expr.kind {
+                    ExprKind::And(_, _) => TerminatorKind::Branch(left, rest, done),
+                    _ => TerminatorKind::Branch(left, done, rest),
+                };
+                self.terminate(kind, expr.span);
+                self.current = rest;
+                let right = self.operand(right);
+                self.push(Place::local(result), Rvalue::Use(right), expr.span);
+                self.goto(done, expr.span);
+                Rvalue::Use(Operand::Copy(Place::local(result), expr.span))
+            }
+            ExprKind::Unary(op, operand) => Rvalue::Unary(*op, self.operand(operand)),
+            ExprKind::Binary(op, left, right) => {
+                let left = self.operand(left);
+                Rvalue::Binary(*op, left, self.operand(right))
+            }
+        }
+    }
+
+    /// Lowers the body of a lambda into a body of its own, which starts by
+    /// copying the variables it uses from this one out of its environment.
+    fn lambda(&mut self, args: &[Arg<Var>], body: &hir::Expr) -> Rvalue {
+        let mut bound: Vec<u32> = args.iter().map(|arg| (arg.0).0).collect();
+        // the variables used, with their types and where they are first used
+        let mut used: Vec<(Var, Type, Span)> = Vec::new();
+        body.clone().map_exprs(&mut |expr| {
+            let (var, ty) = match &expr.kind {
+                ExprKind::Lambda(args, _) => {
+                    bound.extend(args.iter().map(|arg| (arg.0).0));
+                    return;
+                }
+                ExprKind::Name(Name::Local(var)) => (var, expr.get_type()),
+                ExprKind::Call(Name::Local(var), _, call_args) => (
+                    var,
+                    Type::Fn(
+                        call_args.iter().map(|arg| arg.get_type()).collect(),
+                        Box::new(expr.get_type()),
+                        false,
+                    ),
+                ),
+                _ => return,
+            };
+            if used.iter().all(|(other, _, _)| other != var) {
+                used.push((var.clone(), ty, expr.span));
+            }
+        });
+        let captures = used
+            .into_iter()
+            .filter(|(var, _, _)| !bound.contains(&var.0) && self.vars.contains_key(&var.0))
+            .collect::<Vec<_>>();
+
+        let mut builder = Builder::new(self.tables, body.get_type());
+        for (var, ty, _) in captures.iter() {
+            let local = builder.var(var, ty);
+            builder.body.captures.push(local);
+        }
+        for arg in args {
+            let local = builder.var(&arg.0, &arg.1);
+            builder.body.args.push(local);
+        }
+        let value = if body.ty == Type::Unit {
+            builder.stmt(&hir::Stmt::new(StmtKind::Expr(body.clone()), body.span));
+            None
+        } else {
+            Some(builder.operand(body))
+        };
+        builder.terminate(TerminatorKind::Return(value), body.span);
+
+        self.body.lambdas.push(builder.body);
+        let captures = captures
+            .iter()
+            .map(|(var, ty, span)| Operand::Copy(Place::local(self.var(var, ty)), *span))
+            .collect();
+        Rvalue::Closure(self.body.lambdas.len() - 1, captures)
     }
 }
@@ -0,0 +1,562 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+
+use ast::{self, Expr, ExprKind, Stmt, StmtKind, TopDecl, TopDeclKind};
+use common::{Arg, Field, Generic, Method, PatternKind, Type, Variant};
+use diagnostic::Diagnostic;
+use span::{SourceMap, Span};
+use syntax;
+
+/// The module imported into every other one.
+const PRELUDE: &str = "std.prelude";
+
+/// Where the modules imported by a program are found.
+pub struct SearchPath {
+    /// The directory of the file being compiled, which `import a.b` looks in
+    /// for `a/b.g`.
+    pub root: PathBuf,
+    /// The directory `import std.a` looks in for `a.g`.
+    pub std: PathBuf,
+}
+
+impl SearchPath {
+    fn file(&self, path: &[String]) -> PathBuf {
+        let (mut file, rest) = match path.split_first() {
+            Some((first, rest)) if first == "std" => (self.std.clone(), rest),
+            _ => (self.root.clone(), path),
+        };
+        for segment in rest {
+            file.push(segment);
+        }
+        file.set_extension("g");
+        file
+    }
+}
+
+struct Module {
+    /// The path the module is imported by, empty for the file being compiled.
+    path: Vec<String>,
+    decls: Vec<TopDecl>,
+    /// The modules this one imports, both by their full path and by the last
+    /// segment of it.
+    imports: HashMap<String, usize>,
+}
+
+/// A function, struct, enum or variant that can be named from other modules.
+struct Item {
+    symbol: String,
+    public: bool,
+    span: Span,
+    /// The enum a variant belongs to.
+    parent: Option<String>,
+}
+
+/// Parses the file being compiled and every module it imports, and joins them
+/// into one program. Functions, structs, enums and their variants are renamed
+/// after the module they are declared in, so `fn area` in `geometry/shapes.g`
+/// becomes `geometry.shapes.area`, and every use of them is rewritten to
+/// match. Externs and classes share one namespace.
+pub fn load(
+    sources: &mut SourceMap,
+    name: String,
+    contents: &str,
+    search: &SearchPath,
+) -> Result<ast::Program, Vec<Diagnostic>> {
+    let file = sources.add(name, contents);
+    let program = syntax::parse(file, contents)?;
+
+    let mut loader = Loader {
+        sources,
+        search,
+        modules: Vec::new(),
+        loaded: HashMap::new(),
+        errors: Vec::new(),
+    };
+    loader.modules.push(Module {
+        path: Vec::new(),
+        decls: program.0,
+        imports: HashMap::new(),
+    });
+    // modules are appended as they are first imported
+    let mut next = 0;
+    while next < loader.modules.len() {
+        loader.load_imports(next);
+        next += 1;
+    }
+    let Loader {
+        modules,
+        loaded,
+        mut errors,
+        ..
+    } = loader;
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let exports = modules
+        .iter()
+        .map(|module| {
+            let mut items = HashMap::new();
+            for decl in module.decls.iter() {
+                match &decl.kind {
+                    TopDeclKind::Fn(..) | TopDeclKind::Struct(..) | TopDeclKind::Enum(..) => {
+                        items.entry(decl.name().to_owned()).or_insert(Item {
+                            symbol: qualify(&module.path, decl.name()),
+                            public: decl.public,
+                            span: decl.span,
+                            parent: None,
+                        });
+                    }
+                    _ => (),
+                }
+                // variants are as public as their enum
+                if let TopDeclKind::Enum(_, variants) = &decl.kind {
+                    for Variant(name, _, span) in variants.iter() {
+                        items.entry(name.clone()).or_insert(Item {
+                            symbol: qualify(&module.path, name),
+                            public: decl.public,
+                            span: *span,
+                            parent: Some(decl.name().to_owned()),
+                        });
+                    }
+                }
+            }
+            items
+        }).collect::<Vec<_>>();
+
+    let mut modules = modules;
+    let mut decls = modules
+        .iter_mut()
+        .map(|module| mem::take(&mut module.decls))
+        .collect::<Vec<_>>();
+    let mut externs = HashMap::new();
+    let mut program = Vec::new();
+    // imported modules go first, so that their types are defined before the
+    // modules using them
+    for (index, module_decls) in decls.iter_mut().enumerate().rev() {
+        let mut scope = Scope {
+            modules: &modules,
+            exports: &exports,
+            module: index,
+            prelude: loaded.get(PRELUDE).cloned(),
+            scopes: Vec::new(),
+            params: Vec::new(),
+            errors: &mut errors,
+        };
+        for mut decl in module_decls.drain(..) {
+            scope.decl(&mut decl);
+            // the same extern may be declared by several modules
+            if let TopDeclKind::Extern(name, ty) = &decl.kind {
+                if externs.get(name) == Some(ty) {
+                    continue;
+                }
+                externs.insert(name.clone(), ty.clone());
+            }
+            program.push(decl);
+        }
+    }
+    if errors.is_empty() {
+        Ok(ast::Program(program))
+    } else {
+        Err(errors)
+    }
+}
+
+fn qualify(module: &[String], name: &str) -> String {
+    if module.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", module.join("."), name)
+    }
+}
+
+struct Loader<'a> {
+    sources: &'a mut SourceMap,
+    search: &'a SearchPath,
+    modules: Vec<Module>,
+    loaded: HashMap<String, usize>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Loader<'a> {
+    fn load_imports(&mut self, index: usize) {
+        let mut imports = self.modules[index]
+            .decls
+            .iter()
+            .filter_map(|decl| match &decl.kind {
+                TopDeclKind::Import(path) => Some((path.clone(), decl.span)),
+                _ => None,
+            }).collect::<Vec<_>>();
+        if self.modules[index].path.join(".") != PRELUDE {
+            let prelude = PRELUDE.split('.').map(str::to_owned).collect();
+            imports.push((prelude, Span::default()));
+        }
+        for (path, span) in imports {
+            if let Some(module) = self.module(&path, span) {
+                let imports = &mut self.modules[index].imports;
+                imports.insert(path.join("."), module);
+                imports.insert(path[path.len() - 1].clone(), module);
+            }
+        }
+    }
+
+    /// Finds the module imported as `path`, loading it if it hasn't been.
+    fn module(&mut self, path: &[String], span: Span) -> Option<usize> {
+        let name = path.join(".");
+        if let Some(&index) = self.loaded.get(&name) {
+            return Some(index);
+        }
+        let file = self.search.file(path);
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(_) => {
+                let mut diagnostic =
+                    Diagnostic::error(span, format!("cannot find module `{}`", name))
+                        .with_note(format!("there is no file at `{}`", file.display()));
+                if name == PRELUDE {
+                    diagnostic = diagnostic.with_help("use `--std-path` to say where `std` is");
+                }
+                self.errors.push(diagnostic);
+                return None;
+            }
+        };
+        let id = self.sources.add(file.to_string_lossy(), &contents);
+        // a module that doesn't parse is still recorded, so that its errors
+        // are reported once
+        let decls = match syntax::parse(id, &contents) {
+            Ok(program) => program.0,
+            Err(errors) => {
+                self.errors.extend(errors);
+                Vec::new()
+            }
+        };
+        let index = self.modules.len();
+        self.modules.push(Module {
+            path: path.to_vec(),
+            decls,
+            imports: HashMap::new(),
+        });
+        self.loaded.insert(name, index);
+        Some(index)
+    }
+}
+
+/// Rewrites the names used in one module to the symbols they refer to.
+struct Scope<'a> {
+    modules: &'a [Module],
+    exports: &'a [HashMap<String, Item>],
+    module: usize,
+    prelude: Option<usize>,
+    /// The variables in scope in the function being rewritten, innermost
+    /// block last, which hide top-level names and imported modules.
+    scopes: Vec<HashSet<String>>,
+    /// The generic parameters of the declaration being rewritten.
+    params: Vec<String>,
+    errors: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> Scope<'a> {
+    fn bind(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_owned());
+        }
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    /// Rewrites the statements of a block, whose variables go out of scope at
+    /// its end.
+    fn block(&mut self, body: &mut [Stmt]) {
+        self.scopes.push(HashSet::new());
+        for stmt in body.iter_mut() {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    /// The symbol of a name used without a module: one declared in this
+    /// module, or else a public one from the prelude.
+    fn item(&self, name: &str) -> Option<String> {
+        if let Some(item) = self.exports[self.module].get(name) {
+            return Some(item.symbol.clone());
+        }
+        self.prelude
+            .and_then(|prelude| self.exports[prelude].get(name))
+            .filter(|item| item.public)
+            .map(|item| item.symbol.clone())
+    }
+
+    /// The symbol of a path like `module.name`, or `None` if it doesn't start
+    /// with an imported module.
+    fn path(&mut self, path: &[String], span: Span) -> Option<String> {
+        let (name, module) = path.split_last()?;
+        let &index = self.modules[self.module].imports.get(&module.join("."))?;
+        let module = self.modules[index].path.join(".");
+        match self.exports[index].get(name) {
+            Some(item) => {
+                if !item.public && index != self.module {
+                    let help = match &item.parent {
+                        Some(parent) => format!(
+                            "declare `{}` with `pub` to use its variants from other modules",
+                            parent
+                        ),
+                        None => "declare it with `pub` to use it from other modules".to_owned(),
+                    };
+                    self.errors.push(
+                        Diagnostic::error(
+                            span,
+                            format!("`{}` is private to module `{}`", name, module),
+                        ).with_secondary(item.span, "declared here")
+                        .with_help(help),
+                    );
+                }
+                Some(item.symbol.clone())
+            }
+            None => {
+                self.errors.push(Diagnostic::error(
+                    span,
+                    format!("cannot find `{}` in module `{}`", name, module),
+                ));
+                Some(path.join("."))
+            }
+        }
+    }
+
+    /// Rewrites the name of a type or variant, which may be a path.
+    fn item_name(&mut self, name: &mut String, span: Span) {
+        let path = name.split('.').map(str::to_owned).collect::<Vec<_>>();
+        let symbol = if path.len() > 1 {
+            let symbol = self.path(&path, span);
+            if symbol.is_none() {
+                let module = path[..path.len() - 1].join(".");
+                self.errors.push(
+                    Diagnostic::error(span, format!("cannot find module `{}`", module))
+                        .with_help(format!("add `import {}` to use it", module)),
+                );
+            }
+            symbol
+        } else {
+            self.item(name)
+        };
+        if let Some(symbol) = symbol {
+            *name = symbol;
+        }
+    }
+
+    fn type_name(&mut self, name: &mut String, span: Span) {
+        if !self.params.contains(name) {
+            self.item_name(name, span);
+        }
+    }
+
+    fn ty(&mut self, ty: &mut Type, span: Span) {
+        match ty {
+            Type::Struct(name) => self.type_name(name, span),
+            Type::Fn(args, ret, _) => {
+                for arg in args.iter_mut() {
+                    self.ty(arg, span);
+                }
+                self.ty(ret, span);
+            }
+            _ => (),
+        }
+    }
+
+    fn decl(&mut self, decl: &mut TopDecl) {
+        let span = decl.span;
+        let symbol = qualify(&self.modules[self.module].path, decl.name());
+        self.scopes.clear();
+        self.params.clear();
+        match &mut decl.kind {
+            TopDeclKind::Import(_) => (),
+            TopDeclKind::Extern(_, ty) => self.ty(ty, span),
+            TopDeclKind::Fn(name, generics, args, ty, body) => {
+                *name = symbol;
+                self.function(generics, args, ty, body, span);
+            }
+            TopDeclKind::Struct(name, fields) => {
+                *name = symbol;
+                for Field(_, ty, span) in fields.iter_mut() {
+                    self.ty(ty, *span);
+                }
+            }
+            TopDeclKind::Enum(name, variants) => {
+                *name = symbol;
+                for Variant(variant, payload, span) in variants.iter_mut() {
+                    *variant = qualify(&self.modules[self.module].path, variant);
+                    for ty in payload.iter_mut() {
+                        self.ty(ty, *span);
+                    }
+                }
+            }
+            TopDeclKind::Class(_, param, methods) => {
+                self.params.push(param.clone());
+                for Method(_, args, ty, span) in methods.iter_mut() {
+                    for Arg(_, ty, span) in args.iter_mut() {
+                        self.ty(ty, *span);
+                    }
+                    self.ty(ty, *span);
+                }
+            }
+            // methods keep their names, which come from the class
+            TopDeclKind::Instance(_, ty, methods) => {
+                self.ty(ty, span);
+                for method in methods.iter_mut() {
+                    let span = method.span;
+                    if let TopDeclKind::Fn(_, generics, args, ty, body) = &mut method.kind {
+                        self.scopes.clear();
+                        self.params.clear();
+                        self.function(generics, args, ty, body, span);
+                    }
+                }
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        generics: &[Generic],
+        args: &mut [Arg],
+        ty: &mut Option<Type>,
+        body: &mut [Stmt],
+        span: Span,
+    ) {
+        self.params = Generic::names(generics);
+        self.scopes.push(HashSet::new());
+        for Arg(name, ty, span) in args.iter_mut() {
+            self.ty(ty, *span);
+            self.bind(name);
+        }
+        if let Some(ty) = ty {
+            self.ty(ty, span);
+        }
+        self.block(body);
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::Assign(_, name, expr) => {
+                self.expr(expr);
+                self.bind(name);
+            }
+            StmtKind::Declare(name, ty) => {
+                self.ty(ty, stmt.span);
+                self.bind(name);
+            }
+            StmtKind::SetField(object, _, expr) => {
+                self.expr(object);
+                self.expr(expr);
+            }
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::If(cond, body1, body2) => {
+                self.expr(cond);
+                self.block(body1);
+                if let Some(body) = body2 {
+                    self.block(body);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::Match(expr, arms) => {
+                self.expr(expr);
+                for arm in arms.iter_mut() {
+                    self.scopes.push(HashSet::new());
+                    let span = arm.pattern.span;
+                    if let PatternKind::Variant(variant, names) = &mut arm.pattern.kind {
+                        self.item_name(variant, span);
+                        for name in names.iter() {
+                            self.bind(name);
+                        }
+                    }
+                    self.block(&mut arm.body);
+                    self.scopes.pop();
+                }
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        let span = expr.span;
+        // `module.name`, unless `module` is a variable whose field is read
+        if let Some(path) = expr.path() {
+            if path.len() > 1 && !self.is_local(&path[0]) {
+                if let Some(symbol) = self.path(&path, span) {
+                    expr.kind = ExprKind::Name(symbol);
+                    return;
+                }
+            }
+        }
+        match &mut expr.kind {
+            ExprKind::Call(name, args) => {
+                if !self.is_local(name) {
+                    if let Some(symbol) = self.item(name) {
+                        *name = symbol;
+                    }
+                }
+                for arg in args.iter_mut() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Apply(func, args) => {
+                self.expr(func);
+                for arg in args.iter_mut() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Lambda(args, body) => {
+                self.scopes.push(HashSet::new());
+                for Arg(name, ty, span) in args.iter_mut() {
+                    self.ty(ty, *span);
+                    self.bind(name);
+                }
+                self.expr(body);
+                self.scopes.pop();
+            }
+            ExprKind::Literal(_) => (),
+            ExprKind::Name(name) => {
+                if !self.is_local(name) {
+                    if let Some(symbol) = self.item(name) {
+                        *name = symbol;
+                    }
+                }
+            }
+            ExprKind::Dot(object, _) => self.expr(object),
+            ExprKind::Struct(name, fields) => {
+                self.type_name(name, span);
+                for (_, expr) in fields.iter_mut() {
+                    self.expr(expr);
+                }
+            }
+            ExprKind::And(left, right)
+            | ExprKind::Or(left, right)
+            | ExprKind::Binary(_, left, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Unary(_, expr) => self.expr(expr),
+        }
+        // `module.f(x)` calls `f` directly once `module.f` is resolved
+        let call = match &mut expr.kind {
+            ExprKind::Apply(func, args) => match &func.kind {
+                ExprKind::Name(name) => Some((name.clone(), mem::take(args))),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some((name, args)) = call {
+            expr.kind = ExprKind::Call(name, args);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::cmp;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::process;
+
+use ast::{self, Stmt, StmtKind, TopDeclKind};
+use common::Type;
+use dataflow;
+use diagnostic::{Diagnostic, Severity};
+use hir::{self, IntoHir};
+use interp::{Frame, Interpreter, Stop};
+use lexer::{Lexer, Token};
+use mir;
+use module::{self, SearchPath};
+use span::{FileId, SourceMap, Span};
+use typeck::TypeStack;
+
+const HELP: &str = "\
+Enter declarations to add them, or statements to run them and print the value
+of the last one. Blocks end at an empty line.
+
+:type <expr>   print the type of an expression without running it
+:mir [<expr>]  print the MIR of the functions declared so far, or of an expression
+:help          print this message
+:quit          leave the REPL";
+
+/// Reads lines from stdin and runs them until it ends or `:quit` is entered.
+pub fn run(search: SearchPath) -> io::Result<()> {
+    let mut repl = Repl::new(search);
+    // the prelude is loaded with the first input, so load it up front to
+    // report any problems with it right away
+    if let Err(errors) = repl.declare("") {
+        repl.report(&errors);
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut buffer = String::new();
+    loop {
+        print!("{}", if buffer.is_empty() { ">> " } else { ".. " });
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if buffer.is_empty() {
+            let command = line.trim();
+            if command.is_empty() {
+                continue;
+            }
+            if command.starts_with(':') {
+                if !repl.command(command) {
+                    return Ok(());
+                }
+                continue;
+            }
+        } else {
+            buffer.push('\n');
+        }
+        buffer += &line;
+        // keep reading until the blocks are closed by an empty line
+        if Lexer::new(&buffer).is_incomplete() {
+            continue;
+        }
+        let input = mem::take(&mut buffer);
+        let result = if is_declaration(&input) {
+            repl.declare(&input)
+        } else {
+            repl.evaluate(&input)
+        };
+        if let Err(errors) = result {
+            repl.report(&errors);
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// Whether an input declares something, rather than being statements to run.
+fn is_declaration(input: &str) -> bool {
+    let mut tokens = Lexer::new(input).filter_map(|token| token.ok().map(|token| token.1));
+    match (tokens.next(), tokens.next()) {
+        // `fn` also starts lambdas
+        (Some(Token::KeywordFn), Some(Token::Ident(_))) => true,
+        (Some(Token::KeywordPub), _)
+        | (Some(Token::KeywordExtern), _)
+        | (Some(Token::KeywordStruct), _)
+        | (Some(Token::KeywordEnum), _)
+        | (Some(Token::KeywordClass), _)
+        | (Some(Token::KeywordInstance), _)
+        | (Some(Token::KeywordImport), _) => true,
+        _ => false,
+    }
+}
+
+/// A program typechecked one input at a time, whose declarations and
+/// variables carry over from one input to the next.
+struct Repl {
+    sources: SourceMap,
+    search: SearchPath,
+    context: hir::Context,
+    stack: TypeStack,
+    interpreter: Interpreter,
+    /// The values of the variables from earlier inputs.
+    frame: Frame,
+    /// Everything declared so far.
+    program: hir::Program,
+    /// The modules that have been declared already. Every input is loaded
+    /// as a module of its own, which imports them again.
+    modules: BTreeSet<String>,
+    /// The modules imported by earlier inputs, which are imported into the
+    /// later ones too so that their names keep working.
+    imports: BTreeSet<String>,
+    inputs: usize,
+}
+
+/// An input of statements after typechecking, as the body of a function.
+struct Checked {
+    context: hir::Context,
+    stack: TypeStack,
+    function: hir::TopDecl,
+    wrapper: Wrapper,
+}
+
+/// The files of an input of statements and of the function it is wrapped
+/// in, which indents every line and adds one in front.
+#[derive(Clone, Copy)]
+struct Wrapper {
+    input: FileId,
+    file: FileId,
+}
+
+impl Wrapper {
+    /// Moves the spans of `diagnostic` from the wrapper back to the input,
+    /// so that it points at what was entered.
+    fn unwrap(self, sources: &SourceMap, mut diagnostic: Diagnostic) -> Diagnostic {
+        let labels = diagnostic.primary.iter_mut().chain(diagnostic.secondary.iter_mut());
+        for label in labels {
+            if label.span.file == self.file {
+                label.span = Span::new(
+                    self.input,
+                    self.offset(sources, label.span.start),
+                    self.offset(sources, label.span.end),
+                );
+            }
+        }
+        diagnostic
+    }
+
+    fn offset(self, sources: &SourceMap, offset: usize) -> usize {
+        let (file, input) = (sources.get(self.file), sources.get(self.input));
+        let line = file.line_index(offset);
+        if line == 0 {
+            return 0;
+        }
+        let column = (offset - file.line_start(line)).saturating_sub(4);
+        cmp::min(input.line_start(line - 1) + column, input.source.len())
+    }
+}
+
+impl Repl {
+    fn new(search: SearchPath) -> Self {
+        let mut context = hir::Context::default();
+        // the variables of earlier inputs live here
+        context.resolver.scope();
+        Repl {
+            sources: SourceMap::default(),
+            search,
+            context,
+            stack: TypeStack::default(),
+            interpreter: Interpreter::new(&hir::Program(Vec::new())),
+            frame: Frame::default(),
+            program: hir::Program(Vec::new()),
+            modules: BTreeSet::new(),
+            imports: BTreeSet::new(),
+            inputs: 0,
+        }
+    }
+
+    fn report(&self, diagnostics: &[Diagnostic]) {
+        for diagnostic in diagnostics {
+            eprintln!("{}", diagnostic.render(&self.sources));
+        }
+    }
+
+    /// Runs a `:` command, returning whether to keep going.
+    fn command(&mut self, command: &str) -> bool {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        let result = match name {
+            ":q" | ":quit" => return false,
+            ":h" | ":help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ":t" | ":type" if !argument.is_empty() => self.check(argument).map(|checked| {
+                if let hir::TopDeclKind::Fn(_, _, _, ty, _) = &checked.function.kind {
+                    println!("{}", ty);
+                }
+            }),
+            ":mir" if argument.is_empty() => {
+                print!("{}", mir::lower(self.program.clone()));
+                Ok(())
+            }
+            ":mir" => self.check(argument).map(|checked| {
+                let name = checked.function.name().to_owned();
+                let mut program = self.program.clone();
+                program.0.push(checked.function);
+                let program = mir::lower(program);
+                for function in program.functions.iter().filter(|function| function.name == name) {
+                    print!("{}", function);
+                }
+            }),
+            _ => Err(vec![Diagnostic::unspanned(format!(
+                "unknown command `{}`, enter `:help` for a list",
+                command
+            ))]),
+        };
+        if let Err(errors) = result {
+            self.report(&errors);
+        }
+        true
+    }
+
+    /// Loads an input as a module, returning its declarations along with
+    /// those of the modules it imports for the first time, and the names of
+    /// those modules.
+    fn load(&mut self, input: &str) -> Result<(Vec<ast::TopDecl>, Vec<String>), Vec<Diagnostic>> {
+        self.inputs += 1;
+        let name = format!("<repl:{}>", self.inputs);
+        // the imports go after the input, so that its lines keep their numbers
+        let mut source = input.to_owned();
+        for path in self.imports.iter() {
+            if !source.is_empty() && !source.ends_with('\n') {
+                source.push('\n');
+            }
+            source += &format!("import {}\n", path);
+        }
+        let program = module::load(&mut self.sources, name.clone(), &source, &self.search)?;
+        let mut modules = Vec::new();
+        let mut decls = Vec::new();
+        for decl in program.0 {
+            let file = &self.sources.get(decl.span.file).name;
+            if *file != name {
+                if self.modules.contains(file) {
+                    continue;
+                }
+                modules.push(file.clone());
+            }
+            decls.push(decl);
+        }
+        Ok((decls, modules))
+    }
+
+    /// Adds the declarations of an input to the program.
+    fn declare(&mut self, input: &str) -> Result<(), Vec<Diagnostic>> {
+        let (decls, modules) = self.load(input)?;
+        let imports = decls
+            .iter()
+            .filter_map(|decl| match &decl.kind {
+                TopDeclKind::Import(path) => Some(path.join(".")),
+                _ => None,
+            }).collect::<Vec<_>>();
+        let mut context = self.context.clone();
+        let mut stack = self.stack.clone();
+        let mut hir = ast::Program(decls).into_hir(&mut context);
+        if !context.errors().is_empty() {
+            return Err(context.errors().to_vec());
+        }
+        hir.typeck(&mut stack)?;
+
+        // lower everything, so that calls to the earlier functions are
+        // lowered like they are in a whole program, but only check the new
+        // functions
+        let names = hir.0.iter().map(|decl| decl.name().to_owned()).collect::<BTreeSet<_>>();
+        let mut program = self.program.clone();
+        program.0.extend(hir.0.iter().cloned());
+        let mut lowered = mir::lower(program.clone());
+        lowered.functions.retain(|function| names.contains(&function.name));
+        let (errors, warnings): (Vec<_>, Vec<_>) = dataflow::check(&lowered)
+            .into_iter()
+            .partition(|diagnostic| diagnostic.severity == Severity::Error);
+        self.report(&warnings);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for decl in hir.0.iter() {
+            self.interpreter.declare(decl);
+        }
+        self.context = context;
+        self.stack = stack;
+        self.program = program;
+        self.modules.extend(modules);
+        self.imports.extend(imports);
+        Ok(())
+    }
+
+    /// Typechecks an input of statements as the body of a function, which
+    /// returns the value of the last one if it is an expression.
+    fn check(&mut self, input: &str) -> Result<Checked, Vec<Diagnostic>> {
+        // the wrapper is loaded right after the input is added
+        let input = self.sources.add(format!("<repl:{}>", self.inputs + 1), input);
+        let wrapper = Wrapper {
+            input,
+            file: FileId(input.0 + 1),
+        };
+        self.check_wrapped(wrapper).map_err(|errors| {
+            errors.into_iter().map(|err| wrapper.unwrap(&self.sources, err)).collect()
+        })
+    }
+
+    fn check_wrapped(&mut self, wrapper: Wrapper) -> Result<Checked, Vec<Diagnostic>> {
+        let input = self.sources.get(wrapper.input).source.clone();
+        let name = format!("__repl{}", self.inputs + 1);
+        let mut source = format!("fn {}() =\n", name);
+        for line in input.lines() {
+            source += "    ";
+            source += line;
+            source += "\n";
+        }
+        // the prelude was declared at the start, so this is the only
+        // declaration
+        let (mut decls, _) = self.load(&source)?;
+        debug_assert!(self.sources.get(wrapper.file).source.starts_with(&source));
+        for decl in decls.iter_mut() {
+            if let TopDeclKind::Fn(_, _, _, _, body) = &mut decl.kind {
+                if let Some(last) = body.pop() {
+                    body.push(match last.kind {
+                        StmtKind::Expr(expr) => Stmt::new(StmtKind::Return(Some(expr)), last.span),
+                        kind => Stmt::new(kind, last.span),
+                    });
+                }
+            }
+        }
+
+        let mut context = self.context.clone();
+        let mut stack = self.stack.clone();
+        let mut hir = ast::Program(decls).into_hir(&mut context);
+        if !context.errors().is_empty() {
+            return Err(context.errors().to_vec());
+        }
+        hir.typeck(&mut stack)?;
+        let function = hir.0.pop().expect("the input is wrapped in a function");
+
+        // the variables of the input stay around for the next ones
+        if let hir::TopDeclKind::Fn(_, _, _, _, body) = &function.kind {
+            for stmt in body.iter() {
+                let (var, ty) = match &stmt.kind {
+                    hir::StmtKind::Assign(false, var, expr) => (var, &expr.ty),
+                    hir::StmtKind::Declare(var, ty) => (var, ty),
+                    _ => continue,
+                };
+                context.resolver.rebind(var);
+                stack.local(var, ty);
+            }
+        }
+        Ok(Checked {
+            context,
+            stack,
+            function,
+            wrapper,
+        })
+    }
+
+    /// Runs an input of statements, printing the value of the last one.
+    fn evaluate(&mut self, input: &str) -> Result<(), Vec<Diagnostic>> {
+        let checked = self.check(input)?;
+        self.context = checked.context;
+        self.stack = checked.stack;
+        let (ty, body) = match &checked.function.kind {
+            hir::TopDeclKind::Fn(_, _, _, ty, body) => (ty, body),
+            _ => unreachable!(),
+        };
+        match self.interpreter.exec(&mut self.frame, body) {
+            Ok(Some(value)) => {
+                if *ty != Type::Unit {
+                    println!("{}: {}", value, ty);
+                }
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(Stop::Exit(code)) => {
+                let _ = io::stdout().flush();
+                process::exit(code)
+            }
+            Err(Stop::Error(err)) => Err(vec![checked.wrapper.unwrap(&self.sources, err)]),
+        }
+    }
+}
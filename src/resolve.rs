@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use common::next_int;
+use diagnostic::Diagnostic;
+use hir::{Name, Var};
+use span::Span;
+
+/// Works out what every name in a program refers to. Each variable binding
+/// gets a `Var` of its own, so later passes never have to deal with scopes or
+/// shadowing.
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    /// Every top-level name with where it was declared, to find duplicates.
+    declared: BTreeMap<String, Span>,
+    /// The top-level names that can be used as values.
+    globals: BTreeSet<String>,
+    scopes: Vec<BTreeMap<String, Var>>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver {
+            declared: BTreeMap::new(),
+            // the methods of the classes built into typeck
+            globals: vec!["add", "eq"].into_iter().map(str::to_owned).collect(),
+            scopes: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Resolver {
+    /// Declares a top-level name, which `value` says can be used in
+    /// expressions rather than only as a type.
+    pub fn declare(&mut self, name: impl AsRef<str>, span: Span, value: bool) {
+        let name = name.as_ref();
+        if let Some(previous) = self.declared.get(name) {
+            self.errors.push(
+                Diagnostic::error(span, format!("name `{}` is defined more than once", name))
+                    .with_label("redefined here")
+                    .with_secondary(*previous, "previous definition here"),
+            );
+            return;
+        }
+        self.declared.insert(name.to_owned(), span);
+        if value {
+            self.globals.insert(name.to_owned());
+        }
+    }
+
+    /// Declares the name of a class method, which instances can share.
+    pub fn declare_method(&mut self, name: impl AsRef<str>) {
+        self.globals.insert(name.as_ref().to_owned());
+    }
+
+    pub fn scope(&mut self) {
+        self.scopes.push(BTreeMap::new());
+    }
+
+    pub fn unscope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Makes a new variable, hiding any other with the same name.
+    pub fn bind(&mut self, name: impl AsRef<str>) -> Var {
+        let var = Var(next_int(), name.as_ref().to_owned());
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(var.1.clone(), var.clone());
+        }
+        var
+    }
+
+    /// Brings a variable made earlier back into the innermost scope, like
+    /// the REPL does with the variables of the lines before.
+    pub fn rebind(&mut self, var: &Var) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(var.1.clone(), var.clone());
+        }
+    }
+
+    /// Binds a list of names that are introduced together, like the arguments
+    /// of a function, which have to be distinct.
+    pub fn bind_all(&mut self, names: &[(&str, Span)]) -> Vec<Var> {
+        let mut seen: BTreeMap<&str, Span> = BTreeMap::new();
+        for (name, span) in names {
+            if let Some(previous) = seen.get(name) {
+                self.errors.push(
+                    Diagnostic::error(
+                        *span,
+                        format!("identifier `{}` is bound more than once in the same list", name),
+                    ).with_label("used as a name again")
+                    .with_secondary(*previous, "first bound here"),
+                );
+            }
+            seen.insert(name, *span);
+        }
+        names.iter().map(|(name, _)| self.bind(name)).collect()
+    }
+
+    /// The variable in scope named `name`, if there is one.
+    pub fn local(&self, name: impl AsRef<str>) -> Option<Var> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name.as_ref()))
+            .cloned()
+    }
+
+    /// The variable assigned to by `name = ...`.
+    pub fn assign(&mut self, name: impl AsRef<str>, span: Span) -> Var {
+        match self.local(&name) {
+            Some(var) => var,
+            None => {
+                let name = name.as_ref();
+                self.errors.push(
+                    Diagnostic::error(
+                        span,
+                        format!("cannot assign to undeclared variable `{}`", name),
+                    ).with_help(format!("use `let {} = ...` to declare it", name)),
+                );
+                self.bind(name)
+            }
+        }
+    }
+
+    /// What `name` refers to when used as a value: the innermost variable with
+    /// that name, or else a top-level function.
+    pub fn resolve(&mut self, name: impl AsRef<str>, span: Span) -> Name {
+        self.lookup(name.as_ref(), span, "value")
+    }
+
+    /// What the function called `name` refers to, which is looked up like a
+    /// value.
+    pub fn resolve_call(&mut self, name: impl AsRef<str>, span: Span) -> Name {
+        self.lookup(name.as_ref(), span, "function")
+    }
+
+    fn lookup(&mut self, name: &str, span: Span, what: &str) -> Name {
+        if let Some(var) = self.local(name) {
+            return Name::Local(var);
+        }
+        if !self.globals.contains(name) {
+            self.errors.push(
+                Diagnostic::error(span, format!("cannot find {} `{}`", what, name))
+                    .with_label("not found in this scope"),
+            );
+        }
+        Name::Global(name.to_owned())
+    }
+
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadowing_makes_a_new_variable() {
+        let mut resolver = Resolver::default();
+        let span = Span::default();
+        resolver.declare("x", span, true);
+        assert_eq!(resolver.resolve("x", span), Name::Global("x".to_owned()));
+
+        resolver.scope();
+        let outer = resolver.bind("x");
+        assert_eq!(resolver.resolve("x", span), Name::Local(outer.clone()));
+        resolver.scope();
+        let inner = resolver.bind("x");
+        assert_ne!(inner, outer);
+        assert_eq!(resolver.assign("x", span), inner);
+        resolver.unscope();
+        assert_eq!(resolver.resolve("x", span), Name::Local(outer));
+        resolver.unscope();
+        assert_eq!(resolver.resolve("x", span), Name::Global("x".to_owned()));
+        assert!(resolver.errors().is_empty());
+    }
+
+    #[test]
+    fn unknown_and_duplicate_names_are_reported() {
+        let mut resolver = Resolver::default();
+        let span = Span::default();
+        resolver.declare("f", span, true);
+        resolver.declare("f", span, true);
+        resolver.scope();
+        resolver.resolve("y", span);
+        resolver.assign("z", span);
+        resolver.bind_all(&[("a", span), ("a", span)]);
+        let messages = resolver
+            .errors()
+            .iter()
+            .map(|err| err.message.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "name `f` is defined more than once",
+                "cannot find value `y`",
+                "cannot assign to undeclared variable `z`",
+                "identifier `a` is bound more than once in the same list",
+            ]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use lalrpop_util::ParseError;
+
+use ast;
+use diagnostic::Diagnostic;
+use lexer::{LexError, Lexer, Token, UNEXPECTED_INDENT};
+use parser::ProgramParser;
+use span::{FileId, Span};
+
+/// Lexes and parses a whole file, collecting every lexical and syntax error
+/// instead of stopping at the first one.
+pub fn parse(file: FileId, source: &str) -> Result<ast::Program, Vec<Diagnostic>> {
+    // the lexer recovers from its errors, so collect all of them and still
+    // parse the tokens around them
+    let mut errors = Vec::new();
+    let tokens = Lexer::new(source)
+        .filter(|token| match token {
+            Ok(_) => true,
+            Err(err) => {
+                errors.push(lex_error(file, err));
+                false
+            }
+        }).collect::<Vec<_>>();
+
+    let mut recovered = Vec::new();
+    let result = ProgramParser::new().parse(file, &mut recovered, tokens);
+    errors.extend(
+        recovered
+            .into_iter()
+            .filter(|recovery| !at_lex_error(&recovery.error))
+            .map(|recovery| syntax_error(file, recovery.error)),
+    );
+    match result {
+        Ok(program) if errors.is_empty() => Ok(program),
+        Ok(_) => Err(cleanup(source, errors)),
+        Err(err) => {
+            if !at_lex_error(&err) {
+                errors.push(syntax_error(file, err));
+            }
+            Err(cleanup(source, errors))
+        }
+    }
+}
+
+/// Sorts the errors and drops the unexpected indentation reported for the
+/// body of a line that already had an error.
+fn cleanup(source: &str, mut errors: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    errors.sort_by_key(|err| err.span().start);
+    let mut result: Vec<Diagnostic> = Vec::new();
+    for err in errors {
+        if &*err.message == UNEXPECTED_INDENT {
+            if let Some(previous) = result.last() {
+                let between = &source[previous.span().start..err.span().start];
+                if between.lines().skip(1).all(|line| line.trim().is_empty()) {
+                    continue;
+                }
+            }
+        }
+        result.push(err);
+    }
+    result
+}
+
+/// Whether a syntax error is about the stand-in for a character the lexer
+/// couldn't read, which it has reported already.
+fn at_lex_error(err: &ParseError<usize, Token, LexError>) -> bool {
+    matches!(
+        err,
+        ParseError::UnrecognizedToken {
+            token: Some((_, Token::Error, _)),
+            ..
+        } | ParseError::ExtraToken {
+            token: (_, Token::Error, _),
+        }
+    )
+}
+
+fn lex_error(file: FileId, err: &LexError) -> Diagnostic {
+    let diagnostic = Diagnostic::error(Span::new(file, err.start, err.end), err.message.clone());
+    if err.message == UNEXPECTED_INDENT {
+        diagnostic.with_note("this line is indented further than the one before it")
+    } else {
+        diagnostic
+    }
+}
+
+fn syntax_error(file: FileId, err: ParseError<usize, Token, LexError>) -> Diagnostic {
+    match err {
+        ParseError::InvalidToken { location } => {
+            Diagnostic::error(Span::new(file, location, location + 1), "invalid token")
+        }
+        ParseError::UnrecognizedToken { token, expected } => {
+            let (span, found) = match token {
+                Some((start, token, end)) => (Span::new(file, start, end), token.to_string()),
+                None => (Span::new(file, 0, 0), "end of file".to_owned()),
+            };
+            let mut expected = expected
+                .iter()
+                .map(|name| describe_terminal(name))
+                .collect::<Vec<_>>();
+            expected.dedup();
+            let message = match expected.len() {
+                0 => format!("unexpected {}", found),
+                1 => format!("expected {}, found {}", expected[0], found),
+                _ => format!("expected one of {}, found {}", expected.join(", "), found),
+            };
+            Diagnostic::error(span, message).with_label(format!("unexpected {}", found))
+        }
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => Diagnostic::error(Span::new(file, start, end), format!("unexpected {}", token))
+            .with_label("expected end of file"),
+        ParseError::User { error } => lex_error(file, &error),
+    }
+}
+
+/// Maps a terminal name from the grammar to what the user typed or sees.
+fn describe_terminal(name: &str) -> String {
+    let name = name.trim_matches('"');
+    match name {
+        "Ident" => "identifier".to_owned(),
+        "Integer" => "integer literal".to_owned(),
+        "String" => "string literal".to_owned(),
+        "Char" => "character literal".to_owned(),
+        "Newline" => "newline".to_owned(),
+        "Indent" => "indented block".to_owned(),
+        "Dedent" => "end of block".to_owned(),
+        "EOF" => "end of file".to_owned(),
+        _ => format!("`{}`", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::{ExprKind, StmtKind, TopDeclKind};
+
+    fn text(source: &str, span: Span) -> &str {
+        &source[span.start..span.end]
+    }
+
+    #[test]
+    fn nodes_span_their_source() {
+        let source = "fn main(): int =\n    let x = 1\n    return f(x + 22, (3))\n";
+        let program = parse(FileId(0), source).unwrap();
+        let decl = &program.0[0];
+        assert!(text(source, decl.span).starts_with("fn main(): int ="));
+        let body = match &decl.kind {
+            TopDeclKind::Fn(_, _, _, _, body) => body,
+            _ => panic!("expected a function"),
+        };
+        assert_eq!(text(source, body[0].span), "let x = 1");
+        assert_eq!(text(source, body[1].span), "return f(x + 22, (3))");
+        let args = match &body[1].kind {
+            StmtKind::Return(Some(expr)) => {
+                assert_eq!(text(source, expr.span), "f(x + 22, (3))");
+                match &expr.kind {
+                    ExprKind::Call(_, args) => args,
+                    _ => panic!("expected a call"),
+                }
+            }
+            _ => panic!("expected a return"),
+        };
+        assert_eq!(text(source, args[0].span), "x + 22");
+        assert_eq!(text(source, args[1].span), "3");
+        match &args[0].kind {
+            ExprKind::Binary(_, left, right) => {
+                assert_eq!(text(source, left.span), "x");
+                assert_eq!(text(source, right.span), "22");
+            }
+            _ => panic!("expected a binary expression"),
+        }
+    }
+}
@@ -1,319 +1,1358 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use failure::Error;
-
-use common::{next_int, Type, Typed};
-use mir;
+use common::{
+    next_int, Arg, BinOp, Field, Generic, Method, PatternKind, Type, Typed, UnOp, Variant,
+};
+use diagnostic::Diagnostic;
+use hir;
+use span::Span;
 
 pub type Substitution = HashMap<u32, Type>;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub struct Constraint(pub Type, pub Type);
+/// The classes that typeck declares itself, which have no source to point at.
+const BUILTIN_CLASSES: [&str; 2] = ["Add", "Eq"];
+
+/// How deeply the types a generic function is instantiated with may nest.
+/// A function that calls itself with a bigger type each time would otherwise
+/// need infinitely many copies.
+const MAX_INSTANCE_DEPTH: usize = 32;
+
+/// The fresh variables chosen for a scheme's parameters, the instances they
+/// need and the type with them filled in.
+pub type Instantiated = (Vec<Type>, Vec<(String, Type)>, Type);
+
+/// A requirement on types found while walking a function, remembering the node
+/// that required it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// The expected and actual types must be the same.
+    Equal(Type, Type, Span),
+    /// The first type must be a struct with the named field, of the second type.
+    /// These can only be solved once the struct is known, so they wait until
+    /// enough of the equalities have been.
+    Field(Type, String, Type, Span),
+    /// The type must have an instance of the class. These are checked once
+    /// the function's types are solved.
+    Class(String, Type, Span),
+}
 
 impl Constraint {
-    pub fn new(left: &Type, right: &Type) -> Self {
-        Constraint(left.clone(), right.clone())
+    pub fn new(expected: &Type, actual: &Type, span: Span) -> Self {
+        Constraint::Equal(expected.clone(), actual.clone(), span)
+    }
+
+    pub fn field(object: &Type, field: impl AsRef<str>, ty: &Type, span: Span) -> Self {
+        Constraint::Field(object.clone(), field.as_ref().to_owned(), ty.clone(), span)
+    }
+
+    pub fn sub(&mut self, var: u32, t: &Type) {
+        match self {
+            Constraint::Equal(t1, t2, _) | Constraint::Field(t1, _, t2, _) => {
+                t1.sub(var, t);
+                t2.sub(var, t);
+            }
+            Constraint::Class(_, ty, _) => ty.sub(var, t),
+        }
+    }
+}
+
+/// A type that may mention generic parameters, which are replaced with fresh
+/// variables every time the name is used.
+#[derive(Clone, Debug)]
+pub struct Scheme {
+    pub vars: Vec<String>,
+    /// The classes that the parameters must have instances of.
+    pub bounds: Vec<(String, String)>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn new(generics: &[Generic], ty: &Type) -> Self {
+        Scheme {
+            vars: Generic::names(generics),
+            bounds: generics
+                .iter()
+                .flat_map(|generic| {
+                    generic
+                        .1
+                        .iter()
+                        .map(move |class| (class.clone(), generic.0.clone()))
+                }).collect(),
+            ty: ty.clone(),
+        }
+    }
+
+    /// Replaces the parameters with fresh variables.
+    pub fn instantiate(&self) -> Instantiated {
+        let args = self
+            .vars
+            .iter()
+            .map(|_| Type::T(next_int()))
+            .collect::<Vec<_>>();
+        let bounds = self
+            .bounds
+            .iter()
+            .map(|(class, var)| {
+                let mut ty = Type::Param(var.clone());
+                ty.instantiate(&self.vars, &args);
+                (class.clone(), ty)
+            }).collect();
+        let mut ty = self.ty.clone();
+        ty.instantiate(&self.vars, &args);
+        (args, bounds, ty)
     }
 }
 
 pub trait TypeLookup: ::std::fmt::Debug {
-    fn lookup(&self, name: impl AsRef<str>) -> Option<Type>;
-    fn variable(&mut self, name: impl AsRef<str>, ty: &Type);
+    fn lookup_scheme(&self, name: impl AsRef<str>) -> Option<Scheme>;
+    fn generic(&mut self, name: impl AsRef<str>, scheme: Scheme);
+
+    fn variable(&mut self, name: impl AsRef<str>, ty: &Type) {
+        self.generic(name, Scheme::new(&[], ty))
+    }
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct TypeContext {
-    bindings: BTreeMap<String, Type>,
+    bindings: BTreeMap<String, Scheme>,
 }
 
 impl TypeLookup for TypeContext {
-    fn lookup(&self, name: impl AsRef<str>) -> Option<Type> {
+    fn lookup_scheme(&self, name: impl AsRef<str>) -> Option<Scheme> {
         self.bindings.get(name.as_ref()).cloned()
     }
-    fn variable(&mut self, name: impl AsRef<str>, ty: &Type) {
-        self.bindings.insert(name.as_ref().to_owned(), ty.clone());
+    fn generic(&mut self, name: impl AsRef<str>, scheme: Scheme) {
+        self.bindings.insert(name.as_ref().to_owned(), scheme);
     }
 }
 
-#[derive(Debug)]
-pub struct TypeStack(Vec<TypeContext>);
+#[derive(Clone, Debug)]
+pub struct TypeStack {
+    globals: TypeContext,
+    /// The types of the variables, which the resolver numbered uniquely.
+    locals: BTreeMap<u32, Type>,
+    structs: BTreeMap<String, Vec<Field>>,
+    enums: BTreeMap<String, Vec<Variant>>,
+    classes: BTreeMap<String, (String, Vec<Method>)>,
+    instances: Vec<(String, Type)>,
+    return_type: Type,
+}
 
 impl Default for TypeStack {
     fn default() -> Self {
-        TypeStack(vec![TypeContext::default()])
+        let mut stack = TypeStack {
+            globals: TypeContext::default(),
+            locals: BTreeMap::new(),
+            structs: BTreeMap::new(),
+            enums: BTreeMap::new(),
+            classes: BTreeMap::new(),
+            instances: Vec::new(),
+            return_type: Type::Unit,
+        };
+
+        // `+` and `==` go through these, with the primitive instances built
+        // into codegen
+        let param = Type::Param("T".to_owned());
+        let method = |name: &str, ret: &Type| {
+            let args = vec![
+                Arg("a".to_owned(), param.clone(), Span::default()),
+                Arg("b".to_owned(), param.clone(), Span::default()),
+            ];
+            Method(name.to_owned(), args, ret.clone(), Span::default())
+        };
+        stack.class("Add", "T", &[method("add", &param)]);
+        stack.class("Eq", "T", &[method("eq", &Type::Bool)]);
+        stack.instance("Add", &Type::Int);
+        stack.instance("Eq", &Type::Int);
+        stack.instance("Eq", &Type::Bool);
+        stack
     }
 }
 
 impl TypeLookup for TypeStack {
-    fn lookup(&self, name: impl AsRef<str>) -> Option<Type> {
-        for item in self.0.iter().rev() {
-            if let Some(t) = item.lookup(&name) {
-                return Some(t);
-            }
-        }
-        None
+    fn lookup_scheme(&self, name: impl AsRef<str>) -> Option<Scheme> {
+        self.globals.lookup_scheme(name)
     }
-    fn variable(&mut self, name: impl AsRef<str>, ty: &Type) {
-        if let Some(scope) = self.0.last_mut() {
-            scope.variable(name, ty)
-        }
+    fn generic(&mut self, name: impl AsRef<str>, scheme: Scheme) {
+        self.globals.generic(name, scheme)
     }
 }
 
 impl TypeStack {
-    pub fn scope(&mut self) {
-        self.0.push(TypeContext::default());
+    pub fn local(&mut self, var: &hir::Var, ty: &Type) {
+        self.locals.insert(var.0, ty.clone());
+    }
+    pub fn lookup_local(&self, var: &hir::Var) -> Option<Type> {
+        self.locals.get(&var.0).cloned()
+    }
+    /// The type of a name used in an expression, with any generic parameters
+    /// instantiated.
+    pub fn lookup_name(&self, name: &hir::Name) -> Option<Instantiated> {
+        match name {
+            hir::Name::Local(var) => self.lookup_local(var).map(|ty| (Vec::new(), Vec::new(), ty)),
+            hir::Name::Global(name) => self.lookup_scheme(name).map(|scheme| scheme.instantiate()),
+        }
     }
-    pub fn unscope(&mut self) {
-        self.0.pop();
+    pub fn structure(&mut self, name: impl AsRef<str>, fields: &[Field]) {
+        self.structs.insert(name.as_ref().to_owned(), fields.to_vec());
+    }
+    pub fn lookup_struct(&self, name: impl AsRef<str>) -> Option<&[Field]> {
+        self.structs.get(name.as_ref()).map(|fields| fields.as_slice())
+    }
+    pub fn enumeration(&mut self, name: impl AsRef<str>, variants: &[Variant]) {
+        self.enums.insert(name.as_ref().to_owned(), variants.to_vec());
+    }
+    pub fn lookup_enum(&self, name: impl AsRef<str>) -> Option<&[Variant]> {
+        self.enums.get(name.as_ref()).map(|variants| variants.as_slice())
+    }
+    /// Declares a class, making its methods callable on any type with an
+    /// instance.
+    pub fn class(&mut self, name: impl AsRef<str>, param: impl AsRef<str>, methods: &[Method]) {
+        let param = param.as_ref().to_owned();
+        for method in methods {
+            let generics = [Generic(param.clone(), vec![name.as_ref().to_owned()])];
+            self.generic(&method.0, Scheme::new(&generics, &method.get_type()));
+        }
+        self.classes
+            .insert(name.as_ref().to_owned(), (param, methods.to_vec()));
+    }
+    pub fn lookup_class(&self, name: impl AsRef<str>) -> Option<&(String, Vec<Method>)> {
+        self.classes.get(name.as_ref())
+    }
+    pub fn instance(&mut self, class: impl AsRef<str>, ty: &Type) {
+        self.instances.push((class.as_ref().to_owned(), ty.clone()));
+    }
+    pub fn has_instance(&self, class: impl AsRef<str>, ty: &Type) -> bool {
+        self.instances
+            .iter()
+            .any(|(class2, ty2)| class2 == class.as_ref() && ty2 == ty)
+    }
+    /// Finds a variant by name, along with the enum it belongs to.
+    pub fn lookup_variant(&self, name: impl AsRef<str>) -> Option<(String, Variant)> {
+        self.enums.iter().find_map(|(enum_name, variants)| {
+            variants
+                .iter()
+                .find(|variant| variant.0 == name.as_ref())
+                .map(|variant| (enum_name.clone(), variant.clone()))
+        })
+    }
+
+    /// Whether a value of type `ty` holds a value of the struct or enum
+    /// `name` inline. Functions only point to what they capture, so they
+    /// don't count.
+    fn contains(&self, ty: &Type, name: &str, seen: &mut BTreeSet<String>) -> bool {
+        let inner = match ty {
+            Type::Struct(other) | Type::Enum(other) if other == name => return true,
+            Type::Struct(other) if seen.insert(other.clone()) => self
+                .lookup_struct(other)
+                .unwrap_or(&[])
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<_>>(),
+            Type::Enum(other) if seen.insert(other.clone()) => self
+                .lookup_enum(other)
+                .unwrap_or(&[])
+                .iter()
+                .flat_map(|variant| variant.1.iter().cloned())
+                .collect(),
+            _ => return false,
+        };
+        inner.iter().any(|ty| self.contains(ty, name, seen))
+    }
+    /// Checks that a field of the struct or enum `name` doesn't hold another
+    /// `name`, which would make it infinitely big.
+    fn check_recursion(&self, name: &str, ty: &Type, span: Span) -> Result<(), Diagnostic> {
+        if self.contains(ty, name, &mut BTreeSet::new()) {
+            return Err(Diagnostic::error(
+                span,
+                format!("recursive type `{}` has infinite size", name),
+            ).with_label("recursive without indirection")
+            .with_note("values are stored inline, so a type cannot contain itself"));
+        }
+        Ok(())
+    }
+
+    /// Checks that every struct named in `ty` has been declared.
+    pub fn check_type(&self, ty: &Type, span: Span) -> Result<(), Diagnostic> {
+        match ty {
+            Type::Struct(name) if !self.structs.contains_key(name) => Err(Diagnostic::error(
+                span,
+                format!("cannot find type `{}`", name),
+            ).with_label("not found in this scope")),
+            Type::Fn(args, ret, _) => {
+                for arg in args {
+                    self.check_type(arg, span)?;
+                }
+                self.check_type(ret, span)
+            }
+            _ => Ok(()),
+        }
     }
 }
 
-impl mir::Program {
-    pub fn typeck(&mut self, ctx: &mut TypeStack) -> Result<(), Error> {
-        self.0
-            .iter_mut()
-            .map(|decl| {
+impl hir::Program {
+    pub fn typeck(&mut self, ctx: &mut TypeStack) -> Result<(), Vec<Diagnostic>> {
+        let mut errors = Vec::new();
+        let mut instances = BTreeMap::new();
+        let mut rejected = BTreeSet::new();
+        for decl in self.0.iter_mut() {
+            if let hir::TopDeclKind::Instance(class, ty, methods) = &decl.kind {
+                let key = format!("{}[{}]", class, ty);
+                let conflict = Diagnostic::error(
+                    decl.span,
+                    format!("conflicting instances of `{}` for `{}`", class, ty),
+                ).with_label("conflicting instance");
+                if let Some(previous) = instances.get(&key) {
+                    errors.push(conflict.with_secondary(*previous, "first instance here"));
+                } else if ctx.has_instance(class, ty) {
+                    errors.push(conflict.with_note("this instance is built into the compiler"));
+                } else {
+                    instances.insert(key, decl.span);
+                    decl.build_ctx(ctx);
+                    continue;
+                }
+                rejected.extend(methods.iter().map(|method| hir::method_symbol(class, ty, method)));
+                continue;
+            }
+            // the resolver already reported names defined more than once
+            if !rejected.contains(decl.name()) {
                 decl.build_ctx(ctx);
-                decl
-            }).collect::<Vec<_>>()
-            .iter_mut()
-            .map(|decl| decl.typeck(ctx))
-            .collect::<Result<(), _>>()
+            }
+        }
+        for i in 0..self.0.len() {
+            if let hir::TopDeclKind::Instance(class, ty, methods) = &self.0[i].kind {
+                let (class, ty, methods) = (class.clone(), ty.clone(), methods.clone());
+                let span = self.0[i].span;
+                if let Err(err) = self.check_methods(&class, &ty, &methods, span, ctx) {
+                    errors.push(err);
+                }
+            }
+        }
+
+        let mut checked = Vec::new();
+        for decl in self.0.iter() {
+            match decl.check_types(ctx) {
+                Ok(()) => checked.push(true),
+                Err(err) => {
+                    errors.push(err);
+                    checked.push(false);
+                }
+            }
+        }
+
+        // a function is checked after the ones it uses, so that their
+        // signatures are already known and can be generalized; functions that
+        // use each other are checked together
+        for component in call_graph_components(&mut self.0) {
+            if component.iter().any(|i| !checked[*i]) {
+                continue;
+            }
+            let decls = self
+                .0
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| component.contains(i))
+                .map(|(_, decl)| decl)
+                .collect::<Vec<_>>();
+            if let Err(err) = typeck_component(decls, ctx) {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
-}
 
-impl mir::TopDecl {
-    pub fn build_ctx(&mut self, ctx: &mut TypeStack) {
-        use mir::TopDecl;
-        match self {
-            TopDecl::Extern(name, ty) => ctx.variable(name, ty),
-            TopDecl::Fn(name, args, ty, _body) => ctx.variable(
-                name,
-                &Type::Fn(
-                    args.iter().map(|arg| arg.get_type()).collect::<Vec<_>>(),
-                    Box::new(ty.clone()),
-                ),
-            ),
-            _ => (),
-        }
-    }
-    pub fn typeck(&mut self, ctx: &mut TypeStack) -> Result<(), Error> {
-        use mir::TopDecl;
-        match self {
-            TopDecl::Fn(_name, args, _ty, body) => {
-                ctx.scope();
-                for arg in args {
-                    ctx.variable(&arg.0, &arg.get_type());
+    /// Checks that the program has a `main` to start at, whose return value
+    /// can be the exit code.
+    pub fn check_main(&self) -> Result<(), Diagnostic> {
+        let decl = match self.0.iter().find(|decl| decl.name() == "main") {
+            Some(decl) => decl,
+            None => {
+                return Err(Diagnostic::unspanned("there is no `main` function")
+                    .with_help("add a `fn main(): int =` for the program to start at"))
+            }
+        };
+        match &decl.kind {
+            hir::TopDeclKind::Fn(_, generics, args, ret, _)
+                if generics.is_empty()
+                    && args.is_empty()
+                    && (*ret == Type::Int || *ret == Type::Unit) =>
+            {
+                Ok(())
+            }
+            _ => Err(Diagnostic::error(
+                decl.span,
+                "`main` must take no arguments and return `int` or `()`",
+            ).with_label(match decl.signature() {
+                Some(signature) => format!("found `{}`", signature),
+                None => "not a function".to_owned(),
+            }).with_note("the value `main` returns is the exit code of the program")),
+        }
+    }
+
+    /// Walks the generic functions from every function that isn't generic,
+    /// the way codegen copies them, to make sure there are finitely many
+    /// copies to make.
+    pub fn check_instances(&self) -> Result<(), Diagnostic> {
+        let mut functions = HashMap::new();
+        let mut pending = Vec::new();
+        for decl in self.0.iter() {
+            if let hir::TopDeclKind::Fn(name, generics, args, ret, body) = &decl.kind {
+                let params = Generic::names(generics);
+                if params.is_empty() {
+                    pending.push((name.clone(), Vec::new()));
                 }
-                let constraints = body
+                functions.insert(name.clone(), (params, fn_type(args, ret), body));
+            }
+        }
+        let mut seen = HashSet::new();
+        while let Some((name, type_args)) = pending.pop() {
+            let (params, _, body) = &functions[&name];
+            let mut body = body.to_vec();
+            let mut found = Vec::new();
+            for stmt in body.iter_mut() {
+                stmt.map_types(&mut |ty| ty.instantiate(params, &type_args));
+                stmt.map_exprs(&mut |expr| match &expr.kind {
+                    hir::ExprKind::Call(hir::Name::Global(func), args, _) => {
+                        found.push((func.clone(), args.clone(), expr.ty.clone(), expr.span))
+                    }
+                    hir::ExprKind::Name(hir::Name::Global(func)) => {
+                        found.push((func.clone(), Vec::new(), expr.ty.clone(), expr.span))
+                    }
+                    _ => (),
+                });
+            }
+            for (func, mut args, value_ty, span) in found {
+                let (params, ty, _) = match functions.get(&func) {
+                    Some(function) if !function.0.is_empty() => function,
+                    _ => continue,
+                };
+                // a generic function used as a value is copied for its type
+                if args.is_empty() {
+                    let mut bound = BTreeMap::new();
+                    ty.bind_params(&value_ty, &mut bound);
+                    args = params
+                        .iter()
+                        .map(|param| bound.get(param).cloned().unwrap_or(Type::Unit))
+                        .collect();
+                }
+                if args.iter().any(|arg| arg.depth() > MAX_INSTANCE_DEPTH) {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!(
+                            "reached the limit of {} nested types while instantiating `{}`",
+                            MAX_INSTANCE_DEPTH, func
+                        ),
+                    ).with_label("instantiated here")
+                    .with_note(
+                        "a generic function can't call itself with a bigger type each time",
+                    ));
+                }
+                if seen.insert((func.clone(), args.clone())) {
+                    pending.push((func, args));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that an instance has exactly the methods of its class, with the
+    /// types the class gives them. Missing annotations are filled in from the
+    /// class.
+    fn check_methods(
+        &mut self,
+        class: &str,
+        ty: &Type,
+        methods: &[String],
+        span: Span,
+        ctx: &mut TypeStack,
+    ) -> Result<(), Diagnostic> {
+        let (param, declared) = match ctx.lookup_class(class) {
+            Some((param, declared)) => (param.clone(), declared.clone()),
+            None => return Ok(()),
+        };
+        for method in declared.iter() {
+            if !methods.contains(&method.0) {
+                let err = Diagnostic::error(
+                    span,
+                    format!("missing method `{}` in instance of `{}`", method.0, class),
+                ).with_label(format!("`{}` is missing", method.0));
+                return Err(declared_at(err, class, method));
+            }
+        }
+        for name in methods {
+            let symbol = hir::method_symbol(class, ty, name);
+            let decl = match self.0.iter_mut().find(|decl| decl.name() == symbol) {
+                Some(decl) => decl,
+                None => continue,
+            };
+            let expected = match declared.iter().find(|method| &method.0 == name) {
+                Some(method) => method,
+                None => {
+                    return Err(Diagnostic::error(
+                        decl.span,
+                        format!("method `{}` is not a member of class `{}`", name, class),
+                    ).with_label("not a member of the class"))
+                }
+            };
+            let span = decl.span;
+            if let hir::TopDeclKind::Fn(_, _, args, ret, _) = &mut decl.kind {
+                if args.len() != expected.1.len() {
+                    let err = Diagnostic::error(
+                        span,
+                        format!(
+                            "method `{}` takes {} argument(s) but the class declares {}",
+                            name,
+                            args.len(),
+                            expected.1.len()
+                        ),
+                    );
+                    return Err(declared_at(err, class, expected));
+                }
+                let places = args
                     .iter_mut()
-                    .flat_map(|stmt| stmt.generate_constraints(ctx))
-                    .collect::<HashSet<_>>();
-                let substitutions = unify(constraints)?;
-                for stmt in body.iter_mut() {
-                    stmt.apply_subst(&substitutions);
+                    .zip(expected.1.iter())
+                    .map(|(arg, expected)| (&mut arg.1, &expected.1, arg.2))
+                    .chain(::std::iter::once((ret, &expected.2, span)));
+                for (actual, expected, span) in places {
+                    let mut expected = expected.clone();
+                    expected
+                        .instantiate(::std::slice::from_ref(&param), ::std::slice::from_ref(ty));
+                    match actual {
+                        Type::T(_) => *actual = expected,
+                        _ if *actual == expected => (),
+                        _ => {
+                            return Err(Diagnostic::error(
+                                span,
+                                format!("method `{}` has a different type than in its class", name),
+                            ).with_label(format!("expected `{}`, found `{}`", expected, actual)))
+                        }
+                    }
                 }
-                ctx.unscope();
-                Ok(())
             }
-            _ => Ok(()),
+            decl.build_ctx(ctx);
         }
+        Ok(())
     }
 }
 
-impl mir::Stmt {
-    pub fn apply_subst(&mut self, subst: &Substitution) {
-        use mir::Stmt;
-        match self {
-            Stmt::Assign(_, _, expr) => expr.apply_subst(subst),
-            Stmt::Expr(expr) => expr.apply_subst(subst),
-            Stmt::If(cond, body1, body2) => {
-                cond.apply_subst(subst);
-                for stmt in body1 {
-                    stmt.apply_subst(subst);
+impl hir::TopDecl {
+    pub fn build_ctx(&mut self, ctx: &mut TypeStack) {
+        use hir::TopDeclKind;
+        match &self.kind {
+            TopDeclKind::Extern(name, ty) => ctx.variable(name, ty),
+            TopDeclKind::Fn(name, generics, args, ty, _body) => {
+                ctx.generic(name, Scheme::new(generics, &fn_type(args, ty)))
+            }
+            TopDeclKind::Struct(name, fields) => ctx.structure(name, fields),
+            TopDeclKind::Enum(name, variants) => ctx.enumeration(name, variants),
+            TopDeclKind::Class(name, param, methods) => ctx.class(name, param, methods),
+            TopDeclKind::Instance(class, ty, _) => ctx.instance(class, ty),
+        }
+    }
+    pub fn check_types(&self, ctx: &TypeStack) -> Result<(), Diagnostic> {
+        use hir::TopDeclKind;
+        match &self.kind {
+            TopDeclKind::Extern(name, ty) => match ty {
+                Type::Fn(_, _, _) => ctx.check_type(ty, self.span),
+                _ => Err(Diagnostic::error(
+                    self.span,
+                    format!("extern `{}` must have a function type", name),
+                ).with_label(format!("found `{}`", ty))
+                .with_help("only functions can be declared with `extern`")),
+            },
+            TopDeclKind::Fn(_, _, args, ty, _) => {
+                for arg in args {
+                    ctx.check_type(&arg.1, arg.2)?;
                 }
-                if let Some(body) = body2 {
-                    for stmt in body {
-                        stmt.apply_subst(subst);
+                ctx.check_type(ty, self.span)
+            }
+            TopDeclKind::Struct(name, fields) => {
+                for field in fields {
+                    ctx.check_type(&field.1, field.2)?;
+                    ctx.check_recursion(name, &field.1, field.2)?;
+                }
+                Ok(())
+            }
+            TopDeclKind::Enum(name, variants) => {
+                for variant in variants {
+                    for ty in variant.1.iter() {
+                        ctx.check_type(ty, variant.2)?;
+                        ctx.check_recursion(name, ty, variant.2)?;
+                    }
+                }
+                Ok(())
+            }
+            TopDeclKind::Class(name, _, methods) => {
+                for method in methods {
+                    let mut vars = Vec::new();
+                    method.get_type().free_vars(&mut vars);
+                    if !vars.is_empty() {
+                        return Err(Diagnostic::error(
+                            method.3,
+                            format!("the methods of class `{}` need type annotations", name),
+                        ).with_label("the type of this method isn't fully written out"));
                     }
+                    ctx.check_type(&method.get_type(), method.3)?;
                 }
+                Ok(())
             }
-            Stmt::While(cond, body) => {
-                cond.apply_subst(subst);
-                for stmt in body.iter_mut() {
-                    stmt.apply_subst(subst);
+            TopDeclKind::Instance(class, ty, _) => {
+                if ctx.lookup_class(class).is_none() {
+                    return Err(
+                        Diagnostic::error(self.span, format!("cannot find class `{}`", class))
+                            .with_label("not found in this scope"),
+                    );
                 }
+                ctx.check_type(ty, self.span)
             }
-            Stmt::Return(expr) => if let Some(expr) = expr {
-                expr.apply_subst(subst);
-            },
         }
     }
-    pub fn generate_constraints(&mut self, ctx: &mut TypeStack) -> HashSet<Constraint> {
-        use mir::Stmt;
-        match self {
-            Stmt::Assign(re, name, expr) => {
-                if !*re {
-                    ctx.variable(&name, &Type::T(next_int()));
+    /// The constraints from the body of a function.
+    pub fn constraints(&mut self, ctx: &mut TypeStack) -> Result<Vec<Constraint>, Diagnostic> {
+        use hir::TopDeclKind;
+        match &mut self.kind {
+            TopDeclKind::Fn(_, _, args, ty, body) => {
+                for arg in args {
+                    ctx.local(&arg.0, &arg.get_type());
                 }
+                ctx.return_type = ty.clone();
+                block_constraints(body, ctx)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+    pub fn apply_subst(&mut self, subst: &Substitution) {
+        use hir::TopDeclKind;
+        if let TopDeclKind::Fn(_, _, args, ty, body) = &mut self.kind {
+            for arg in args.iter_mut() {
+                arg.1.apply_subst(subst);
+            }
+            ty.apply_subst(subst);
+            for stmt in body.iter_mut() {
+                stmt.apply_subst(subst);
+            }
+        }
+    }
+}
 
-                let mut result = expr.generate_constraints(ctx);
-                result.extend(match ctx.lookup(&name) {
-                    Some(ty) => vec![Constraint::new(&ty, &expr.get_type())],
-                    None => panic!("Name '{}' not found.", name),
+fn fn_type<N>(args: &[Arg<N>], ret: &Type) -> Type {
+    Type::Fn(
+        args.iter().map(|arg| arg.get_type()).collect::<Vec<_>>(),
+        Box::new(ret.clone()),
+        false,
+    )
+}
+
+/// The name of the `n`th generic parameter made up for an unannotated
+/// function.
+fn param_name(n: usize) -> String {
+    if n < 26 {
+        format!("'{}", (b'a' + n as u8) as char)
+    } else {
+        format!("'a{}", n)
+    }
+}
+
+/// Infers the types of functions that call each other. The type variables
+/// left in their signatures afterwards become generic parameters.
+fn typeck_component(
+    mut decls: Vec<&mut hir::TopDecl>,
+    ctx: &mut TypeStack,
+) -> Result<(), Diagnostic> {
+    use hir::TopDeclKind;
+    let mut constraints = Vec::new();
+    let mut classes = Vec::new();
+    for (i, decl) in decls.iter_mut().enumerate() {
+        for constraint in decl.constraints(ctx)? {
+            match constraint {
+                Constraint::Class(class, ty, span) => classes.push((i, class, ty, span)),
+                constraint => {
+                    constraints.push(constraint);
+                }
+            }
+        }
+    }
+    let substitution = unify(constraints, ctx)?;
+
+    let mut params = Substitution::new();
+    for decl in decls.iter_mut() {
+        decl.apply_subst(&substitution);
+        if let TopDeclKind::Fn(_, generics, args, ty, _) = &mut decl.kind {
+            let mut vars = Vec::new();
+            for arg in args.iter() {
+                arg.1.free_vars(&mut vars);
+            }
+            ty.free_vars(&mut vars);
+            for var in vars {
+                let n = params.len();
+                let param = params.entry(var).or_insert_with(|| Type::Param(param_name(n)));
+                if let Type::Param(param) = param {
+                    generics.push(Generic(param.clone(), Vec::new()));
+                }
+            }
+        }
+    }
+
+    for (i, class, mut ty, span) in classes {
+        ty.apply_subst(&substitution);
+        ty.apply_subst(&params);
+        if let TopDeclKind::Fn(_, generics, _, _, _) = &mut decls[i].kind {
+            check_instance(&class, &ty, span, generics, ctx)?;
+        }
+    }
+
+    let mut signatures = BTreeMap::new();
+    for decl in decls.iter_mut() {
+        decl.apply_subst(&params);
+        if let TopDeclKind::Fn(name, generics, args, ty, _) = &decl.kind {
+            signatures.insert(name.clone(), Scheme::new(generics, &fn_type(args, ty)));
+        }
+    }
+    // calls inside the component were checked before the callee was
+    // generalized, so they use its parameters as they are
+    for decl in decls.iter_mut() {
+        if let TopDeclKind::Fn(_, _, _, _, body) = &mut decl.kind {
+            for stmt in body.iter_mut() {
+                stmt.map_exprs(&mut |expr| {
+                    if let hir::ExprKind::Call(hir::Name::Global(name), type_args, _) =
+                        &mut expr.kind
+                    {
+                        if let Some(scheme) = signatures.get(name) {
+                            if type_args.is_empty() {
+                                *type_args = scheme.vars.iter().cloned().map(Type::Param).collect();
+                            }
+                        }
+                    }
                 });
-                result
             }
-            Stmt::Expr(expr) => expr.generate_constraints(ctx),
-            Stmt::If(cond, body1, body2) => vec![
-                cond.generate_constraints(ctx),
-                {
-                    ctx.scope();
-                    body1
-                        .iter_mut()
-                        .flat_map(|stmt| stmt.generate_constraints(ctx))
-                        .collect::<HashSet<_>>()
-                },
-                {
-                    ctx.scope();
-                    match body2 {
-                        Some(body) => body
-                            .iter_mut()
-                            .flat_map(|stmt| stmt.generate_constraints(ctx))
-                            .collect::<HashSet<_>>(),
-                        None => HashSet::new(),
+        }
+    }
+    for (name, scheme) in signatures {
+        ctx.generic(name, scheme);
+    }
+    Ok(())
+}
+
+/// Points at where `class` declares `method`, or says that the compiler does.
+fn declared_at(err: Diagnostic, class: &str, method: &Method) -> Diagnostic {
+    if BUILTIN_CLASSES.contains(&class) {
+        err.with_note(format!("`{}` is built into the compiler", class))
+    } else {
+        err.with_secondary(method.3, "declared here")
+    }
+}
+
+/// Checks that `ty` has an instance of `class`, as needed by the function
+/// with the parameters `generics`.
+fn check_instance(
+    class: &str,
+    ty: &Type,
+    span: Span,
+    generics: &mut [Generic],
+    ctx: &TypeStack,
+) -> Result<(), Diagnostic> {
+    let missing = || {
+        Diagnostic::error(span, format!("no instance of `{}` for `{}`", class, ty))
+            .with_label(format!("`{}` is required here", class))
+    };
+    match ty {
+        Type::Param(name) => match generics.iter_mut().find(|generic| generic.0 == *name) {
+            // parameters made up by inference require whatever is needed
+            Some(generic) if generic.0.starts_with('\'') => {
+                if !generic.1.iter().any(|bound| bound == class) {
+                    generic.1.push(class.to_owned());
+                }
+                Ok(())
+            }
+            Some(generic) if generic.1.iter().any(|bound| bound == class) => Ok(()),
+            _ => Err(missing().with_help(format!("require it with `{}: {}`", name, class))),
+        },
+        Type::T(_) => Err(Diagnostic::error(
+            span,
+            format!("cannot tell which instance of `{}` to use", class),
+        ).with_label("type annotations needed")),
+        _ if ctx.has_instance(class, ty) => Ok(()),
+        _ => Err(missing()),
+    }
+}
+
+/// Groups the functions by which ones call each other, returning the indices
+/// of the groups' declarations with every group after the ones it calls.
+fn call_graph_components(decls: &mut [hir::TopDecl]) -> Vec<Vec<usize>> {
+    let mut functions = BTreeMap::new();
+    for (i, decl) in decls.iter().enumerate() {
+        if let hir::TopDeclKind::Fn(name, _, _, _, _) = &decl.kind {
+            functions.entry(name.clone()).or_insert(i);
+        }
+    }
+    let mut edges = vec![Vec::new(); decls.len()];
+    for (i, decl) in decls.iter_mut().enumerate() {
+        if let hir::TopDeclKind::Fn(_, _, _, _, body) = &mut decl.kind {
+            for stmt in body.iter_mut() {
+                stmt.map_exprs(&mut |expr| match &expr.kind {
+                    hir::ExprKind::Call(hir::Name::Global(name), _, _)
+                    | hir::ExprKind::Name(hir::Name::Global(name)) => {
+                        if let Some(j) = functions.get(name) {
+                            edges[i].push(*j);
+                        }
                     }
-                },
-            ].into_iter()
-            .flatten()
-            .collect::<HashSet<_>>(),
-            Stmt::While(cond, body) => vec![
-                cond.generate_constraints(ctx),
-                body.iter_mut()
-                    .flat_map(|stmt| stmt.generate_constraints(ctx))
-                    .collect::<HashSet<_>>(),
-            ].into_iter()
-            .flatten()
-            .collect::<HashSet<_>>(),
-            Stmt::Return(expr) => match expr {
-                Some(expr) => expr.generate_constraints(ctx),
-                None => HashSet::new(),
-            },
+                    _ => (),
+                });
+            }
         }
     }
+
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; decls.len()],
+        lowlink: vec![0; decls.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; decls.len()],
+        next: 0,
+        components: Vec::new(),
+    };
+    for i in functions.values() {
+        if tarjan.index[*i].is_none() {
+            tarjan.visit(*i);
+        }
+    }
+    tarjan.components
 }
 
-impl mir::Expr {
-    pub fn apply_subst(&mut self, subst: &Substitution) {
-        use mir::Expr;
-        match self {
-            Expr::Literal(_lit, ty) => ty.apply_subst(subst),
-            Expr::Name(_, ty) => ty.apply_subst(subst),
-            Expr::Call(_, args, ty) => {
-                for arg in args {
-                    arg.apply_subst(subst);
+/// Tarjan's strongly connected components algorithm, which finds each
+/// component only after every component reachable from it.
+struct Tarjan {
+    edges: Vec<Vec<usize>>,
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    next: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.next);
+        self.lowlink[v] = self.next;
+        self.next += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+
+        for w in self.edges[v].clone() {
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
                 }
-                ty.apply_subst(subst);
+                Some(index) if self.on_stack[w] => {
+                    self.lowlink[v] = self.lowlink[v].min(index);
+                }
+                Some(_) => (),
             }
-            Expr::NotEquals(left, right, ty)
-            | Expr::Equals(left, right, ty)
-            | Expr::Times(left, right, ty)
-            | Expr::Minus(left, right, ty)
-            | Expr::Plus(left, right, ty) => {
-                left.apply_subst(subst);
-                right.apply_subst(subst);
-                ty.apply_subst(subst);
+        }
+
+        if Some(self.lowlink[v]) == self.index[v] {
+            let mut component = Vec::new();
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
             }
+            self.components.push(component);
         }
     }
-    pub fn generate_constraints(&mut self, ctx: &mut TypeStack) -> HashSet<Constraint> {
-        use mir::Expr;
-        match self {
-            Expr::Call(name, args, ty) => match ctx.lookup(&name) {
-                Some(Type::Fn(args_t, ret)) => {
-                    if args.len() != args_t.len() {
-                        panic!("Function argument length mismatch.");
-                    }
+}
 
-                    let mut result = vec![Constraint::new(&ret, &ty)];
-                    result.extend(
-                        args.iter()
-                            .zip(args_t.iter())
-                            .map(|(a, b)| Constraint::new(&a.get_type(), &b))
-                            .collect::<Vec<_>>(),
+fn block_constraints(
+    body: &mut [hir::Stmt],
+    ctx: &mut TypeStack,
+) -> Result<Vec<Constraint>, Diagnostic> {
+    let mut result = Vec::new();
+    for stmt in body.iter_mut() {
+        result.extend(stmt.generate_constraints(ctx)?);
+    }
+    Ok(result)
+}
+
+impl hir::Stmt {
+    pub fn apply_subst(&mut self, subst: &Substitution) {
+        self.map_types(&mut |ty| ty.apply_subst(subst));
+    }
+    pub fn generate_constraints(
+        &mut self,
+        ctx: &mut TypeStack,
+    ) -> Result<Vec<Constraint>, Diagnostic> {
+        use hir::StmtKind;
+        let span = self.span;
+        Ok(match &mut self.kind {
+            // locals aren't generalized like `fn`s are, so each has one type
+            StmtKind::Assign(re, var, expr) => {
+                if !*re {
+                    ctx.local(var, &Type::T(next_int()));
+                }
+
+                let mut result = expr.generate_constraints(ctx)?;
+                if let Some(ty) = ctx.lookup_local(var) {
+                    result.push(Constraint::new(&ty, &expr.get_type(), expr.span));
+                }
+                result
+            }
+            StmtKind::Declare(var, ty) => {
+                ctx.check_type(ty, span)?;
+                ctx.local(var, ty);
+                Vec::new()
+            }
+            StmtKind::SetField(object, field, expr) => {
+                if !object.is_place() {
+                    return Err(
+                        Diagnostic::error(object.span, "invalid left-hand side of assignment")
+                            .with_label("cannot assign to a field of this expression")
+                            .with_help("store the value in a variable first"),
                     );
+                }
+                let mut result = object.generate_constraints(ctx)?;
+                result.extend(expr.generate_constraints(ctx)?);
+                result.push(Constraint::field(
+                    &object.get_type(),
+                    field,
+                    &expr.get_type(),
+                    expr.span,
+                ));
+                result
+            }
+            StmtKind::Expr(expr) => expr.generate_constraints(ctx)?,
+            StmtKind::If(cond, body1, body2) => {
+                let mut result = cond.generate_constraints(ctx)?;
+                result.push(Constraint::new(&Type::Bool, &cond.get_type(), cond.span));
+                result.extend(block_constraints(body1, ctx)?);
+                if let Some(body) = body2 {
+                    result.extend(block_constraints(body, ctx)?);
+                }
+                result
+            }
+            StmtKind::While(cond, body) => {
+                let mut result = cond.generate_constraints(ctx)?;
+                result.push(Constraint::new(&Type::Bool, &cond.get_type(), cond.span));
+                result.extend(block_constraints(body, ctx)?);
+                result
+            }
+            StmtKind::Match(expr, arms) => {
+                let mut result = expr.generate_constraints(ctx)?;
+                let mut enum_name = None;
+                let mut covered = BTreeSet::new();
+                let mut wildcard = false;
+                for arm in arms.iter_mut() {
+                    if let PatternKind::Variant(name, binders) = &arm.pattern.kind {
+                        let (parent, variant) = match ctx.lookup_variant(name) {
+                            Some(found) => found,
+                            None => {
+                                return Err(Diagnostic::error(
+                                    arm.pattern.span,
+                                    format!("cannot find variant `{}`", name),
+                                ).with_label("not found in this scope"));
+                            }
+                        };
+                        if binders.len() != variant.1.len() {
+                            return Err(Diagnostic::error(
+                                arm.pattern.span,
+                                format!(
+                                    "this pattern has {} field(s), but variant `{}` has {}",
+                                    binders.len(),
+                                    name,
+                                    variant.1.len()
+                                ),
+                            ).with_secondary(variant.2, "variant defined here"));
+                        }
+                        for (binder, ty) in binders.iter().zip(variant.1.iter()) {
+                            if let Some(var) = binder {
+                                ctx.local(var, ty);
+                            }
+                        }
+                        result.push(Constraint::new(
+                            &Type::Enum(parent.clone()),
+                            &expr.get_type(),
+                            arm.pattern.span,
+                        ));
+                        covered.insert(name.clone());
+                        enum_name = Some(parent);
+                    } else {
+                        wildcard = true;
+                    }
+                    result.extend(block_constraints(&mut arm.body, ctx)?);
+                }
+
+                if !wildcard {
+                    let missing = enum_name
+                        .and_then(|name| ctx.lookup_enum(name))
+                        .unwrap_or(&[])
+                        .iter()
+                        .filter(|variant| !covered.contains(&variant.0))
+                        .map(|variant| format!("`{}`", variant.0))
+                        .collect::<Vec<_>>();
+                    if arms.is_empty() || !missing.is_empty() {
+                        let missing = missing.join(", ");
+                        return Err(Diagnostic::error(
+                            expr.span,
+                            format!("non-exhaustive patterns: {} not covered", missing),
+                        ).with_label(format!("pattern(s) {} not covered", missing))
+                        .with_help("add an arm for each missing variant, or a `_` arm"));
+                    }
+                }
+                result
+            }
+            StmtKind::Return(expr) => match expr {
+                Some(expr) => {
+                    let mut result = expr.generate_constraints(ctx)?;
+                    result.push(Constraint::new(&ctx.return_type, &expr.get_type(), expr.span));
                     result
                 }
-                _ => panic!("Name '{}' not bound or not a function.", name),
-            },
-            Expr::Literal(lit, ty) => vec![Constraint::new(&lit.get_type(), ty)],
-            Expr::Name(name, ty) => match ctx.lookup(&name) {
-                Some(ty1) => vec![Constraint::new(&ty1, ty)],
-                None => panic!("Name '{}' not bound.", name),
+                None => {
+                    vec![Constraint::new(&ctx.return_type, &Type::Unit, span)]
+                }
             },
-            Expr::NotEquals(left, right, ty) | Expr::Equals(left, right, ty) => {
-                let left = left.get_type();
-                let right = right.get_type();
-                vec![
-                    Constraint::new(&left, &right),
-                    Constraint::new(ty, &Type::Bool),
-                ]
-            }
-            Expr::Times(left, right, ty)
-            | Expr::Minus(left, right, ty)
-            | Expr::Plus(left, right, ty) => {
-                let left = left.get_type();
-                let right = right.get_type();
-                vec![
-                    Constraint::new(&left, &right),
-                    Constraint::new(ty, &left),
-                    Constraint::new(ty, &right),
-                ]
-            }
-        }.into_iter()
-        .collect::<HashSet<_>>()
+        })
     }
 }
 
-fn unify(constraints: HashSet<Constraint>) -> Result<Substitution, Error> {
-    let mut constraints = constraints.into_iter().collect::<Vec<_>>();
-    let mut substitution = Substitution::new();
-
-    while let Some(Constraint(t1, t2)) = constraints.pop() {
-        if t1 == t2 {
-            continue;
+impl hir::Expr {
+    /// Whether this expression names storage that can be assigned through.
+    pub fn is_place(&self) -> bool {
+        use hir::ExprKind;
+        match &self.kind {
+            ExprKind::Name(_) => true,
+            ExprKind::Field(object, _) => object.is_place(),
+            _ => false,
         }
+    }
+    pub fn generate_constraints(
+        &mut self,
+        ctx: &mut TypeStack,
+    ) -> Result<Vec<Constraint>, Diagnostic> {
+        use hir::ExprKind;
+        let ty = &self.ty;
+        let span = self.span;
+        let mut result = Vec::new();
+        match &mut self.kind {
+            ExprKind::Call(name, type_args, args) => match ctx.lookup_name(name) {
+                Some((fresh, bounds, Type::Fn(args_t, ret, variadic))) => {
+                    *type_args = fresh;
+                    for (class, ty) in bounds {
+                        result.push(Constraint::Class(class, ty, span));
+                    }
+                    if args.len() < args_t.len() || (!variadic && args.len() > args_t.len()) {
+                        let expected = if variadic {
+                            format!("at least {}", args_t.len())
+                        } else {
+                            args_t.len().to_string()
+                        };
+                        return Err(Diagnostic::error(
+                            span,
+                            format!(
+                                "function `{}` takes {} argument(s) but {} were supplied",
+                                name,
+                                expected,
+                                args.len()
+                            ),
+                        ).with_label(format!("expected {} argument(s)", expected)));
+                    }
 
-        match (&t1, &t2) {
-            (Type::T(n), t) | (t, Type::T(n)) => {
-                for Constraint(c1, c2) in &mut constraints {
-                    c1.sub(*n, &t);
-                    c2.sub(*n, &t);
+                    result.push(Constraint::new(&ret, ty, span));
+                    for (i, arg) in args.iter_mut().enumerate() {
+                        result.extend(arg.generate_constraints(ctx)?);
+                        // the variadic arguments can be anything
+                        if let Some(arg_t) = args_t.get(i) {
+                            result.push(Constraint::new(arg_t, &arg.get_type(), arg.span));
+                        }
+                    }
                 }
-                substitution.insert(*n, t.clone());
+                // a function value whose type hasn't been worked out yet
+                Some((_, _, Type::T(n))) => {
+                    for arg in args.iter_mut() {
+                        result.extend(arg.generate_constraints(ctx)?);
+                    }
+                    let args_t = args.iter().map(|arg| arg.get_type()).collect();
+                    result.push(Constraint::new(
+                        &Type::T(n),
+                        &Type::Fn(args_t, Box::new(ty.clone()), false),
+                        span,
+                    ));
+                }
+                Some((_, _, other)) => {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("`{}` is not a function", name),
+                    ).with_label(format!("`{}` has type `{}`", name, other)))
+                }
+                None => {
+                    return Err(
+                        Diagnostic::error(span, format!("cannot find function `{}`", name))
+                            .with_label("not found in this scope"),
+                    )
+                }
+            },
+            ExprKind::Apply(func, args) => {
+                result.extend(func.generate_constraints(ctx)?);
+                for arg in args.iter_mut() {
+                    result.extend(arg.generate_constraints(ctx)?);
+                }
+                let args_t = args.iter().map(|arg| arg.get_type()).collect();
+                result.push(Constraint::new(
+                    &func.get_type(),
+                    &Type::Fn(args_t, Box::new(ty.clone()), false),
+                    func.span,
+                ));
             }
-            _ => bail!("Can't unify {:?} ~ {:?}", t1, t2),
-        };
+            ExprKind::Lambda(args, body) => {
+                for arg in args.iter() {
+                    ctx.local(&arg.0, &arg.get_type());
+                }
+                result.extend(body.generate_constraints(ctx)?);
+                result.push(Constraint::new(ty, &fn_type(args, &body.get_type()), span));
+            }
+            ExprKind::Literal(lit) => {
+                result.push(Constraint::new(ty, &lit.get_type(), span));
+            }
+            ExprKind::Name(name) => match ctx.lookup_name(name) {
+                // the function value wouldn't know how many arguments to pass
+                Some((_, _, Type::Fn(_, _, true))) => {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("cannot use variadic function `{}` as a value", name),
+                    ).with_label("can only be called directly"))
+                }
+                Some((_, _, ty1)) => {
+                    result.push(Constraint::new(ty, &ty1, span));
+                }
+                None => {
+                    return Err(
+                        Diagnostic::error(span, format!("cannot find value `{}`", name))
+                            .with_label("not found in this scope"),
+                    )
+                }
+            },
+            ExprKind::Field(object, field) => {
+                result.extend(object.generate_constraints(ctx)?);
+                result.push(Constraint::field(&object.get_type(), field, ty, span));
+            }
+            ExprKind::Struct(name, fields) => {
+                let decl = match ctx.lookup_struct(&name) {
+                    Some(decl) => decl.to_vec(),
+                    None => {
+                        return Err(
+                            Diagnostic::error(span, format!("cannot find struct `{}`", name))
+                                .with_label("not found in this scope"),
+                        )
+                    }
+                };
+                let mut seen: BTreeMap<String, Span> = BTreeMap::new();
+                for (field, expr) in fields.iter_mut() {
+                    let field_t = match decl.iter().find(|decl| &decl.0 == field) {
+                        Some(decl) => decl.get_type(),
+                        None => {
+                            return Err(Diagnostic::error(
+                                expr.span,
+                                format!("struct `{}` has no field named `{}`", name, field),
+                            ).with_label("unknown field"))
+                        }
+                    };
+                    if let Some(previous) = seen.get(field) {
+                        return Err(Diagnostic::error(
+                            expr.span,
+                            format!("field `{}` specified more than once", field),
+                        ).with_label("used again here")
+                        .with_secondary(*previous, "first use here"));
+                    }
+                    seen.insert(field.clone(), expr.span);
+                    result.extend(expr.generate_constraints(ctx)?);
+                    result.push(Constraint::new(&field_t, &expr.get_type(), expr.span));
+                }
+                let missing = decl
+                    .iter()
+                    .filter(|decl| !seen.contains_key(&decl.0))
+                    .map(|decl| format!("`{}`", decl.0))
+                    .collect::<Vec<_>>();
+                if !missing.is_empty() {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("missing field(s) {} in initializer of `{}`", missing.join(", "), name),
+                    ).with_label("missing field(s)"));
+                }
+                result.push(Constraint::new(ty, &Type::Struct(name.clone()), span));
+            }
+            ExprKind::Variant(_, name, args) => {
+                let variant = match ctx.lookup_variant(&name) {
+                    Some((_, variant)) => variant,
+                    None => {
+                        return Err(
+                            Diagnostic::error(span, format!("cannot find variant `{}`", name))
+                                .with_label("not found in this scope"),
+                        )
+                    }
+                };
+                if args.len() != variant.1.len() {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!(
+                            "variant `{}` takes {} field(s) but {} were supplied",
+                            name,
+                            variant.1.len(),
+                            args.len()
+                        ),
+                    ).with_label(format!("expected {} field(s)", variant.1.len()))
+                    .with_secondary(variant.2, "variant defined here"));
+                }
+                for (arg, arg_t) in args.iter_mut().zip(variant.1.iter()) {
+                    result.extend(arg.generate_constraints(ctx)?);
+                    result.push(Constraint::new(arg_t, &arg.get_type(), arg.span));
+                }
+            }
+            ExprKind::And(left, right) | ExprKind::Or(left, right) => {
+                result.extend(left.generate_constraints(ctx)?);
+                result.extend(right.generate_constraints(ctx)?);
+                result.push(Constraint::new(&Type::Bool, &left.get_type(), left.span));
+                result.push(Constraint::new(&Type::Bool, &right.get_type(), right.span));
+                result.push(Constraint::new(&Type::Bool, ty, span));
+            }
+            ExprKind::Unary(op, expr) => {
+                result.extend(expr.generate_constraints(ctx)?);
+                let expr_t = match op {
+                    UnOp::Not => Type::Bool,
+                    UnOp::Neg => Type::Int,
+                };
+                result.push(Constraint::new(&expr_t, &expr.get_type(), expr.span));
+                result.push(Constraint::new(&expr_t, ty, span));
+            }
+            ExprKind::Binary(op, left, right) => {
+                result.extend(left.generate_constraints(ctx)?);
+                result.extend(right.generate_constraints(ctx)?);
+                let left_t = left.get_type();
+                let right_t = right.get_type();
+                result.push(Constraint::new(&left_t, &right_t, right.span));
+                match op {
+                    BinOp::Add => {
+                        result.push(Constraint::Class("Add".to_owned(), left_t.clone(), span));
+                        result.push(Constraint::new(&left_t, ty, span));
+                    }
+                    BinOp::Eq | BinOp::Ne => {
+                        result.push(Constraint::Class("Eq".to_owned(), left_t.clone(), span));
+                    }
+                    _ => {
+                        result.push(Constraint::new(&Type::Int, &left_t, left.span));
+                    }
+                }
+                if op.is_comparison() {
+                    result.push(Constraint::new(&Type::Bool, ty, span));
+                } else if *op != BinOp::Add {
+                    result.push(Constraint::new(&Type::Int, ty, span));
+                }
+            }
+        }
+        Ok(result)
     }
+}
+
+/// Solves the constraints in the order they were found, so that the first
+/// error in the source is the one reported.
+fn unify(mut constraints: Vec<Constraint>, ctx: &TypeStack) -> Result<Substitution, Diagnostic> {
+    // the next constraint to solve is at the end
+    constraints.reverse();
+    let mut deferred = Vec::new();
+    let mut substitution = Substitution::new();
 
     loop {
-        let mut flag = false;
-        let mut inserts = Vec::new();
-        for (n, t) in substitution.iter() {
-            if let Type::T(m) = t {
-                flag = true;
-                let s = substitution.get(m);
+        while let Some(constraint) = constraints.pop() {
+            match constraint {
+                Constraint::Equal(t1, t2, span) => {
+                    if t1 == t2 {
+                        continue;
+                    }
 
-                if let Some(t) = s {
-                    inserts.push((*n, t.clone()));
+                    match (&t1, &t2) {
+                        (Type::T(n), t) | (t, Type::T(n)) => {
+                            if t.occurs(*n) {
+                                return Err(Diagnostic::error(
+                                    span,
+                                    format!(
+                                        "cannot construct the infinite type `{} = {}`",
+                                        Type::T(*n),
+                                        t
+                                    ),
+                                ).with_label("this value would have to contain its own type"));
+                            }
+                            for constraint in constraints.iter_mut().chain(deferred.iter_mut()) {
+                                constraint.sub(*n, t);
+                            }
+                            // keep every earlier binding free of `n`, so the
+                            // substitution never has to be applied twice
+                            for binding in substitution.values_mut() {
+                                binding.sub(*n, t);
+                            }
+                            substitution.insert(*n, t.clone());
+                        }
+                        (Type::Fn(args1, ret1, variadic1), Type::Fn(args2, ret2, variadic2))
+                            if args1.len() == args2.len() && variadic1 == variadic2 =>
+                        {
+                            constraints.push(Constraint::new(ret1, ret2, span));
+                            for (arg1, arg2) in args1.iter().zip(args2.iter()).rev() {
+                                constraints.push(Constraint::new(arg1, arg2, span));
+                            }
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(span, "mismatched types")
+                                .with_label(format!("expected `{}`, found `{}`", t1, t2)))
+                        }
+                    };
                 }
+                // these are checked once the types are known
+                Constraint::Class(_, _, _) => (),
+                Constraint::Field(object, field, ty, span) => match &object {
+                    Type::T(_) => deferred.push(Constraint::Field(object, field, ty, span)),
+                    Type::Struct(name) => {
+                        let fields = ctx.lookup_struct(name).unwrap_or(&[]);
+                        match fields.iter().find(|decl| decl.0 == field) {
+                            Some(decl) => constraints.push(Constraint::new(&decl.1, &ty, span)),
+                            None => {
+                                return Err(Diagnostic::error(
+                                    span,
+                                    format!("no field `{}` on type `{}`", field, object),
+                                ).with_label("unknown field"))
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(Diagnostic::error(
+                            span,
+                            format!("type `{}` has no fields", object),
+                        ).with_label(format!("cannot access field `{}`", field)))
+                    }
+                },
             }
         }
-        substitution.extend(inserts);
-        if !flag {
+
+        // retry the field accesses once some of their objects became known
+        if deferred.is_empty() {
             break;
         }
+        let stuck = deferred
+            .iter()
+            .all(|constraint| matches!(constraint, Constraint::Field(Type::T(_), _, _, _)));
+        if stuck {
+            if let Some(Constraint::Field(_, field, _, span)) = deferred.first() {
+                return Err(Diagnostic::error(
+                    *span,
+                    format!("type of this value must be known to access field `{}`", field),
+                ).with_label("cannot infer the type of this value"));
+            }
+        }
+        deferred.reverse();
+        constraints.append(&mut deferred);
     }
 
     Ok(substitution)
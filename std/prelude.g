@@ -1,5 +1,3 @@
-struct Pair =
+pub struct Pair =
     first: int
     second: int
-
-
@@ -15,3 +15,5 @@ fn main(): int =
     puts("Hello, world!")
     return factorial(4)
 
+# stdout: Hello, world!
+# exit: 24
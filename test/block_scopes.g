@@ -0,0 +1,6 @@
+import geo.scoped
+
+fn main(): int =
+    return scoped.twice(4)
+
+# exit: 10
@@ -0,0 +1,23 @@
+# the right side of `and` and `or` only runs when it decides the result
+extern printf: (string, ...) -> int
+
+fn say(b: bool, s: string): bool =
+    printf("%s ", s)
+    return b
+
+fn main(): int =
+    let t = true
+    let f = not t
+    if say(f, "a") and say(true, "b"):
+        printf("no\n")
+    if say(t, "c") or say(false, "d"):
+        printf("yes\n")
+    let n = 0
+    while not (n == 3) and say(t, "e"):
+        n = n + 1
+    printf("%d %d\n", f or not f, t and f)
+    return n
+
+# stdout: a c yes
+# stdout: e e e 1 0
+# exit: 3
@@ -0,0 +1,26 @@
+# lambdas capture the values of locals when they are created
+extern printf: (string, ...) -> int
+
+struct Handler =
+    name: string
+    run: (int) -> int
+
+fn counter(start: int) =
+    return fn(step: int) -> start + step
+
+fn twice(f: (int) -> int): (int) -> int =
+    return fn(x) -> f(f(x))
+
+fn main(): int =
+    let n = 1
+    let add = fn(x: int) -> x + n
+    n = 100
+    let h = Handler { name: "square", run: fn(x) -> x * x }
+    let run = h.run
+    let fs = twice(counter(5))
+    printf("%d %d %s %d\n", add(1), n, h.name, run(7))
+    let quad = twice(twice(add))
+    return fs(0) + quad(0)
+
+# stdout: 2 100 square 49
+# exit: 14
@@ -0,0 +1,47 @@
+extern printf: (string, ...) -> int
+
+struct V =
+    x: int
+    y: int
+
+class Show[T] =
+    fn show(x: T): int
+
+instance Add[V] =
+    fn add(a, b) =
+        return V { x: a.x + b.x, y: a.y + b.y }
+
+instance Eq[V] =
+    fn eq(a: V, b: V): bool =
+        return a.x == b.x and a.y == b.y
+
+instance Show[V] =
+    fn show(v) =
+        return printf("V(%d, %d)\n", v.x, v.y)
+
+instance Show[int] =
+    fn show(n) =
+        return printf("%d\n", n)
+
+fn sum[T: Add](a: T, b: T, c: T): T =
+    return a + b + c
+
+fn twice(x) =
+    return x + x
+
+fn main(): int =
+    let v = V { x: 1, y: 2 }
+    show(sum(v, v, V { x: 10, y: 20 }))
+    show(twice(v))
+    show(sum(1, 2, 3))
+    show(add(4, 5))
+    if v == V { x: 1, y: 2 } and v != twice(v) and eq(3, 3):
+        show(twice(21))
+    return 0
+
+# stdout: V(12, 24)
+# stdout: V(2, 4)
+# stdout: 6
+# stdout: 9
+# stdout: 42
+# exit: 0
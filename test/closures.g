@@ -0,0 +1,28 @@
+extern printf: (string, ...) -> int
+
+fn double(x: int): int =
+    return x * 2
+
+fn apply(f, x) =
+    return f(x)
+
+fn compose(f, g) =
+    return fn(x) -> f(g(x))
+
+fn adder(n: int) =
+    return fn(x) -> x + n
+
+fn main(): int =
+    let add3 = adder(3)
+    let k = 10
+    let both = compose(add3, double)
+    let twice = fn(s: string) -> printf("%s %s\n", s, s)
+    twice("hey")
+    printf("%d %d %d %d\n", apply(double, 4), add3(k), both(5), (fn(y) -> y - k)(50))
+    printf("%s\n", apply(fn(s) -> s, "id"))
+    return apply(adder(k), 1)
+
+# stdout: hey hey
+# stdout: 8 13 13 40
+# stdout: id
+# exit: 11
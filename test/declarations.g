@@ -0,0 +1,17 @@
+extern printf: (string, ...) -> int
+
+# a variable declared without a value can be assigned on every path
+fn sign(n: int): int =
+    let s: int
+    if n > 0:
+        s = 1
+    else:
+        s = 0 - 1
+    return s
+
+fn main(): int =
+    printf("%d\n", sign(3))
+    return sign(0 - 5) + 2
+
+# stdout: 1
+# exit: 1
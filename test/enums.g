@@ -0,0 +1,36 @@
+extern printf: (string, ...) -> int
+
+enum Shape =
+    Circle(int)
+    Rect(int, int)
+    Empty
+
+struct Box =
+    shape: Shape
+    n: int
+
+struct Outer =
+    inner: Box
+    k: int
+
+fn area(s: Shape): int =
+    match s:
+        Circle(r):
+            return 3 * r * r
+        Rect(w, _):
+            return w * 2
+        _:
+            return 0
+
+fn main(): int =
+    let o = Outer { k: 1, inner: Box { shape: Rect(4, 5), n: 2 } }
+    o.inner.n = 7
+    let t = true and o.k == 1 or false
+    if t and not (o.inner.n == 3):
+        printf("%d %d\n", area(o.inner.shape), o.inner.n)
+    printf("%d %d\n", area(Circle(2)), area(Empty))
+    return o.inner.n + o.k
+
+# stdout: 8 7
+# stdout: 12 0
+# exit: 8
@@ -0,0 +1,40 @@
+# instances must be unique and match their class exactly
+class Show[T] =
+    fn show(x: T): int
+
+instance Show[int] =
+    fn show(n) =
+        return n
+
+instance Show[int] =
+    fn show(n) =
+        return 0
+
+instance Show[bool] =
+    fn display(b) =
+        return 1
+
+instance Show[string] =
+    fn show(s) =
+        return 1
+    fn display(s) =
+        return 2
+
+instance Show[()] =
+    fn show(a, b) =
+        return 0
+
+instance Print[int] =
+    fn print(n) =
+        return n
+
+fn main(): int =
+    return show(main)
+
+# error 9: conflicting instances of `Show` for `int`
+# error 13: missing method `show` in instance of `Show`
+# error 20: method `display` is not a member of class `Show`
+# error 24: method `show` takes 2 argument(s) but the class declares 1
+# error 27: cannot find class `Print`
+# error 32: no instance of `Show` for `() -> int`
+# exit: 1
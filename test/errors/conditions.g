@@ -0,0 +1,21 @@
+# conditions have to be `bool`, not a number compared to 0
+fn a(): int =
+    if 1:
+        return 1
+    return 0
+
+fn b(): int =
+    while "s":
+        return 2
+    return 0
+
+fn c(): bool =
+    return not 3
+
+fn main(): int =
+    return 0
+
+# error 3: mismatched types
+# error 8: mismatched types
+# error 13: mismatched types
+# exit: 1
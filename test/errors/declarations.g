@@ -0,0 +1,32 @@
+# an unclosed bracket ends at the next declaration, and a bad field, variant or
+# method doesn't hide the ones after it
+fn g(: int =
+    return 1
+
+struct S =
+    a int
+    b: int
+
+extern e: (int -> int
+
+enum E =
+    A(int int)
+    B
+    C(bool bool)
+
+class C[T] =
+    fn c(x: T) int
+    fn d(x: T): int
+    fn e(x T): int
+
+fn main(): int =
+    return 0
+
+# error 3: expected one of `)`, `,`, identifier, found `:`
+# error 7: expected `:`, found `int`
+# error 10: expected one of `)`, `,`, found `->`
+# error 13: expected one of `)`, `,`, found `int`
+# error 15: expected one of `)`, `,`, found `bool`
+# error 18: expected one of `:`, end of block, newline, found `int`
+# error 20: expected one of `)`, `,`, `:`, found identifier `T`
+# exit: 1
@@ -0,0 +1,10 @@
+# the first mismatch in the source is the one reported, every time
+fn main(): int =
+    let a = 1 + "x"
+    let b = 3
+    b = false
+    let c = true and 4
+    return "s"
+
+# error 3: mismatched types
+# exit: 1
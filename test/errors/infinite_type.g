@@ -0,0 +1,9 @@
+# a function applied to itself would need an infinitely big type
+fn f(x) =
+    return x(x)
+
+fn main(): int =
+    return 0
+
+# error 3: cannot construct the infinite type `'t1 = ('t1) -> 't5`
+# exit: 1
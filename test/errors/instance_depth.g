@@ -0,0 +1,12 @@
+# each call wraps `x` in one more function, so `f` would need a copy for
+# every depth
+fn f[T](n: int, x: T): int =
+    if n == 0:
+        return 0
+    return f(n - 1, fn() -> x)
+
+fn main(): int =
+    return f(3, 1)
+
+# error 6: reached the limit of 32 nested types while instantiating `f`
+# exit: 1
@@ -0,0 +1,8 @@
+# only `fn`s are generic, a local variable has one type for all its uses
+fn main(): int =
+    let id = fn(x) -> x
+    id("a")
+    return id(4)
+
+# error 5: mismatched types
+# exit: 1
@@ -0,0 +1,6 @@
+# the value `main` returns is the exit code, so it can't be a string
+fn main(): string =
+    return "s"
+
+# error 2: `main` must take no arguments and return `int` or `()`
+# exit: 1
@@ -0,0 +1,17 @@
+fn sign(n: int): int =
+    if n > 0:
+        return 1
+    else:
+        if n < 0:
+            return -1
+
+fn loops(n: int): int =
+    while n > 0:
+        return n
+
+fn main(): int =
+    return sign(1) + loops(1)
+
+# error 1: function `sign` does not return a value on every path
+# error 8: function `loops` does not return a value on every path
+# exit: 1
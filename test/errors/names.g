@@ -0,0 +1,19 @@
+fn f(a: int, a: int): int =
+    return b
+
+fn f(): int =
+    y = 3
+    if true:
+        let z = 1
+    return z
+
+fn main(): int =
+    return nope(1)
+
+# error 1: identifier `a` is bound more than once in the same list
+# error 2: cannot find value `b`
+# error 4: name `f` is defined more than once
+# error 5: cannot assign to undeclared variable `y`
+# error 8: cannot find value `z`
+# error 11: cannot find function `nope`
+# exit: 1
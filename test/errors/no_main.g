@@ -0,0 +1,6 @@
+# a program can't be run without a `main`
+fn start(): int =
+    return 0
+
+# error: there is no `main` function
+# exit: 1
@@ -0,0 +1,21 @@
+# each of these is reported, and the unclosed bracket doesn't hide the rest
+fn first(): int =
+    let x = (1 + 2
+    return x
+
+fn second(): int =
+    let y = 3 +
+    return y
+
+fn third(): int =
+    if true
+        return 1
+    return 0
+
+fn main(): int =
+    return first() + second() + third()
+
+# error 4: expected one of `!=`, `%`, `&`, `(`, `)`, `*`, `+`, `-`, `.`, `/`, `<`, `<<`, `<=`, `==`, `>`, `>=`, `>>`, `^`, `and`, `or`, `|`, found `return`
+# error 7: expected one of `(`, `-`, character literal, identifier, integer literal, string literal, `false`, `true`, found newline
+# error 11: expected one of `!=`, `%`, `&`, `(`, `*`, `+`, `-`, `.`, `/`, `:`, `<`, `<<`, `<=`, `==`, `>`, `>=`, `>>`, `^`, `and`, `or`, `|`, found newline
+# exit: 1
@@ -0,0 +1,19 @@
+# values are stored inline, so these would be infinitely big
+enum List =
+    Nil
+    Cons(int, List)
+
+struct A =
+    b: B
+
+struct B =
+    a: A
+    f: () -> A
+
+fn main(): int =
+    return 0
+
+# error 4: recursive type `List` has infinite size
+# error 7: recursive type `A` has infinite size
+# error 10: recursive type `B` has infinite size
+# exit: 1
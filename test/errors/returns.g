@@ -0,0 +1,19 @@
+# every `return` is checked against the declared return type
+fn a(): int =
+    return
+
+fn b(): () =
+    return 1
+
+fn c(n: int): int =
+    if n > 0:
+        return n
+    return true
+
+fn main(): int =
+    return 0
+
+# error 3: mismatched types
+# error 6: mismatched types
+# error 11: mismatched types
+# exit: 1
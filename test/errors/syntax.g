@@ -0,0 +1,17 @@
+fn main(): int =
+    let x = (1 +
+    return x
+
+fn other(): int =
+    return 1 $ 2
+
+fn chars(): int =
+    let a = ''
+    let b = 'x
+    return 'y'
+
+# error 3: expected one of `(`, `-`, character literal, identifier, integer literal, string literal, `false`, `true`, found `return`
+# error 6: unexpected character `$`
+# error 9: empty character literal
+# error 10: unterminated character literal
+# exit: 1
@@ -0,0 +1,26 @@
+extern printf: (string, ...) -> int
+
+struct P =
+    a: int
+
+fn wrong(): int =
+    return "text"
+
+fn field(p: P): int =
+    return p.b
+
+fn needs_add(x: string): string =
+    return x + x
+
+fn variadic_value(): int =
+    let q = printf
+    return 0
+
+fn main(): int =
+    return 0
+
+# error 7: mismatched types
+# error 10: no field `b` on type `P`
+# error 13: no instance of `Add` for `string`
+# error 16: cannot use variadic function `printf` as a value
+# exit: 1
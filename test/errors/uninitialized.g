@@ -0,0 +1,28 @@
+struct P =
+    x: int
+
+fn maybe(n: int): int =
+    let s: int
+    if n > 0:
+        s = 1
+    return s
+
+# assigning a field doesn't initialize the whole struct
+fn field(): int =
+    let p: P
+    p.x = 1
+    return p.x
+
+fn captured(): int =
+    let x: int
+    let f = fn() -> x
+    x = 2
+    return f()
+
+fn main(): int =
+    return maybe(1) + field() + captured()
+
+# error 8: use of possibly uninitialized variable `s`
+# error 14: use of possibly uninitialized variable `p`
+# error 18: use of possibly uninitialized variable `x`
+# exit: 1
@@ -0,0 +1,21 @@
+# externs are declared and called with the signature they are given
+extern putchar: (int) -> int
+extern puts: (string) -> int
+extern printf: (string, ...) -> int
+extern exit: (int) -> ()
+
+fn main(): int =
+    let c = putchar(104)
+    putchar(c + 1)
+    putchar(10)
+    puts("puts")
+    let n = printf("%s %d %d\n", "mixed", 3, true)
+    printf("no arguments\n")
+    exit(n)
+    return 0
+
+# stdout: hi
+# stdout: puts
+# stdout: mixed 3 1
+# stdout: no arguments
+# exit: 10
@@ -0,0 +1,19 @@
+extern printf: (string, ...) -> int
+
+struct P =
+    a: int
+
+fn id[T](x: T): T =
+    return x
+
+fn first[A, B](a: A, b: B): A =
+    let y = id(a)
+    return y
+
+fn main(): int =
+    let p = first(P { a: 3 }, "x")
+    printf(id("%d %s\n"), id(p.a), first("hi", true))
+    return first(id(7), p)
+
+# stdout: 3 hi
+# exit: 7
@@ -0,0 +1,10 @@
+pub fn helper(n: int): int =
+    return n * 2
+
+# a variable only hides `helper` until the end of its block
+pub fn twice(n: int): int =
+    if n > 100:
+        let helper = n
+        return helper
+    let f = fn(helper: int) -> helper + 1
+    return helper(f(n))
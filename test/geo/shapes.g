@@ -0,0 +1,28 @@
+extern puts: (string) -> int
+
+pub struct Point =
+    x: int
+    y: int
+
+pub fn area(p: Point): int =
+    return helper(p.x) * p.y
+
+fn helper(n: int): int =
+    return n
+
+pub fn origin(): Point =
+    return Point { x: 0, y: 0 }
+
+pub enum Shape =
+    Circle(int)
+    Square(int)
+
+enum Secret =
+    Hidden(int)
+
+pub fn size(s: Shape): int =
+    match s:
+        Circle(r):
+            return 3 * r * r
+        Square(w):
+            return w * w
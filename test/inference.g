@@ -0,0 +1,31 @@
+extern printf: (string, ...) -> int
+
+fn id(x) =
+    return x
+
+fn even(n) =
+    if n == 0:
+        return true
+    return odd(n - 1)
+
+fn odd(n) =
+    if n == 0:
+        return false
+    return even(n - 1)
+
+fn fact(n) =
+    if n == 0:
+        return 1
+    return n * fact(n - 1)
+
+fn pick(c, a, b) =
+    if c:
+        return a
+    return b
+
+fn main(): int =
+    printf("%d %d %s\n", id(fact(5)), even(10), pick(odd(3), id("yes"), "no"))
+    return pick(true, 3, 4)
+
+# stdout: 120 1 yes
+# exit: 3
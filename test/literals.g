@@ -0,0 +1,18 @@
+extern printf: (string, ...) -> int
+
+fn main(): int =
+    printf("%d %d %d %d\n", 0x1F, 0o17, 0b101, 1000)
+    printf("tab\tquote\" backslash\\\n")
+    printf("%d %d %d\n", 7 / 2, 7 % 3, -7 / 2)
+    printf("%d %d %d %d\n", 6 & 3, 6 | 3, 6 ^ 3, 1 << 4 >> 2)
+    printf("%5d|%-3d|%03d|%x\n", 42, 7, 5, 255)
+    printf("%d %d %c\n", 'a', '\n', 'z' - 1)
+    return 0
+
+# stdout: 31 15 5 1000
+# stdout: tab	quote" backslash\
+# stdout: 3 1 -3
+# stdout: 2 7 5 4
+# stdout:    42|7  |005|ff
+# stdout: 97 10 y
+# exit: 0
@@ -0,0 +1,40 @@
+# locals and arguments of every type are stored and reassigned
+extern printf: (string, ...) -> int
+
+struct P =
+    a: int
+    s: string
+
+enum E =
+    One(string)
+    Two(bool)
+
+fn swap(s: string, b: bool, p: P, e: E) =
+    s = p.s
+    b = not b
+    p = P { a: p.a + 1, s: "new" }
+    e = Two(b)
+    printf("%s %d %d %s\n", s, b, p.a, p.s)
+    match e:
+        One(t):
+            printf("%s\n", t)
+        Two(t):
+            printf("two %d\n", t)
+
+fn main(): int =
+    let s = "first"
+    let b = false
+    let p = P { a: 1, s: "old" }
+    let e = One("one")
+    let f = fn(x: int) -> x + p.a
+    swap(s, b, p, e)
+    s = "second"
+    b = p.a == 1
+    f = fn(x: int) -> x * 10
+    printf("%s %d %d %s\n", s, b, p.a, p.s)
+    return f(4)
+
+# stdout: old 1 2 new
+# stdout: two 1
+# stdout: second 1 1 old
+# exit: 40
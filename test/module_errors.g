@@ -0,0 +1,19 @@
+import geo.shapes
+
+fn main(): int =
+    let p = shapes.origin()
+    let g = fn(a: shapes.Point) -> a.x
+    return shapes.helper(1) + shapes.nope(2)
+    let z = geo.Point { x: 1 }
+
+fn variants(): int =
+    let s = shapes.Hidden(3)
+    match Circle(3):
+        shapes.Circle(r):
+            return r
+
+# error 6: `helper` is private to module `geo.shapes`
+# error 6: cannot find `nope` in module `geo.shapes`
+# error 7: cannot find module `geo`
+# error 10: `Hidden` is private to module `geo.shapes`
+# exit: 1
@@ -0,0 +1,8 @@
+# the variants of an imported enum are named through their module
+import geo.shapes
+
+fn main(): int =
+    return shapes.size(Circle(1))
+
+# error 5: cannot find function `Circle`
+# exit: 1
@@ -0,0 +1,28 @@
+import geo.shapes
+
+extern puts: (string) -> int
+
+# doesn't clash with the variant of `geo.shapes.Shape`
+enum Mine =
+    Circle(string)
+
+fn main(): int =
+    let p = shapes.Point { x: 3, y: 4 }
+    let q = geo.shapes.origin()
+    let pair = Pair { first: 1, second: 2 }
+    let f = shapes.area
+    puts("modules")
+    let c = shapes.Circle(1)
+    match shapes.Square(2):
+        shapes.Circle(r):
+            return 0
+        shapes.Square(w):
+            c = shapes.Circle(w)
+    match Circle("mine"):
+        Circle(s):
+            puts(s)
+    return shapes.area(p) + f(p) + q.x + pair.second + shapes.size(c)
+
+# stdout: modules
+# stdout: mine
+# exit: 38
@@ -0,0 +1,17 @@
+extern printf: (string, ...) -> int
+
+fn main(): int =
+    let x = 5
+    printf("%d %d %d\n", 1 + 2 * 3, (1 + 2) * 3, 10 - 4 - 3)
+    printf("%d %d %d\n", 2 * 3 % 4, -x + 2, -(x - 8))
+    printf("%d %d %d\n", 1 | 2 ^ 3 & 6, 1 << 2 + 1, 20 - 6 / 4)
+    printf("%d %d %d %d\n", x < 5, x <= 5, x > 4, x >= 6)
+    printf("%d %d\n", 1 + 1 == 2 and x * 2 > 9, x != 5 or -x < 0)
+    return x / 2 * 2 + x % 2
+
+# stdout: 7 9 3
+# stdout: 2 -3 3
+# stdout: 1 8 19
+# stdout: 0 1 1 0
+# stdout: 1 1
+# exit: 5
@@ -0,0 +1,9 @@
+# the program's own `malloc` is the one closures allocate with
+extern malloc: (int) -> string
+
+fn main(): int =
+    let n = 3
+    let add = fn(x: int) -> x + n
+    return add(4)
+
+# exit: 7
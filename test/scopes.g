@@ -0,0 +1,32 @@
+extern printf: (string, ...) -> int
+
+enum Opt =
+    Some(int)
+    None
+
+fn main(): int =
+    let x = 1
+    if true:
+        let x = x + 10
+        printf("%d\n", x)
+    printf("%d\n", x)
+    let f = fn(y) -> x + y
+    let x = 100
+    printf("%d %d\n", f(1), x)
+    let o = Some(5)
+    match o:
+        Some(x):
+            printf("%d\n", x)
+        None:
+            printf("none\n")
+    let n = 0
+    while n < 3:
+        let x = n
+        n = n + 1
+    return x
+
+# stdout: 11
+# stdout: 1
+# stdout: 2 100
+# stdout: 5
+# exit: 100
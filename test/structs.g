@@ -0,0 +1,30 @@
+# structs are values, so a function changing its copy leaves the caller's alone
+extern printf: (string, ...) -> int
+
+struct Point =
+    x: int
+    y: int
+
+struct Label =
+    name: string
+    at: Point
+
+fn origin(): Point =
+    return Point { x: 0, y: 0 }
+
+fn moved(p: Point, dx: int): Point =
+    p.x = p.x + dx
+    return p
+
+fn main(): int =
+    let p = Point { y: 4, x: 3 }
+    let q = moved(p, 10)
+    let l = Label { name: "here", at: origin() }
+    l.at.y = q.y * 2
+    printf("%d %d %d\n", p.x, q.x, origin().y)
+    printf("%s %d %d\n", l.name, l.at.x, l.at.y)
+    return l.at.y + p.x
+
+# stdout: 3 13 0
+# stdout: here 0 8
+# exit: 11
@@ -0,0 +1,16 @@
+extern puts: (string) -> int
+
+# indented with tabs, which are eight columns wide
+fn count(n: int): int =
+	let total = 0
+	while n > 0:
+		total = total + n
+		n = n - 1
+	return total
+
+fn main(): int =
+	puts("tabs")
+	return count(4)
+
+# stdout: tabs
+# exit: 10
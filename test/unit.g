@@ -0,0 +1,26 @@
+# values of type () are passed around without being stored
+extern puts: (string) -> int
+
+fn noret() =
+    puts("noret")
+
+fn id[T](x: T): T =
+    return x
+
+fn apply[T](f: (T) -> T, x: T): T =
+    return f(x)
+
+fn main(): int =
+    id(noret())
+    let u = noret()
+    id(u)
+    apply(fn(x) -> x, u)
+    let g = id
+    g(u)
+    let h = fn() -> u
+    h()
+    return 0
+
+# stdout: noret
+# stdout: noret
+# exit: 0
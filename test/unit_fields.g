@@ -0,0 +1,34 @@
+# fields and payloads of type () take no space in the layout
+extern printf: (string, ...) -> int
+
+struct S =
+    u: ()
+    n: int
+
+struct Nothing =
+    a: ()
+
+enum E =
+    A((), int)
+    B(())
+
+fn noop() =
+    printf("noop\n")
+
+fn main(): int =
+    let s = S { u: noop(), n: 4 }
+    let t = Nothing { a: s.u }
+    let e = A(t.a, s.n)
+    s.u = noop()
+    match e:
+        A(u, n):
+            printf("%d\n", n)
+        B(u):
+            return 1
+    let b = B(s.u)
+    return s.n
+
+# stdout: noop
+# stdout: noop
+# stdout: 4
+# exit: 4
@@ -0,0 +1,9 @@
+# a `main` that returns () exits with 0
+extern puts: (string) -> int
+
+fn main() =
+    puts("done")
+    return
+
+# stdout: done
+# exit: 0
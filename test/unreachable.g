@@ -0,0 +1,29 @@
+extern printf: (string, ...) -> int
+
+fn forever(n: int): int =
+    while true:
+        if n > 3:
+            return n
+        n = n + 1
+    printf("done\n")
+
+fn early(n: int): int =
+    return n
+    printf("never\n")
+    n = 2
+
+fn branches(b: bool): int =
+    if b:
+        return 1
+    else:
+        return 2
+    while b:
+        printf("x")
+
+fn main(): int =
+    return forever(0) + early(1) + branches(true)
+
+# warning 8: unreachable statement
+# warning 12: unreachable statement
+# warning 20: unreachable statement
+# exit: 6
@@ -0,0 +1,21 @@
+# functions without a return type return (), with or without a plain `return`
+extern printf: (string, ...) -> int
+
+fn log(n: int): () =
+    if n < 0:
+        return
+    printf("%d\n", n)
+
+fn nothing() =
+    return
+
+fn main(): int =
+    log(1)
+    log(-1)
+    nothing()
+    log(2)
+    return 0
+
+# stdout: 1
+# stdout: 2
+# exit: 0
@@ -0,0 +1,196 @@
+//! Checks the options of `gala build`, the errors of `gala run --interp` and
+//! the parts of `gala repl` that the golden tests don't use.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+
+fn gala(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gala"))
+        .args(args)
+        .output()
+        .expect("could not run gala")
+}
+
+/// Writes a program to a file of its own, for the tests that need one that
+/// isn't under `test/`.
+fn program(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("gala-cli-{}-{}.g", name, process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn missing_input_is_reported_as_a_diagnostic() {
+    let output = gala(&["build", "test/does_not_exist.g"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.starts_with("error: could not read `test/does_not_exist.g`"),
+        "unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn print_types_writes_to_the_output() {
+    let path = env::temp_dir().join(format!("gala-cli-test-{}.txt", process::id()));
+    let output = gala(&["build", "--print-types", "-o", path.to_str().unwrap(), "test/1.g"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let types = fs::read_to_string(&path).expect("the types were not written");
+    fs::remove_file(&path).unwrap();
+    assert!(types.contains("fn factorial(n: int): int"), "unexpected types: {}", types);
+}
+
+#[test]
+fn print_types_shows_inferred_signatures() {
+    let output = gala(&["build", "--print-types", "test/inference.g"]);
+    assert!(output.status.success());
+    let types = String::from_utf8_lossy(&output.stdout);
+    // mutually recursive functions are inferred together
+    for signature in &[
+        "fn id['a](x: 'a): 'a",
+        "fn even(n: int): bool",
+        "fn odd(n: int): bool",
+        "fn pick['a](c: bool, a: 'a, b: 'a): 'a",
+    ] {
+        assert!(types.lines().any(|line| line == *signature), "unexpected types: {}", types);
+    }
+}
+
+#[test]
+fn interpreter_errors_stop_the_program() {
+    let path = program(
+        "divide",
+        "extern puts: (string) -> int\n\
+         \n\
+         fn div(a: int, b: int): int =\n    return a / b\n\
+         \n\
+         fn main(): int =\n    puts(\"before\")\n    return div(1, 0)\n",
+    );
+    let output = gala(&["run", "--interp", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "before\n");
+    assert!(
+        stderr.starts_with("error: attempt to divide by zero\n") && stderr.contains(":4:12\n"),
+        "unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn interpreter_rejects_unknown_externs() {
+    let path = program(
+        "extern",
+        "extern abs: (int) -> int\n\nfn main(): int =\n    return abs(0 - 3)\n",
+    );
+    let output = gala(&["run", "--interp", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.starts_with("error: extern `abs` is not available in the interpreter"),
+        "unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn builtin_classes_have_no_declaration_to_point_at() {
+    let path = program(
+        "builtin",
+        "instance Add[string] =\n    fn add(a) =\n        return a\n\n\
+         fn main(): int =\n    return 0\n",
+    );
+    let output = gala(&["build", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.contains("= note: `Add` is built into the compiler")
+            && !stderr.contains("declared here"),
+        "unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn uninitialized_reads_point_at_the_variable() {
+    let path = program(
+        "uninit",
+        "fn main(): int =\n    let s: int\n    if 1 + s > 0:\n        return 1\n    return 0\n",
+    );
+    let output = gala(&["build", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.starts_with("error: use of possibly uninitialized variable `s`")
+            && stderr.contains(":3:12\n"),
+        "unexpected stderr: {}",
+        stderr
+    );
+}
+
+/// Runs the REPL on `input`, as if it was typed in.
+fn repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gala"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not run gala");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn repl_keeps_declarations_and_variables() {
+    let output = repl(
+        "fn sq(x: int): int =\n    return x * x\n\n\
+         let a = sq(3)\na + 1\n:t fn(s) -> s\nlet b = true\nnot b\n:q\n",
+    );
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let printed = stdout
+        .split(&['\n', ' '][..])
+        .filter(|part| !part.is_empty() && *part != ">>" && *part != "..")
+        .collect::<Vec<_>>()
+        .join(" ");
+    assert_eq!(printed, "10: int ('a) -> 'a false: bool");
+}
+
+#[test]
+fn repl_errors_point_at_the_input() {
+    let output = repl("let x = 1\nx + true\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(" --> <repl:3>:1:5\n") && stderr.contains("\n1 | x + true\n"),
+        "unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn repl_keeps_imports() {
+    let output = repl("import test.geo.shapes\nshapes.area(shapes.origin())\n");
+    assert!(output.status.success());
+    assert!(
+        output.stderr.is_empty(),
+        "unexpected stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("0: int"));
+}
@@ -0,0 +1,253 @@
+//! Runs every program under `test/` and compares what it does with the
+//! directives in the comments at the end of the file:
+//!
+//! ```text
+//! # stdout: a line the program prints
+//! # exit: 24
+//! # error 5: mismatched types
+//! # warning 11: unreachable statement
+//! ```
+//!
+//! Files without directives are modules imported by the others, and are only
+//! run through them. Each program is run by the interpreter and, if LLVM is
+//! installed, compiled to native code too. Set `GALA_BLESS=1` to rewrite the
+//! directives to match what the programs do now.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TEST_DIR: &str = "test";
+
+/// What running a program did, or is expected to do.
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    stdout: Vec<String>,
+    exit: i32,
+    /// The diagnostics as `error 5: message`, sorted by line so that the order
+    /// functions are checked in doesn't matter.
+    diagnostics: Vec<String>,
+}
+
+impl Outcome {
+    /// Reads the directives in a test file, or `None` if it has none.
+    fn expected(source: &str) -> Option<Outcome> {
+        let mut outcome = Outcome::default();
+        let mut found = false;
+        for line in source.lines() {
+            match directive(line) {
+                Some(("stdout", text)) => outcome.stdout.push(text.to_owned()),
+                Some(("exit", code)) => {
+                    outcome.exit = code.trim().parse().expect("exit code is not a number")
+                }
+                Some((_, _)) => outcome.diagnostics.push(line[2..].to_owned()),
+                None => continue,
+            }
+            found = true;
+        }
+        outcome.diagnostics.sort_by_key(|diagnostic| diagnostic_line(diagnostic));
+        if found {
+            Some(outcome)
+        } else {
+            None
+        }
+    }
+
+    fn actual(stdout: &[u8], stderr: &[u8], exit: i32) -> Outcome {
+        let stdout = String::from_utf8_lossy(stdout);
+        let stderr = String::from_utf8_lossy(stderr);
+        let mut diagnostics = Vec::new();
+        let mut lines = stderr.lines().peekable();
+        while let Some(line) = lines.next() {
+            let (severity, message) = match line.find(": ") {
+                Some(i) if line.starts_with("error") || line.starts_with("warning") => {
+                    (&line[..i], &line[i + 2..])
+                }
+                _ => continue,
+            };
+            // the location follows the message, as ` --> file:line:column`
+            let location = lines
+                .peek()
+                .and_then(|next| next.trim_start().strip_prefix("--> "))
+                .and_then(|location| location.rsplit(':').nth(1));
+            diagnostics.push(match location {
+                Some(line) => format!("{} {}: {}", severity, line, message),
+                None => format!("{}: {}", severity, message),
+            });
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic_line(diagnostic));
+        Outcome {
+            stdout: stdout.lines().map(str::to_owned).collect(),
+            exit,
+            diagnostics,
+        }
+    }
+
+    fn directives(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for line in self.stdout.iter() {
+            if line.is_empty() {
+                lines.push("# stdout:".to_owned());
+            } else {
+                lines.push(format!("# stdout: {}", line));
+            }
+        }
+        for diagnostic in self.diagnostics.iter() {
+            lines.push(format!("# {}", diagnostic));
+        }
+        lines.push(format!("# exit: {}", self.exit));
+        lines
+    }
+}
+
+/// Splits a directive comment into its kind and the rest of the line.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.strip_prefix("# ")?;
+    let colon = line.find(':')?;
+    let kind = line[..colon].split(' ').next()?;
+    let rest = &line[colon + 1..];
+    match kind {
+        "stdout" | "exit" | "error" | "warning" => {
+            Some((kind, rest.strip_prefix(' ').unwrap_or(rest)))
+        }
+        _ => None,
+    }
+}
+
+fn diagnostic_line(diagnostic: &str) -> usize {
+    diagnostic
+        .split(':')
+        .next()
+        .and_then(|kind| kind.split(' ').nth(1))
+        .and_then(|line| line.parse().ok())
+        .unwrap_or(0)
+}
+
+fn find_tests(dir: &Path, tests: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).expect("could not read the test directory");
+    for entry in entries {
+        let path = entry.expect("could not read the test directory").path();
+        if path.is_dir() {
+            find_tests(&path, tests);
+        } else if path.extension().is_some_and(|extension| extension == "g") {
+            tests.push(path);
+        }
+    }
+}
+
+fn run(path: &Path, interp: bool) -> Outcome {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gala"));
+    command.arg("run");
+    if interp {
+        command.arg("--interp");
+    }
+    let output = command.arg(path).output().expect("could not run gala");
+    Outcome::actual(&output.stdout, &output.stderr, output.status.code().unwrap_or(-1))
+}
+
+/// A line diff of what was expected and what happened.
+fn diff(expected: &[String], actual: &[String]) -> String {
+    // the longest common subsequence of every pair of suffixes
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            out += &format!("   {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out += &format!("  -{}\n", expected[i]);
+            i += 1;
+        } else {
+            out += &format!("  +{}\n", actual[j]);
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Replaces the directives at the end of a test file with `outcome`'s.
+fn bless(path: &Path, source: &str, outcome: &Outcome) {
+    let mut lines = source
+        .lines()
+        .filter(|line| directive(line).is_none())
+        .collect::<Vec<_>>();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let mut contents = lines.join("\n");
+    contents += "\n\n";
+    for line in outcome.directives() {
+        contents += &line;
+        contents += "\n";
+    }
+    fs::write(path, contents).expect("could not write the test file");
+}
+
+#[test]
+fn golden() {
+    let blessing = env::var_os("GALA_BLESS").is_some();
+    let native = Command::new("llc").arg("--version").output().is_ok();
+    if !native {
+        eprintln!("`llc` was not found, so the tests are only run by the interpreter");
+    }
+
+    let mut tests = Vec::new();
+    find_tests(Path::new(TEST_DIR), &mut tests);
+    tests.sort();
+    let mut failures = Vec::new();
+    let mut count = 0;
+    for path in tests.iter() {
+        let source = fs::read_to_string(path).expect("could not read the test file");
+        let expected = match Outcome::expected(&source) {
+            Some(expected) => expected,
+            None => continue,
+        };
+        count += 1;
+        let interpreted = run(path, true);
+        if blessing {
+            if interpreted != expected {
+                bless(path, &source, &interpreted);
+                eprintln!("blessed {}", path.display());
+            }
+            continue;
+        }
+
+        let mut runs = vec![("interpreted", interpreted)];
+        if native {
+            runs.push(("compiled", run(path, false)));
+        }
+        for (mode, actual) in runs {
+            if actual != expected {
+                let report = format!(
+                    "{} ({}) does not match its directives:\n{}",
+                    path.display(),
+                    mode,
+                    diff(&expected.directives(), &actual.directives())
+                );
+                failures.push(report);
+            }
+        }
+    }
+
+    assert!(count > 0, "found no tests under `{}`", TEST_DIR);
+    if !failures.is_empty() {
+        panic!(
+            "{} of {} golden test runs failed, run with GALA_BLESS=1 to update them\n\n{}",
+            failures.len(),
+            count * if native { 2 } else { 1 },
+            failures.join("\n")
+        );
+    }
+}